@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use krata::{
+    events::EventStream,
+    v1::{
+        common::{Guest, GuestSpec, GuestStatus},
+        control::{
+            control_service_client::ControlServiceClient, CreateGuestGroupRequest,
+            CreateGuestRequest, DestroyGuestGroupRequest, DestroyGuestRequest, ListGuestsRequest,
+            ResolveGuestRequest, UpdateGuestNetworkPolicyRequest, UpdateGuestResourcesRequest,
+        },
+    },
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::{
+    cli::destroy::{wait_guest_destroyed, wait_guests_destroyed},
+    format::proto2kv,
+    manifest::load_guest_manifests,
+};
+
+// matches the defaults of launch
+const DEFAULT_GUEST_VCPUS: u32 = 1;
+const DEFAULT_GUEST_MEM: u64 = 512;
+
+enum GroupChange {
+    Create,
+    Update(Vec<(Guest, GuestSpec, GuestChange)>),
+    Recreate,
+}
+
+enum GuestChange {
+    Create,
+    Unchanged,
+    Update { resources: bool, policy: bool },
+    Recreate,
+}
+
+#[derive(Parser)]
+#[command(about = "Create or update guests to match manifest files")]
+pub struct ApplyCommand {
+    #[arg(
+        short = 'f',
+        long = "file",
+        required = true,
+        help = "Guest manifest in YAML or TOML format, use - to read YAML from stdin"
+    )]
+    files: Vec<String>,
+}
+
+impl ApplyCommand {
+    pub async fn run(
+        self,
+        mut client: ControlServiceClient<Channel>,
+        events: EventStream,
+    ) -> Result<()> {
+        let mut specs = Vec::new();
+        let mut groups: Vec<(String, Vec<GuestSpec>)> = Vec::new();
+        for file in &self.files {
+            for spec in load_guest_manifests(file).await? {
+                let spec = normalize_spec(spec);
+                if spec.group.is_empty() {
+                    specs.push(spec);
+                } else if let Some((_, members)) =
+                    groups.iter_mut().find(|(name, _)| *name == spec.group)
+                {
+                    members.push(spec);
+                } else {
+                    groups.push((spec.group.clone(), vec![spec]));
+                }
+            }
+        }
+
+        // groups are planned before any guest is touched, so a group that cannot be
+        // applied is rejected before anything is destroyed
+        let guests = client
+            .list_guests(Request::new(ListGuestsRequest {}))
+            .await?
+            .into_inner()
+            .guests;
+        let mut plans = Vec::new();
+        for (name, members) in groups {
+            let change = group_change(&guests, &name, &members)?;
+            plans.push((name, members, change));
+        }
+
+        for spec in specs {
+            let existing = client
+                .resolve_guest(Request::new(ResolveGuestRequest {
+                    name: spec.name.clone(),
+                }))
+                .await?
+                .into_inner()
+                .guest;
+
+            let change = match existing {
+                Some(ref guest) => guest_change(guest, &spec),
+                None => GuestChange::Create,
+            };
+            if matches!(change, GuestChange::Update { .. } | GuestChange::Recreate) {
+                if let Some(current) = existing.as_ref().and_then(current_spec) {
+                    print_spec_changes(&current, &spec)?;
+                }
+            }
+
+            match change {
+                GuestChange::Unchanged => {
+                    println!("guest {} unchanged", spec.name);
+                }
+
+                GuestChange::Create => {
+                    let name = spec.name.clone();
+                    create_guest(&mut client, spec).await?;
+                    println!("guest {} created", name);
+                }
+
+                GuestChange::Update { resources, policy } => {
+                    let Some(guest) = existing else {
+                        continue;
+                    };
+                    update_guest(&mut client, &guest, &spec, resources, policy).await?;
+                    println!("guest {} updated", spec.name);
+                }
+
+                GuestChange::Recreate => {
+                    let Some(guest) = existing else {
+                        continue;
+                    };
+                    client
+                        .destroy_guest(Request::new(DestroyGuestRequest {
+                            guest_id: guest.id.clone(),
+                            timeout_seconds: 0,
+                            force: false,
+                        }))
+                        .await?;
+                    wait_guest_destroyed(&guest.id, events.clone()).await?;
+                    let name = spec.name.clone();
+                    create_guest(&mut client, spec).await?;
+                    println!("guest {} recreated", name);
+                }
+            }
+        }
+
+        for (name, members, change) in plans {
+            match change {
+                GroupChange::Create => {
+                    create_group(&mut client, &name, members).await?;
+                    println!("group {} created", name);
+                }
+
+                GroupChange::Update(changes) => {
+                    for (guest, spec, change) in changes {
+                        match change {
+                            GuestChange::Update { resources, policy } => {
+                                update_guest(&mut client, &guest, &spec, resources, policy).await?;
+                                println!("guest {} updated", spec.name);
+                            }
+                            _ => println!("guest {} unchanged", spec.name),
+                        }
+                    }
+                }
+
+                GroupChange::Recreate => {
+                    let stream = events.subscribe();
+                    let destroyed = client
+                        .destroy_guest_group(Request::new(DestroyGuestGroupRequest {
+                            name: name.clone(),
+                            timeout_seconds: 0,
+                            force: false,
+                        }))
+                        .await?
+                        .into_inner()
+                        .guest_ids;
+                    wait_guests_destroyed(&destroyed, stream).await?;
+                    create_group(&mut client, &name, members).await?;
+                    println!("group {} recreated", name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn update_guest(
+    client: &mut ControlServiceClient<Channel>,
+    guest: &Guest,
+    spec: &GuestSpec,
+    resources: bool,
+    policy: bool,
+) -> Result<()> {
+    if resources {
+        client
+            .update_guest_resources(Request::new(UpdateGuestResourcesRequest {
+                guest_id: guest.id.clone(),
+                vcpus: spec.vcpus,
+                mem: spec.mem,
+            }))
+            .await?;
+    }
+    if policy {
+        client
+            .update_guest_network_policy(Request::new(UpdateGuestNetworkPolicyRequest {
+                guest_id: guest.id.clone(),
+                policy: spec.network_policy.clone(),
+            }))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn create_group(
+    client: &mut ControlServiceClient<Channel>,
+    name: &str,
+    guests: Vec<GuestSpec>,
+) -> Result<()> {
+    client
+        .create_guest_group(Request::new(CreateGuestGroupRequest {
+            name: name.to_string(),
+            guests,
+            registry_auth: None,
+        }))
+        .await?;
+    Ok(())
+}
+
+/// Plans a group as a whole, guests cannot join or leave an existing group so
+/// anything beyond an in place update recreates every member.
+fn group_change(guests: &[Guest], name: &str, desired: &[GuestSpec]) -> Result<GroupChange> {
+    let live = |guest: &&Guest| {
+        guest
+            .state
+            .as_ref()
+            .map(|x| x.status() != GuestStatus::Destroyed)
+            .unwrap_or(true)
+    };
+    let members = guests
+        .iter()
+        .filter(live)
+        .filter(|guest| {
+            guest
+                .spec
+                .as_ref()
+                .map(|x| x.group == name)
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+
+    for (index, spec) in desired.iter().enumerate() {
+        if spec.name.is_empty() {
+            return Err(anyhow!("guests in group {} must have a name", name));
+        }
+        if desired[..index].iter().any(|x| x.name == spec.name) {
+            return Err(anyhow!(
+                "guest {} is listed more than once in group {}",
+                spec.name,
+                name
+            ));
+        }
+        let outside = guests.iter().filter(live).any(|guest| {
+            guest
+                .spec
+                .as_ref()
+                .map(|x| x.name == spec.name && x.group != name)
+                .unwrap_or(false)
+        });
+        if outside {
+            return Err(anyhow!(
+                "guest {} already exists outside of group {}",
+                spec.name,
+                name
+            ));
+        }
+    }
+
+    if members.is_empty() {
+        return Ok(GroupChange::Create);
+    }
+
+    let mut changes = Vec::new();
+    let mut recreate = members.len() != desired.len();
+    for spec in desired {
+        let member = members.iter().find(|guest| {
+            guest
+                .spec
+                .as_ref()
+                .map(|x| x.name == spec.name)
+                .unwrap_or(false)
+        });
+        let Some(member) = member else {
+            println!("group {} adds guest {}", name, spec.name);
+            recreate = true;
+            continue;
+        };
+        let change = guest_change(member, spec);
+        if matches!(change, GuestChange::Update { .. } | GuestChange::Recreate) {
+            if let Some(current) = current_spec(member) {
+                print_spec_changes(&current, spec)?;
+            }
+        }
+        if matches!(change, GuestChange::Recreate | GuestChange::Create) {
+            recreate = true;
+        }
+        changes.push(((*member).clone(), spec.clone(), change));
+    }
+    for member in &members {
+        let Some(spec) = member.spec.as_ref() else {
+            continue;
+        };
+        if !desired.iter().any(|x| x.name == spec.name) {
+            println!("group {} removes guest {}", name, spec.name);
+        }
+    }
+
+    Ok(if recreate {
+        GroupChange::Recreate
+    } else {
+        GroupChange::Update(changes)
+    })
+}
+
+fn print_spec_changes(current: &GuestSpec, desired: &GuestSpec) -> Result<()> {
+    for line in spec_changes(current, desired)? {
+        println!("guest {} {}", desired.name, line);
+    }
+    Ok(())
+}
+
+async fn create_guest(client: &mut ControlServiceClient<Channel>, spec: GuestSpec) -> Result<()> {
+    client
+        .create_guest(Request::new(CreateGuestRequest {
+            spec: Some(spec),
+            registry_auth: None,
+        }))
+        .await?;
+    Ok(())
+}
+
+fn normalize_spec(mut spec: GuestSpec) -> GuestSpec {
+    if spec.vcpus == 0 {
+        spec.vcpus = DEFAULT_GUEST_VCPUS;
+    }
+    if spec.mem == 0 {
+        spec.mem = DEFAULT_GUEST_MEM;
+    }
+    spec
+}
+
+/// The spec of a guest as it is running, resources may have been changed at runtime.
+fn current_spec(guest: &Guest) -> Option<GuestSpec> {
+    let mut current = normalize_spec(guest.spec.clone()?);
+    if let Some(resources) = guest.state.as_ref().and_then(|x| x.resources.as_ref()) {
+        current.vcpus = resources.vcpus;
+        current.mem = resources.mem;
+    }
+    Some(current)
+}
+
+/// Describes every field that differs between two specs, one line per field.
+fn spec_changes(current: &GuestSpec, desired: &GuestSpec) -> Result<Vec<String>> {
+    let current = proto2kv(current.clone())?;
+    let desired = proto2kv(desired.clone())?;
+    let keys = current
+        .keys()
+        .chain(desired.keys())
+        .collect::<BTreeSet<_>>();
+    Ok(keys
+        .into_iter()
+        .filter(|key| current.get(*key) != desired.get(*key))
+        .map(|key| {
+            format!(
+                "{}: {} -> {}",
+                key,
+                current.get(key).map(|x| x.as_str()).unwrap_or("<unset>"),
+                desired.get(key).map(|x| x.as_str()).unwrap_or("<unset>")
+            )
+        })
+        .collect())
+}
+
+fn guest_change(guest: &Guest, desired: &GuestSpec) -> GuestChange {
+    let (Some(spec), Some(mut current)) =
+        (guest.spec.clone().map(normalize_spec), current_spec(guest))
+    else {
+        return GuestChange::Recreate;
+    };
+    let state = guest.state.as_ref();
+    if current == *desired {
+        return GuestChange::Unchanged;
+    }
+
+    // resources and network policy can be changed in place, anything else needs a new guest
+    let resources = current.vcpus != desired.vcpus || current.mem != desired.mem;
+    let policy = current.network_policy != desired.network_policy;
+    current.vcpus = desired.vcpus;
+    current.mem = desired.mem;
+    current.network_policy = desired.network_policy.clone();
+    if current != *desired {
+        return GuestChange::Recreate;
+    }
+
+    let running = state
+        .map(|x| matches!(x.status(), GuestStatus::Started | GuestStatus::Paused))
+        .unwrap_or(false);
+    let within_bounds = desired.vcpus <= spec.max_vcpus.max(spec.vcpus)
+        && desired.mem <= spec.max_mem.max(spec.mem);
+    if resources && !(running && within_bounds) {
+        return GuestChange::Recreate;
+    }
+    GuestChange::Update { resources, policy }
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use clap::Parser;
+use krata::{
+    events::EventStream,
+    v1::control::{
+        control_service_client::ControlServiceClient, DestroyGuestRequest, ResolveGuestRequest,
+    },
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::{cli::destroy::wait_guest_destroyed, manifest::load_guest_manifests};
+
+#[derive(Parser)]
+#[command(about = "Destroy the guests described by manifest files")]
+pub struct DeleteCommand {
+    #[arg(
+        short = 'f',
+        long = "file",
+        required = true,
+        help = "Guest manifest in YAML or TOML format, use - to read YAML from stdin"
+    )]
+    files: Vec<String>,
+    #[arg(
+        short = 'W',
+        long,
+        help = "Wait for the destruction of the guests to complete"
+    )]
+    wait: bool,
+    #[arg(
+        long,
+        help = "Destroy the guests immediately instead of asking their tasks to stop first"
+    )]
+    force: bool,
+}
+
+impl DeleteCommand {
+    pub async fn run(
+        self,
+        mut client: ControlServiceClient<Channel>,
+        events: EventStream,
+    ) -> Result<()> {
+        let mut specs = Vec::new();
+        for file in &self.files {
+            specs.extend(load_guest_manifests(file).await?);
+        }
+
+        for spec in specs {
+            let Some(guest) = client
+                .resolve_guest(Request::new(ResolveGuestRequest {
+                    name: spec.name.clone(),
+                }))
+                .await?
+                .into_inner()
+                .guest
+            else {
+                println!("guest {} not found", spec.name);
+                continue;
+            };
+
+            client
+                .destroy_guest(Request::new(DestroyGuestRequest {
+                    guest_id: guest.id.clone(),
+                    timeout_seconds: 0,
+                    force: self.force,
+                }))
+                .await?;
+            if self.wait {
+                wait_guest_destroyed(&guest.id, events.clone()).await?;
+            }
+            println!("guest {} deleted", spec.name);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use krata::{
     events::EventStream,
@@ -12,6 +12,7 @@ use krata::{
 };
 
 use log::error;
+use tokio::sync::broadcast::Receiver;
 use tonic::{transport::Channel, Request};
 
 use crate::cli::resolve_guest;
@@ -25,6 +26,12 @@ pub struct DestroyCommand {
         help = "Wait for the destruction of the guest to complete"
     )]
     wait: bool,
+    #[arg(
+        short,
+        long,
+        help = "Destroy the guest immediately instead of asking its task to stop first"
+    )]
+    force: bool,
     #[arg(help = "Guest to destroy, either the name or the uuid")]
     guest: String,
 }
@@ -39,6 +46,8 @@ impl DestroyCommand {
         let _ = client
             .destroy_guest(Request::new(DestroyGuestRequest {
                 guest_id: guest_id.clone(),
+                timeout_seconds: 0,
+                force: self.force,
             }))
             .await?
             .into_inner();
@@ -49,16 +58,25 @@ impl DestroyCommand {
     }
 }
 
-async fn wait_guest_destroyed(id: &str, events: EventStream) -> Result<()> {
-    let mut stream = events.subscribe();
-    while let Ok(event) = stream.recv().await {
+pub async fn wait_guest_destroyed(id: &str, events: EventStream) -> Result<()> {
+    wait_guests_destroyed(&[id.to_string()], events.subscribe()).await
+}
+
+/// Waits until every guest in `ids` is destroyed. Subscribe before requesting the
+/// destruction so that no event is missed.
+pub async fn wait_guests_destroyed(ids: &[String], mut stream: Receiver<Event>) -> Result<()> {
+    let mut remaining = ids.to_vec();
+    while !remaining.is_empty() {
+        let Ok(event) = stream.recv().await else {
+            break;
+        };
         match event {
             Event::GuestChanged(changed) => {
                 let Some(guest) = changed.guest else {
                     continue;
                 };
 
-                if guest.id != id {
+                if !remaining.contains(&guest.id) {
                     continue;
                 }
 
@@ -68,15 +86,14 @@ async fn wait_guest_destroyed(id: &str, events: EventStream) -> Result<()> {
 
                 if let Some(ref error) = state.error_info {
                     if state.status() == GuestStatus::Failed {
-                        error!("destroy failed: {}", error.message);
-                        std::process::exit(1);
+                        return Err(anyhow!("destroy failed: {}", error.message));
                     } else {
                         error!("guest error: {}", error.message);
                     }
                 }
 
                 if state.status() == GuestStatus::Destroyed {
-                    std::process::exit(0);
+                    remaining.retain(|x| *x != guest.id);
                 }
             }
         }
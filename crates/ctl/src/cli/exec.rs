@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use async_stream::stream;
+use clap::Parser;
+use crossterm::{terminal::enable_raw_mode, tty::IsTty};
+use krata::v1::{
+    common::{GuestTaskSpec, GuestTaskSpecEnvVar},
+    control::{control_service_client::ControlServiceClient, ExecGuestRequest},
+};
+use log::debug;
+use tokio::io::{stderr, stdin, stdout, AsyncReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use tonic::{transport::Channel, Request};
+
+use crate::console::StdioConsoleStream;
+
+use super::resolve_guest;
+
+#[derive(Parser)]
+#[command(about = "Execute a command inside the guest")]
+pub struct ExecCommand {
+    #[arg[short, long, help = "Environment variables"]]
+    env: Option<Vec<String>>,
+    #[arg(short = 'w', long, help = "Working directory")]
+    working_directory: Option<String>,
+    #[arg(short, long, help = "Allocate a TTY for the command")]
+    tty: bool,
+    #[arg(help = "Guest to exec inside, either the name or the uuid")]
+    guest: String,
+    #[arg(
+        allow_hyphen_values = true,
+        trailing_var_arg = true,
+        help = "Command to run inside the guest"
+    )]
+    command: Vec<String>,
+}
+
+impl ExecCommand {
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        if self.command.is_empty() {
+            return Err(anyhow!("no command was specified"));
+        }
+
+        let initial = ExecGuestRequest {
+            guest_id,
+            task: Some(GuestTaskSpec {
+                environment: env_map(&self.env.unwrap_or_default())
+                    .iter()
+                    .map(|(key, value)| GuestTaskSpecEnvVar {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+                command: self.command,
+                tty: self.tty,
+                resources: None,
+            }),
+            working_directory: self.working_directory.unwrap_or_default(),
+            tty: self.tty,
+            data: vec![],
+            stdin_closed: false,
+        };
+
+        let input = stream! {
+            yield initial;
+
+            let mut stdin = stdin();
+            let mut buffer = vec![0u8; 8192];
+            loop {
+                let size = match stdin.read(&mut buffer).await {
+                    Ok(size) => size,
+                    Err(error) => {
+                        debug!("failed to read stdin: {}", error);
+                        break;
+                    }
+                };
+                if size == 0 {
+                    break;
+                }
+                yield ExecGuestRequest {
+                    data: buffer[0..size].to_vec(),
+                    ..Default::default()
+                };
+            }
+            yield ExecGuestRequest {
+                stdin_closed: true,
+                ..Default::default()
+            };
+        };
+
+        let mut output = client.exec_guest(Request::new(input)).await?.into_inner();
+
+        if self.tty && stdin().is_tty() {
+            enable_raw_mode()?;
+        }
+
+        let mut stdout = stdout();
+        let mut stderr = stderr();
+        let mut code = 1;
+        while let Some(reply) = output.next().await {
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(error) => {
+                    StdioConsoleStream::restore_terminal_mode();
+                    return Err(error.into());
+                }
+            };
+
+            if !reply.stdout.is_empty() {
+                stdout.write_all(&reply.stdout).await?;
+                stdout.flush().await?;
+            }
+
+            if !reply.stderr.is_empty() {
+                stderr.write_all(&reply.stderr).await?;
+                stderr.flush().await?;
+            }
+
+            if reply.exited {
+                if !reply.error.is_empty() {
+                    StdioConsoleStream::restore_terminal_mode();
+                    return Err(anyhow!("exec failed: {}", reply.error));
+                }
+                code = reply.exit_code;
+                break;
+            }
+        }
+        StdioConsoleStream::restore_terminal_mode();
+        std::process::exit(code);
+    }
+}
+
+fn env_map(env: &[String]) -> HashMap<String, String> {
+    let mut map = HashMap::<String, String>::new();
+    for item in env {
+        if let Some((key, value)) = item.split_once('=') {
+            map.insert(key.to_string(), value.to_string());
+        }
+    }
+    map
+}
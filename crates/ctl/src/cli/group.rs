@@ -0,0 +1,105 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use krata::v1::control::{
+    control_service_client::ControlServiceClient, CreateGuestGroupRequest, DestroyGuestGroupRequest,
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::{cli::RegistryAuthArgs, manifest::load_guest_manifests};
+
+#[derive(Parser)]
+#[command(about = "Manage groups of guests sharing a private network")]
+pub struct GroupCommand {
+    #[command(subcommand)]
+    command: GroupCommands,
+}
+
+#[derive(Subcommand)]
+enum GroupCommands {
+    Create(GroupCreateCommand),
+    Destroy(GroupDestroyCommand),
+}
+
+impl GroupCommand {
+    pub async fn run(self, client: ControlServiceClient<Channel>) -> Result<()> {
+        match self.command {
+            GroupCommands::Create(create) => create.run(client).await,
+            GroupCommands::Destroy(destroy) => destroy.run(client).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Create a group of guests from manifest files")]
+struct GroupCreateCommand {
+    #[arg(
+        short = 'f',
+        long = "file",
+        required = true,
+        help = "Guest manifest in YAML or TOML format, use - to read YAML from stdin"
+    )]
+    files: Vec<String>,
+    #[command(flatten)]
+    registry: RegistryAuthArgs,
+    #[arg(help = "Name of the group")]
+    name: String,
+}
+
+impl GroupCreateCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let mut guests = Vec::new();
+        for file in &self.files {
+            guests.extend(load_guest_manifests(file).await?);
+        }
+        let reply = client
+            .create_guest_group(Request::new(CreateGuestGroupRequest {
+                name: self.name,
+                guests,
+                registry_auth: self.registry.registry_auth(),
+            }))
+            .await?
+            .into_inner();
+        for guest_id in reply.guest_ids {
+            println!("{}", guest_id);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Destroy all guests in a group")]
+struct GroupDestroyCommand {
+    #[arg(
+        short,
+        long,
+        default_value_t = 10,
+        help = "Seconds to wait for each guest task to exit before destroying the guest"
+    )]
+    timeout: u64,
+    #[arg(
+        short,
+        long,
+        help = "Destroy the guests immediately instead of asking their tasks to stop first"
+    )]
+    force: bool,
+    #[arg(help = "Name of the group")]
+    name: String,
+}
+
+impl GroupDestroyCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let reply = client
+            .destroy_guest_group(Request::new(DestroyGuestGroupRequest {
+                name: self.name,
+                timeout_seconds: self.timeout,
+                force: self.force,
+            }))
+            .await?
+            .into_inner();
+        for guest_id in reply.guest_ids {
+            println!("{}", guest_id);
+        }
+        Ok(())
+    }
+}
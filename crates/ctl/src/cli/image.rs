@@ -0,0 +1,364 @@
+use std::{
+    cmp::Reverse,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use async_stream::stream;
+use clap::{Parser, Subcommand, ValueEnum};
+use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, Table};
+use fancy_duration::FancyDuration;
+use human_bytes::human_bytes;
+use krata::v1::{
+    common::CachedImage,
+    control::{
+        control_service_client::ControlServiceClient, ImagePullLayerPhase, ImagePullPhase,
+        ImportImageRequest, ListImagesRequest, PruneImagesRequest, PullImageRequest,
+        RemoveImageRequest,
+    },
+};
+
+use log::debug;
+use serde_json::Value;
+use tokio::{fs::File, io::AsyncReadExt};
+use tokio_stream::StreamExt;
+use tonic::{transport::Channel, Request};
+
+use crate::format::{image_digest_short, kv2line, proto2dynamic, proto2kv};
+
+use super::RegistryAuthArgs;
+
+#[derive(Parser)]
+#[command(about = "Manage the images cached on the hypervisor")]
+pub struct ImageCommand {
+    #[command(subcommand)]
+    command: ImageCommands,
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    #[command(alias = "ls")]
+    List(ImageListCommand),
+    Pull(ImagePullCommand),
+    #[command(alias = "rm")]
+    Remove(ImageRemoveCommand),
+    Prune(ImagePruneCommand),
+    Import(ImageImportCommand),
+}
+
+impl ImageCommand {
+    pub async fn run(self, client: ControlServiceClient<Channel>) -> Result<()> {
+        match self.command {
+            ImageCommands::List(list) => list.run(client).await,
+            ImageCommands::Pull(pull) => pull.run(client).await,
+            ImageCommands::Remove(remove) => remove.run(client).await,
+            ImageCommands::Prune(prune) => prune.run(client).await,
+            ImageCommands::Import(import) => import.run(client).await,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
+enum ImageListFormat {
+    Table,
+    Json,
+    JsonPretty,
+    Jsonl,
+    Yaml,
+    KeyValue,
+}
+
+#[derive(Parser)]
+#[command(about = "List the cached images")]
+struct ImageListCommand {
+    #[arg(short, long, default_value = "table", help = "Output format")]
+    format: ImageListFormat,
+}
+
+impl ImageListCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let mut images = client
+            .list_images(Request::new(ListImagesRequest {}))
+            .await?
+            .into_inner()
+            .images;
+
+        images.sort_by_key(|image| Reverse(image.last_used));
+
+        match self.format {
+            ImageListFormat::Table => {
+                self.print_image_table(images)?;
+            }
+
+            ImageListFormat::Json | ImageListFormat::JsonPretty | ImageListFormat::Yaml => {
+                let mut values = Vec::new();
+                for image in images {
+                    let message = proto2dynamic(image)?;
+                    values.push(serde_json::to_value(message)?);
+                }
+                let value = Value::Array(values);
+                let encoded = if self.format == ImageListFormat::JsonPretty {
+                    serde_json::to_string_pretty(&value)?
+                } else if self.format == ImageListFormat::Yaml {
+                    serde_yaml::to_string(&value)?
+                } else {
+                    serde_json::to_string(&value)?
+                };
+                println!("{}", encoded.trim());
+            }
+
+            ImageListFormat::Jsonl => {
+                for image in images {
+                    let message = proto2dynamic(image)?;
+                    println!("{}", serde_json::to_string(&message)?);
+                }
+            }
+
+            ImageListFormat::KeyValue => {
+                for image in images {
+                    let kvs = proto2kv(image)?;
+                    println!("{}", kv2line(kvs));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_image_table(&self, images: Vec<CachedImage>) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut table = Table::new();
+        table.load_preset(UTF8_FULL_CONDENSED);
+        table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+        table.set_header(vec![
+            "id",
+            "references",
+            "digest",
+            "size",
+            "last used",
+            "in use",
+        ]);
+        for image in images {
+            let last_used = FancyDuration(Duration::from_secs(now.saturating_sub(image.last_used)))
+                .truncate(1)
+                .to_string();
+            table.add_row(vec![
+                Cell::new(image_id_short(&image.id)),
+                Cell::new(image.references.join("\n")),
+                Cell::new(image_digest_short(&image.digest)),
+                Cell::new(human_bytes(image.size as f64)),
+                Cell::new(format!("{} ago", last_used)),
+                Cell::new(if image.in_use { "yes" } else { "no" }),
+            ]);
+        }
+        if table.is_empty() {
+            println!("no images have been cached");
+        } else {
+            println!("{}", table);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Pull an image into the cache")]
+struct ImagePullCommand {
+    #[command(flatten)]
+    registry: RegistryAuthArgs,
+    #[arg(help = "Container image to pull")]
+    image: String,
+}
+
+impl ImagePullCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let mut stream = client
+            .pull_image(Request::new(PullImageRequest {
+                image: self.image.clone(),
+                registry_auth: self.registry.registry_auth(),
+            }))
+            .await?
+            .into_inner();
+
+        let mut last_phase = None;
+        let mut last_layers = Vec::new();
+        while let Some(reply) = stream.next().await {
+            let reply = reply?;
+            if let Some(progress) = reply.progress {
+                if last_phase != Some(progress.phase()) {
+                    last_phase = Some(progress.phase());
+                    println!("{}", image_pull_phase_text(progress.phase()));
+                }
+                for layer in &progress.layers {
+                    let key = (layer.id.clone(), layer.phase());
+                    if last_layers.contains(&key) {
+                        continue;
+                    }
+                    last_layers.push(key);
+                    if let Some(text) = image_pull_layer_phase_text(layer.phase()) {
+                        println!(
+                            "  {} {} ({})",
+                            image_digest_short(&layer.id),
+                            text,
+                            human_bytes(layer.total as f64)
+                        );
+                    }
+                }
+            }
+
+            if let Some(image) = reply.image {
+                println!(
+                    "{} {}",
+                    image_id_short(&image.id),
+                    image_digest_short(&image.digest)
+                );
+                return Ok(());
+            }
+        }
+        Err(anyhow!("image pull of {} ended unexpectedly", self.image))
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Remove a cached image")]
+struct ImageRemoveCommand {
+    #[arg(short, long, help = "Remove the image even if a guest is using it")]
+    force: bool,
+    #[arg(help = "Image to remove, either the reference, digest or id")]
+    image: String,
+}
+
+impl ImageRemoveCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let reply = client
+            .remove_image(Request::new(RemoveImageRequest {
+                image: self.image,
+                force: self.force,
+            }))
+            .await?
+            .into_inner();
+        for image in reply.removed {
+            println!("{}", image_id_short(&image.id));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Remove cached images that are not used by any guest")]
+struct ImagePruneCommand {
+    #[arg(
+        long,
+        help = "Only remove images unused for a duration, such as 10m or 2h"
+    )]
+    unused: Option<String>,
+}
+
+impl ImagePruneCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let unused_seconds = match self.unused {
+            Some(ref unused) => FancyDuration::<Duration>::from_str(unused)
+                .map_err(|error| anyhow!("invalid duration '{}': {}", unused, error))?
+                .duration()
+                .as_secs(),
+            None => 0,
+        };
+        let reply = client
+            .prune_images(Request::new(PruneImagesRequest { unused_seconds }))
+            .await?
+            .into_inner();
+        for image in &reply.removed {
+            println!("{}", image_id_short(&image.id));
+        }
+        println!(
+            "removed {} images, reclaimed {}",
+            reply.removed.len(),
+            human_bytes(reply.reclaimed as f64)
+        );
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Import images from an OCI layout or docker save archive")]
+struct ImageImportCommand {
+    #[arg(
+        short,
+        long,
+        help = "Reference to tag an archive holding a single image with"
+    )]
+    tag: Option<String>,
+    #[arg(help = "Image archive to import, as a tar file")]
+    archive: PathBuf,
+}
+
+impl ImageImportCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let mut file = File::open(&self.archive)
+            .await
+            .map_err(|error| anyhow!("failed to open {:?}: {}", self.archive, error))?;
+        let tag = self.tag.unwrap_or_default();
+        let input = stream! {
+            yield ImportImageRequest {
+                tag,
+                data: vec![],
+            };
+
+            let mut buffer = vec![0u8; 1024 * 1024];
+            loop {
+                let size = match file.read(&mut buffer).await {
+                    Ok(size) => size,
+                    Err(error) => {
+                        debug!("failed to read image archive: {}", error);
+                        break;
+                    }
+                };
+                if size == 0 {
+                    break;
+                }
+                yield ImportImageRequest {
+                    data: buffer[0..size].to_vec(),
+                    ..Default::default()
+                };
+            }
+        };
+
+        let reply = client.import_image(Request::new(input)).await?.into_inner();
+        for image in reply.images {
+            println!(
+                "{} {} {}",
+                image_id_short(&image.id),
+                image_digest_short(&image.digest),
+                image.references.join(",")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn image_id_short(id: &str) -> &str {
+    if id.len() > 12 {
+        &id[..12]
+    } else {
+        id
+    }
+}
+
+fn image_pull_phase_text(phase: ImagePullPhase) -> &'static str {
+    match phase {
+        ImagePullPhase::Resolving => "resolving",
+        ImagePullPhase::Resolved => "resolved",
+        ImagePullPhase::ConfigAcquire => "acquiring config",
+        ImagePullPhase::LayerAcquire => "acquiring layers",
+        ImagePullPhase::Packing => "packing",
+        ImagePullPhase::Complete => "complete",
+    }
+}
+
+fn image_pull_layer_phase_text(phase: ImagePullLayerPhase) -> Option<&'static str> {
+    match phase {
+        ImagePullLayerPhase::Downloaded => Some("downloaded"),
+        ImagePullLayerPhase::Extracted => Some("extracted"),
+        _ => None,
+    }
+}
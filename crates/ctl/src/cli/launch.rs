@@ -1,13 +1,16 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
 use krata::{
     events::EventStream,
     v1::{
         common::{
-            guest_image_spec::Image, GuestImageSpec, GuestOciImageSpec, GuestSpec, GuestStatus,
-            GuestTaskSpec, GuestTaskSpecEnvVar,
+            guest_health_check_spec::Check, guest_image_spec::Image, GuestHealthCheckCommand,
+            GuestHealthCheckHttp, GuestHealthCheckSpec, GuestHealthCheckTcp, GuestImageSpec,
+            GuestMountSpec, GuestOciImageSpec, GuestPortProtocol, GuestPortSpec,
+            GuestRestartPolicy, GuestRestartPolicyMode, GuestSpec, GuestStatus, GuestTaskResources,
+            GuestTaskSpec, GuestTaskSpecEnvVar, GuestVolumeSpec,
         },
         control::{
             control_service_client::ControlServiceClient, watch_events_reply::Event,
@@ -19,13 +22,30 @@ use log::error;
 use tokio::select;
 use tonic::{transport::Channel, Request};
 
-use crate::console::StdioConsoleStream;
+use crate::{cli::RegistryAuthArgs, console::StdioConsoleStream, manifest::load_network_policy};
+
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
+enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
 
 #[derive(Parser)]
 #[command(about = "Launch a new guest")]
 pub struct LauchCommand {
     #[arg(short, long, help = "Name of the guest")]
     name: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "Guest group to join, guests in a group share a private network"
+    )]
+    group: Option<String>,
+    #[arg(long, help = "Network policy for the guest in YAML or TOML format")]
+    network_policy: Option<String>,
+    #[command(flatten)]
+    registry: RegistryAuthArgs,
     #[arg(
         short,
         long,
@@ -40,14 +60,133 @@ pub struct LauchCommand {
         help = "Memory available to the guest, in megabytes"
     )]
     mem: u64,
+    #[arg(
+        long,
+        help = "vCPUs the guest can be resized to while running, defaults to the vCPUs"
+    )]
+    max_cpus: Option<u32>,
+    #[arg(
+        long,
+        help = "Memory the guest can be resized to while running, in megabytes, defaults to the memory"
+    )]
+    max_mem: Option<u64>,
+    #[arg(long, help = "Maximum number of processes the guest task may create")]
+    pids_limit: Option<u64>,
+    #[arg(
+        long,
+        help = "Memory available to the guest task, in megabytes, defaults to the guest memory"
+    )]
+    memory_limit: Option<u64>,
+    #[arg(
+        long,
+        value_parser = parse_cpu_limit,
+        help = "CPU time available to the guest task, in vCPUs, such as 0.5"
+    )]
+    cpu_limit: Option<f64>,
+    #[arg(long, help = "Relative IO weight of the guest task, from 1 to 10000")]
+    io_weight: Option<u32>,
+    #[arg(
+        long,
+        help = "Maximum disk read rate of the guest task, in bytes per second"
+    )]
+    io_read_bps: Option<u64>,
+    #[arg(
+        long,
+        help = "Maximum disk write rate of the guest task, in bytes per second"
+    )]
+    io_write_bps: Option<u64>,
     #[arg[short, long, help = "Environment variables set in the guest"]]
     env: Option<Vec<String>>,
+    #[arg(
+        short,
+        long,
+        help = "Volumes to attach to the guest, in the form volume:path[:ro]"
+    )]
+    volume: Vec<String>,
+    #[arg(
+        long,
+        help = "Host directories to share with the guest, in the form host:guest[:ro]"
+    )]
+    mount: Vec<String>,
+    #[arg(
+        short,
+        long,
+        help = "Ports to forward from the host to the guest, in the form host:guest[/udp]"
+    )]
+    port: Vec<String>,
+    #[arg(
+        long,
+        default_value = "never",
+        help = "Restart policy applied when the guest task exits"
+    )]
+    restart: RestartPolicy,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Maximum restarts for the on-failure policy, 0 for unlimited"
+    )]
+    restart_max_retries: u32,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Initial delay before restarting the guest, in seconds, doubled on each restart"
+    )]
+    restart_backoff: u64,
+    #[arg(
+        long,
+        default_value_t = 300,
+        help = "Maximum delay before restarting the guest, in seconds"
+    )]
+    restart_max_backoff: u64,
+    #[arg(
+        long,
+        conflicts_with_all = ["health_tcp", "health_http"],
+        help = "Shell command run inside the guest to check its health"
+    )]
+    health_cmd: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "health_http",
+        help = "Port inside the guest to check for accepted TCP connections"
+    )]
+    health_tcp: Option<u16>,
+    #[arg(
+        long,
+        help = "HTTP endpoint inside the guest to check, in the form port[/path]"
+    )]
+    health_http: Option<String>,
+    #[arg(
+        long,
+        help = "Interval between health checks, in seconds, defaults to 30"
+    )]
+    health_interval: Option<u64>,
+    #[arg(
+        long,
+        help = "Timeout of a single health check, in seconds, defaults to 30"
+    )]
+    health_timeout: Option<u64>,
+    #[arg(
+        long,
+        help = "Consecutive failures before the guest is unhealthy, defaults to 3"
+    )]
+    health_retries: Option<u32>,
+    #[arg(
+        long,
+        help = "Initial period where failed health checks are not counted, in seconds"
+    )]
+    health_start_period: Option<u64>,
     #[arg(
         short,
         long,
         help = "Attach to the guest after guest starts, implies --wait"
     )]
     attach: bool,
+    #[arg(
+        short,
+        long,
+        help = "Give the guest task the console as its terminal instead of capturing its output, implied by --attach"
+    )]
+    tty: bool,
     #[arg(
         short = 'W',
         long,
@@ -70,6 +209,26 @@ impl LauchCommand {
         mut client: ControlServiceClient<Channel>,
         events: EventStream,
     ) -> Result<()> {
+        let volumes = self
+            .volume
+            .iter()
+            .map(|x| parse_volume(x))
+            .collect::<Result<Vec<_>>>()?;
+        let mounts = self
+            .mount
+            .iter()
+            .map(|x| parse_mount(x))
+            .collect::<Result<Vec<_>>>()?;
+        let ports = self
+            .port
+            .iter()
+            .map(|x| parse_port(x))
+            .collect::<Result<Vec<_>>>()?;
+        let healthcheck = self.healthcheck()?;
+        let network_policy = match self.network_policy {
+            Some(ref path) => Some(load_network_policy(path).await?),
+            None => None,
+        };
         let request = CreateGuestRequest {
             spec: Some(GuestSpec {
                 name: self.name.unwrap_or_default(),
@@ -78,6 +237,8 @@ impl LauchCommand {
                 }),
                 vcpus: self.cpus,
                 mem: self.mem,
+                max_vcpus: self.max_cpus.unwrap_or(0),
+                max_mem: self.max_mem.unwrap_or(0),
                 task: Some(GuestTaskSpec {
                     environment: env_map(&self.env.unwrap_or_default())
                         .iter()
@@ -87,9 +248,39 @@ impl LauchCommand {
                         })
                         .collect(),
                     command: self.command,
+                    tty: self.tty || self.attach,
+                    resources: Some(GuestTaskResources {
+                        pids_max: self.pids_limit.unwrap_or(0),
+                        memory_max: self.memory_limit.unwrap_or(0) * 1024 * 1024,
+                        cpu_millis: self
+                            .cpu_limit
+                            .map(|x| (x * 1000.0).round() as u32)
+                            .unwrap_or(0),
+                        io_weight: self.io_weight.unwrap_or(0),
+                        io_read_bps: self.io_read_bps.unwrap_or(0),
+                        io_write_bps: self.io_write_bps.unwrap_or(0),
+                    }),
                 }),
                 annotations: vec![],
+                volumes,
+                mounts,
+                ports,
+                restart_policy: Some(GuestRestartPolicy {
+                    mode: match self.restart {
+                        RestartPolicy::Never => GuestRestartPolicyMode::Never,
+                        RestartPolicy::OnFailure => GuestRestartPolicyMode::OnFailure,
+                        RestartPolicy::Always => GuestRestartPolicyMode::Always,
+                    }
+                    .into(),
+                    max_retries: self.restart_max_retries,
+                    backoff_seconds: self.restart_backoff,
+                    max_backoff_seconds: self.restart_max_backoff,
+                }),
+                healthcheck,
+                group: self.group.unwrap_or_default(),
+                network_policy,
             }),
+            registry_auth: self.registry.registry_auth(),
         };
         let response = client
             .create_guest(Request::new(request))
@@ -121,6 +312,27 @@ impl LauchCommand {
         StdioConsoleStream::restore_terminal_mode();
         std::process::exit(code.unwrap_or(0));
     }
+
+    fn healthcheck(&self) -> Result<Option<GuestHealthCheckSpec>> {
+        let check = if let Some(ref command) = self.health_cmd {
+            Check::Command(GuestHealthCheckCommand {
+                command: vec!["/bin/sh".to_string(), "-c".to_string(), command.clone()],
+            })
+        } else if let Some(port) = self.health_tcp {
+            Check::Tcp(GuestHealthCheckTcp { port: port as u32 })
+        } else if let Some(ref http) = self.health_http {
+            Check::Http(parse_health_http(http)?)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(GuestHealthCheckSpec {
+            check: Some(check),
+            interval_seconds: self.health_interval.unwrap_or(0),
+            timeout_seconds: self.health_timeout.unwrap_or(0),
+            retries: self.health_retries.unwrap_or(0),
+            start_period_seconds: self.health_start_period.unwrap_or(0),
+        }))
+    }
 }
 
 async fn wait_guest_started(id: &str, events: EventStream) -> Result<()> {
@@ -172,3 +384,115 @@ fn env_map(env: &[String]) -> HashMap<String, String> {
     }
     map
 }
+
+fn parse_volume(value: &str) -> Result<GuestVolumeSpec> {
+    let parts = value.split(':').collect::<Vec<_>>();
+    let read_only = match parts.get(2) {
+        None => false,
+        Some(&"ro") => true,
+        Some(&"rw") => false,
+        Some(mode) => return Err(anyhow!("invalid volume mode '{}' in '{}'", mode, value)),
+    };
+    if parts.len() < 2 || parts.len() > 3 || parts[0].is_empty() || parts[1].is_empty() {
+        return Err(anyhow!(
+            "invalid volume '{}', expected volume:path[:ro]",
+            value
+        ));
+    }
+    Ok(GuestVolumeSpec {
+        volume: parts[0].to_string(),
+        path: parts[1].to_string(),
+        read_only,
+    })
+}
+
+fn parse_mount(value: &str) -> Result<GuestMountSpec> {
+    let parts = value.split(':').collect::<Vec<_>>();
+    let read_only = match parts.get(2) {
+        None => false,
+        Some(&"ro") => true,
+        Some(&"rw") => false,
+        Some(mode) => return Err(anyhow!("invalid mount mode '{}' in '{}'", mode, value)),
+    };
+    if parts.len() < 2 || parts.len() > 3 || parts[0].is_empty() || parts[1].is_empty() {
+        return Err(anyhow!(
+            "invalid mount '{}', expected host:guest[:ro]",
+            value
+        ));
+    }
+    let host_path = if Path::new(parts[0]).is_absolute() {
+        parts[0].to_string()
+    } else {
+        std::fs::canonicalize(parts[0])
+            .map_err(|error| anyhow!("unable to resolve host path '{}': {}", parts[0], error))?
+            .to_string_lossy()
+            .to_string()
+    };
+    Ok(GuestMountSpec {
+        host_path,
+        guest_path: parts[1].to_string(),
+        read_only,
+    })
+}
+
+fn parse_health_http(value: &str) -> Result<GuestHealthCheckHttp> {
+    let (port, path) = match value.find('/') {
+        Some(index) => value.split_at(index),
+        None => (value, "/"),
+    };
+    let port = port.parse::<u16>().map_err(|_| {
+        anyhow!(
+            "invalid health check endpoint '{}', expected port[/path]",
+            value
+        )
+    })?;
+    Ok(GuestHealthCheckHttp {
+        port: port as u32,
+        path: path.to_string(),
+    })
+}
+
+fn parse_port(value: &str) -> Result<GuestPortSpec> {
+    let (ports, protocol) = match value.split_once('/') {
+        None => (value, GuestPortProtocol::Tcp),
+        Some((ports, "tcp")) => (ports, GuestPortProtocol::Tcp),
+        Some((ports, "udp")) => (ports, GuestPortProtocol::Udp),
+        Some((_, protocol)) => {
+            return Err(anyhow!(
+                "invalid port protocol '{}' in '{}'",
+                protocol,
+                value
+            ))
+        }
+    };
+    let Some((host_port, guest_port)) = ports.split_once(':') else {
+        return Err(anyhow!(
+            "invalid port '{}', expected host:guest[/udp]",
+            value
+        ));
+    };
+    let host_port = host_port
+        .parse::<u16>()
+        .map_err(|_| anyhow!("invalid host port '{}' in '{}'", host_port, value))?;
+    let guest_port = guest_port
+        .parse::<u16>()
+        .map_err(|_| anyhow!("invalid guest port '{}' in '{}'", guest_port, value))?;
+    Ok(GuestPortSpec {
+        host_port: host_port as u32,
+        guest_port: guest_port as u32,
+        protocol: protocol.into(),
+    })
+}
+
+fn parse_cpu_limit(value: &str) -> Result<f64> {
+    let limit = value
+        .parse::<f64>()
+        .map_err(|_| anyhow!("invalid cpu limit '{}'", value))?;
+    if !limit.is_finite() || limit <= 0.0 {
+        return Err(anyhow!(
+            "cpu limit '{}' must be a positive number of vCPUs",
+            value
+        ));
+    }
+    Ok(limit)
+}
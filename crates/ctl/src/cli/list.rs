@@ -4,7 +4,7 @@ use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, Color, Table};
 use krata::{
     events::EventStream,
     v1::{
-        common::{Guest, GuestStatus},
+        common::{guest_image_spec::Image, Guest, GuestStatus},
         control::{
             control_service_client::ControlServiceClient, ListGuestsRequest, ResolveGuestRequest,
         },
@@ -14,7 +14,10 @@ use krata::{
 use serde_json::Value;
 use tonic::{transport::Channel, Request};
 
-use crate::format::{guest_simple_line, guest_status_text, kv2line, proto2dynamic, proto2kv};
+use crate::format::{
+    guest_health_text, guest_simple_line, guest_status_text, image_digest_short, kv2line,
+    proto2dynamic, proto2kv,
+};
 
 #[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
 enum ListFormat {
@@ -117,7 +120,9 @@ impl ListCommand {
         let mut table = Table::new();
         table.load_preset(UTF8_FULL_CONDENSED);
         table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
-        table.set_header(vec!["name", "uuid", "status", "ipv4", "ipv6"]);
+        table.set_header(vec![
+            "name", "uuid", "status", "ipv4", "ipv6", "image", "digest",
+        ]);
         for guest in guests {
             let ipv4 = guest
                 .state
@@ -131,18 +136,34 @@ impl ListCommand {
                 .and_then(|x| x.network.as_ref())
                 .map(|x| x.guest_ipv6.as_str())
                 .unwrap_or("n/a");
+            let digest = guest
+                .state
+                .as_ref()
+                .map(|x| x.image_digest.as_str())
+                .filter(|x| !x.is_empty())
+                .map(image_digest_short)
+                .unwrap_or_else(|| "n/a".to_string());
             let Some(spec) = guest.spec else {
                 continue;
             };
+            let image = match spec.image.as_ref().and_then(|x| x.image.as_ref()) {
+                Some(Image::Oci(oci)) => oci.image.clone(),
+                None => "n/a".to_string(),
+            };
             let status = guest.state.as_ref().cloned().unwrap_or_default().status();
-            let status_text = guest_status_text(status);
+            let mut status_text = guest_status_text(status);
+            if let Some(health) = guest.state.as_ref().and_then(|x| x.health_info.as_ref()) {
+                status_text = format!("{} ({})", status_text, guest_health_text(health.status()));
+            }
 
             let status_color = match status {
                 GuestStatus::Destroyed | GuestStatus::Failed => Color::Red,
-                GuestStatus::Destroying | GuestStatus::Exited | GuestStatus::Starting => {
-                    Color::Yellow
-                }
+                GuestStatus::Stopping
+                | GuestStatus::Destroying
+                | GuestStatus::Exited
+                | GuestStatus::Starting => Color::Yellow,
                 GuestStatus::Started => Color::Green,
+                GuestStatus::Paused => Color::Blue,
                 _ => Color::Reset,
             };
 
@@ -152,6 +173,8 @@ impl ListCommand {
                 Cell::new(status_text).fg(status_color),
                 Cell::new(ipv4.to_string()),
                 Cell::new(ipv6.to_string()),
+                Cell::new(image),
+                Cell::new(digest),
             ]);
         }
         if table.is_empty() {
@@ -1,58 +1,158 @@
-use anyhow::Result;
-use async_stream::stream;
-use clap::Parser;
-use krata::{
-    events::EventStream,
-    v1::control::{control_service_client::ControlServiceClient, ConsoleDataRequest},
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use tokio::select;
-use tokio_stream::{pending, StreamExt};
-use tonic::transport::Channel;
-
-use crate::console::StdioConsoleStream;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use fancy_duration::FancyDuration;
+use krata::v1::{
+    common::GuestLogStream,
+    control::{
+        control_service_client::ControlServiceClient, ReadGuestConsoleLogRequest,
+        ReadGuestLogsRequest,
+    },
+};
+use tokio::io::{stderr, stdout, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use tonic::{transport::Channel, Request};
+use uuid::Uuid;
 
 use super::resolve_guest;
 
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
+enum LogStream {
+    Stdout,
+    Stderr,
+}
+
 #[derive(Parser)]
 #[command(about = "View the logs of a guest")]
 pub struct LogsCommand {
     #[arg(short, long, help = "Follow output from the guest")]
     follow: bool,
+    #[arg(
+        long,
+        help = "Only show output newer than a duration, such as 10m or 2h"
+    )]
+    since: Option<String>,
+    #[arg(long, help = "Number of lines to show from the end of the logs")]
+    tail: Option<u64>,
+    #[arg(long, help = "Show the guest console, including kernel messages")]
+    console: bool,
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Task output streams to show, defaults to both stdout and stderr"
+    )]
+    stream: Vec<LogStream>,
+    #[arg(long, help = "Prefix each line of task output with its timestamp")]
+    timestamps: bool,
     #[arg(help = "Guest to show logs for, either the name or the uuid")]
     guest: String,
 }
 
 impl LogsCommand {
-    pub async fn run(
-        self,
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let guest_id = match resolve_guest(&mut client, &self.guest).await {
+            Ok(guest_id) => guest_id,
+            // guests that no longer exist can only be referenced by uuid.
+            Err(error) => match Uuid::from_str(&self.guest) {
+                Ok(uuid) => uuid.to_string(),
+                Err(_) => return Err(error),
+            },
+        };
+
+        let since = match self.since {
+            Some(ref since) => {
+                let duration = FancyDuration::<Duration>::from_str(since)
+                    .map_err(|error| anyhow!("invalid duration '{}': {}", since, error))?
+                    .duration();
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                now.saturating_sub(duration).as_millis() as u64
+            }
+            None => 0,
+        };
+
+        if self.console {
+            self.console_log(client, guest_id, since).await
+        } else {
+            self.task_logs(client, guest_id, since).await
+        }
+    }
+
+    async fn console_log(
+        &self,
+        mut client: ControlServiceClient<Channel>,
+        guest_id: String,
+        since: u64,
+    ) -> Result<()> {
+        let mut output = client
+            .read_guest_console_log(Request::new(ReadGuestConsoleLogRequest {
+                guest_id,
+                since,
+                tail: self.tail.unwrap_or(0),
+                follow: self.follow,
+            }))
+            .await?
+            .into_inner();
+
+        let mut stdout = stdout();
+        while let Some(reply) = output.next().await {
+            let reply = reply?;
+            stdout.write_all(&reply.data).await?;
+            stdout.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn task_logs(
+        &self,
         mut client: ControlServiceClient<Channel>,
-        events: EventStream,
+        guest_id: String,
+        since: u64,
     ) -> Result<()> {
-        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
-        let guest_id_stream = guest_id.clone();
-        let follow = self.follow;
-        let input = stream! {
-            yield ConsoleDataRequest { guest_id: guest_id_stream, data: Vec::new() };
-            if follow {
-                let mut pending = pending::<ConsoleDataRequest>();
-                while let Some(x) = pending.next().await {
-                    yield x;
+        let streams = self
+            .stream
+            .iter()
+            .map(|stream| match stream {
+                LogStream::Stdout => GuestLogStream::Stdout.into(),
+                LogStream::Stderr => GuestLogStream::Stderr.into(),
+            })
+            .collect();
+        let mut output = client
+            .read_guest_logs(Request::new(ReadGuestLogsRequest {
+                guest_id,
+                streams,
+                since,
+                until: 0,
+                tail: self.tail.unwrap_or(0),
+                follow: self.follow,
+            }))
+            .await?
+            .into_inner();
+
+        let mut stdout = stdout();
+        let mut stderr = stderr();
+        while let Some(reply) = output.next().await {
+            let reply = reply?;
+            for entry in reply.entries {
+                let mut line = Vec::new();
+                if self.timestamps {
+                    line.extend_from_slice(format!("{} ", entry.timestamp).as_bytes());
+                }
+                line.extend_from_slice(&entry.data);
+                line.push(b'\n');
+                if entry.stream() == GuestLogStream::Stderr {
+                    stderr.write_all(&line).await?;
+                } else {
+                    stdout.write_all(&line).await?;
                 }
             }
-        };
-        let output = client.console_data(input).await?.into_inner();
-        let stdout_handle =
-            tokio::task::spawn(async move { StdioConsoleStream::stdout(output).await });
-        let exit_hook_task = StdioConsoleStream::guest_exit_hook(guest_id.clone(), events).await?;
-        let code = select! {
-            x = stdout_handle => {
-                x??;
-                None
-            },
-            x = exit_hook_task => x?
-        };
-        StdioConsoleStream::restore_terminal_mode();
-        std::process::exit(code.unwrap_or(0));
+            stdout.flush().await?;
+            stderr.flush().await?;
+        }
+        Ok(())
     }
 }
@@ -1,24 +1,42 @@
+pub mod apply;
 pub mod attach;
+pub mod delete;
 pub mod destroy;
+pub mod exec;
+pub mod group;
+pub mod image;
 pub mod launch;
 pub mod list;
 pub mod logs;
 pub mod metrics;
+pub mod pause;
+pub mod policy;
 pub mod resolve;
+pub mod resume;
+pub mod stop;
+pub mod update;
+pub mod volume;
 pub mod watch;
 
+use std::path::PathBuf;
+
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use krata::{
-    client::ControlClientProvider,
+    client::{ControlClientProvider, ControlClientTls},
     events::EventStream,
-    v1::control::{control_service_client::ControlServiceClient, ResolveGuestRequest},
+    v1::control::{
+        control_service_client::ControlServiceClient, RegistryAuth, ResolveGuestRequest,
+    },
 };
 use tonic::{transport::Channel, Request};
 
 use self::{
-    attach::AttachCommand, destroy::DestroyCommand, launch::LauchCommand, list::ListCommand,
-    logs::LogsCommand, metrics::MetricsCommand, resolve::ResolveCommand, watch::WatchCommand,
+    apply::ApplyCommand, attach::AttachCommand, delete::DeleteCommand, destroy::DestroyCommand,
+    exec::ExecCommand, group::GroupCommand, image::ImageCommand, launch::LauchCommand,
+    list::ListCommand, logs::LogsCommand, metrics::MetricsCommand, pause::PauseCommand,
+    policy::PolicyCommand, resolve::ResolveCommand, resume::ResumeCommand, stop::StopCommand,
+    update::UpdateCommand, volume::VolumeCommand, watch::WatchCommand,
 };
 
 #[derive(Parser)]
@@ -35,25 +53,56 @@ pub struct ControlCommand {
     )]
     connection: String,
 
+    #[arg(
+        long,
+        help = "CA certificate used to verify the daemon for tls connections"
+    )]
+    tls_ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Client certificate presented to the daemon for tls connections"
+    )]
+    tls_certificate: Option<PathBuf>,
+
+    #[arg(long, help = "Client private key for tls connections")]
+    tls_key: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Launch(LauchCommand),
+    Launch(Box<LauchCommand>),
+    Apply(ApplyCommand),
+    Delete(DeleteCommand),
     Destroy(DestroyCommand),
+    Stop(StopCommand),
+    Pause(PauseCommand),
+    Resume(ResumeCommand),
+    Update(UpdateCommand),
+    Policy(PolicyCommand),
     List(ListCommand),
     Attach(AttachCommand),
     Logs(LogsCommand),
     Watch(WatchCommand),
     Resolve(ResolveCommand),
     Metrics(MetricsCommand),
+    Exec(ExecCommand),
+    Volume(VolumeCommand),
+    Group(GroupCommand),
+    Image(ImageCommand),
 }
 
 impl ControlCommand {
     pub async fn run(self) -> Result<()> {
-        let client = ControlClientProvider::dial(self.connection.parse()?).await?;
+        let tls = ControlClientTls {
+            ca_certificate: self.tls_ca,
+            certificate: self.tls_certificate,
+            key: self.tls_key,
+        };
+        let client = ControlClientProvider::dial_with_tls(self.connection.parse()?, &tls).await?;
         let events = EventStream::open(client.clone()).await?;
 
         match self.command {
@@ -61,16 +110,44 @@ impl ControlCommand {
                 launch.run(client, events).await?;
             }
 
+            Commands::Apply(apply) => {
+                apply.run(client, events).await?;
+            }
+
+            Commands::Delete(delete) => {
+                delete.run(client, events).await?;
+            }
+
             Commands::Destroy(destroy) => {
                 destroy.run(client, events).await?;
             }
 
+            Commands::Stop(stop) => {
+                stop.run(client, events).await?;
+            }
+
+            Commands::Pause(pause) => {
+                pause.run(client).await?;
+            }
+
+            Commands::Resume(resume) => {
+                resume.run(client).await?;
+            }
+
+            Commands::Update(update) => {
+                update.run(client).await?;
+            }
+
+            Commands::Policy(policy) => {
+                policy.run(client).await?;
+            }
+
             Commands::Attach(attach) => {
                 attach.run(client, events).await?;
             }
 
             Commands::Logs(logs) => {
-                logs.run(client, events).await?;
+                logs.run(client).await?;
             }
 
             Commands::List(list) => {
@@ -88,6 +165,22 @@ impl ControlCommand {
             Commands::Metrics(metrics) => {
                 metrics.run(client, events).await?;
             }
+
+            Commands::Exec(exec) => {
+                exec.run(client).await?;
+            }
+
+            Commands::Volume(volume) => {
+                volume.run(client).await?;
+            }
+
+            Commands::Image(image) => {
+                image.run(client).await?;
+            }
+
+            Commands::Group(group) => {
+                group.run(client).await?;
+            }
         }
         Ok(())
     }
@@ -110,3 +203,33 @@ pub async fn resolve_guest(
         Err(anyhow!("unable to resolve guest '{}'", name))
     }
 }
+
+/// Credentials passed this way are held in daemon memory only. Guests restarted after
+/// the daemon restarts use the daemon's registry auth_file instead.
+#[derive(Args, Clone, Default)]
+pub struct RegistryAuthArgs {
+    #[arg(long, help = "Username for the image registry")]
+    registry_username: Option<String>,
+    #[arg(long, help = "Password for the image registry")]
+    registry_password: Option<String>,
+    #[arg(
+        long,
+        help = "Identity token for the image registry, exchanged for an access token"
+    )]
+    registry_token: Option<String>,
+}
+
+impl RegistryAuthArgs {
+    /// Credentials to pass along with the request, or none to let the daemon decide.
+    pub fn registry_auth(&self) -> Option<RegistryAuth> {
+        if self.registry_username.is_none() && self.registry_token.is_none() {
+            return None;
+        }
+        Some(RegistryAuth {
+            username: self.registry_username.clone().unwrap_or_default(),
+            password: self.registry_password.clone().unwrap_or_default(),
+            identity_token: self.registry_token.clone().unwrap_or_default(),
+            registry_token: String::new(),
+        })
+    }
+}
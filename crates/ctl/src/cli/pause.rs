@@ -0,0 +1,25 @@
+use anyhow::Result;
+use clap::Parser;
+use krata::v1::control::{control_service_client::ControlServiceClient, PauseGuestRequest};
+
+use tonic::{transport::Channel, Request};
+
+use crate::cli::resolve_guest;
+
+#[derive(Parser)]
+#[command(about = "Pause a guest, freezing all of its vCPUs")]
+pub struct PauseCommand {
+    #[arg(help = "Guest to pause, either the name or the uuid")]
+    guest: String,
+}
+
+impl PauseCommand {
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        let _ = client
+            .pause_guest(Request::new(PauseGuestRequest { guest_id }))
+            .await?
+            .into_inner();
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use clap::Parser;
+use krata::v1::control::{
+    control_service_client::ControlServiceClient, UpdateGuestNetworkPolicyRequest,
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::{cli::resolve_guest, manifest::load_network_policy};
+
+#[derive(Parser)]
+#[command(about = "Replace the network policy of a running guest")]
+pub struct PolicyCommand {
+    #[arg(
+        short = 'f',
+        long = "file",
+        help = "Network policy in YAML or TOML format, use - to read YAML from stdin, omit to clear the policy"
+    )]
+    file: Option<String>,
+    #[arg(help = "Guest to update, either the name or the uuid")]
+    guest: String,
+}
+
+impl PolicyCommand {
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let policy = match self.file {
+            Some(ref file) => Some(load_network_policy(file).await?),
+            None => None,
+        };
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        client
+            .update_guest_network_policy(Request::new(UpdateGuestNetworkPolicyRequest {
+                guest_id,
+                policy,
+            }))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use clap::Parser;
+use krata::v1::control::{control_service_client::ControlServiceClient, ResumeGuestRequest};
+
+use tonic::{transport::Channel, Request};
+
+use crate::cli::resolve_guest;
+
+#[derive(Parser)]
+#[command(about = "Resume a paused guest")]
+pub struct ResumeCommand {
+    #[arg(help = "Guest to resume, either the name or the uuid")]
+    guest: String,
+}
+
+impl ResumeCommand {
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        let _ = client
+            .resume_guest(Request::new(ResumeGuestRequest { guest_id }))
+            .await?
+            .into_inner();
+        Ok(())
+    }
+}
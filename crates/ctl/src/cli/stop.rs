@@ -0,0 +1,48 @@
+use anyhow::Result;
+use clap::Parser;
+use krata::{
+    events::EventStream,
+    v1::control::{control_service_client::ControlServiceClient, DestroyGuestRequest},
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::cli::{destroy::wait_guest_destroyed, resolve_guest};
+
+#[derive(Parser)]
+#[command(about = "Stop a guest, destroying it once the task exits or the timeout elapses")]
+pub struct StopCommand {
+    #[arg(
+        short,
+        long,
+        default_value_t = 10,
+        help = "Seconds to wait for the guest task to exit before destroying the guest"
+    )]
+    timeout: u64,
+    #[arg(short = 'W', long, help = "Wait for the guest to be destroyed")]
+    wait: bool,
+    #[arg(help = "Guest to stop, either the name or the uuid")]
+    guest: String,
+}
+
+impl StopCommand {
+    pub async fn run(
+        self,
+        mut client: ControlServiceClient<Channel>,
+        events: EventStream,
+    ) -> Result<()> {
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        let _ = client
+            .destroy_guest(Request::new(DestroyGuestRequest {
+                guest_id: guest_id.clone(),
+                timeout_seconds: self.timeout,
+                force: self.timeout == 0,
+            }))
+            .await?
+            .into_inner();
+        if self.wait {
+            wait_guest_destroyed(&guest_id, events).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use krata::v1::control::{
+    control_service_client::ControlServiceClient, UpdateGuestResourcesRequest,
+};
+
+use tonic::{transport::Channel, Request};
+
+use crate::cli::resolve_guest;
+
+#[derive(Parser)]
+#[command(about = "Update the vCPUs and memory of a running guest")]
+pub struct UpdateCommand {
+    #[arg(short, long, help = "vCPUs online in the guest")]
+    cpus: Option<u32>,
+    #[arg(short, long, help = "Memory target of the guest, in megabytes")]
+    mem: Option<u64>,
+    #[arg(help = "Guest to update, either the name or the uuid")]
+    guest: String,
+}
+
+impl UpdateCommand {
+    pub async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        if self.cpus.is_none() && self.mem.is_none() {
+            return Err(anyhow!("at least one of --cpus or --mem must be specified"));
+        }
+
+        let guest_id: String = resolve_guest(&mut client, &self.guest).await?;
+        let reply = client
+            .update_guest_resources(Request::new(UpdateGuestResourcesRequest {
+                guest_id,
+                vcpus: self.cpus.unwrap_or(0),
+                mem: self.mem.unwrap_or(0),
+            }))
+            .await?
+            .into_inner();
+        if let Some(resources) = reply.resources {
+            println!("vcpus={} mem={}", resources.vcpus, resources.mem);
+        }
+        Ok(())
+    }
+}
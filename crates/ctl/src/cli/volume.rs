@@ -0,0 +1,183 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, Table};
+use krata::v1::{
+    common::{Volume, VolumeSpec},
+    control::{
+        control_service_client::ControlServiceClient, CreateVolumeRequest, DestroyVolumeRequest,
+        ListVolumesRequest,
+    },
+};
+
+use serde_json::Value;
+use tonic::{transport::Channel, Request};
+
+use crate::format::{kv2line, proto2dynamic, proto2kv};
+
+#[derive(Parser)]
+#[command(about = "Manage persistent guest volumes")]
+pub struct VolumeCommand {
+    #[command(subcommand)]
+    command: VolumeCommands,
+}
+
+#[derive(Subcommand)]
+enum VolumeCommands {
+    Create(VolumeCreateCommand),
+    List(VolumeListCommand),
+    Destroy(VolumeDestroyCommand),
+}
+
+impl VolumeCommand {
+    pub async fn run(self, client: ControlServiceClient<Channel>) -> Result<()> {
+        match self.command {
+            VolumeCommands::Create(create) => create.run(client).await,
+            VolumeCommands::List(list) => list.run(client).await,
+            VolumeCommands::Destroy(destroy) => destroy.run(client).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Create a new volume")]
+struct VolumeCreateCommand {
+    #[arg(
+        short,
+        long,
+        default_value_t = 1024,
+        help = "Size of the volume, in megabytes"
+    )]
+    size: u64,
+    #[arg(help = "Name of the volume")]
+    name: String,
+}
+
+impl VolumeCreateCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let reply = client
+            .create_volume(Request::new(CreateVolumeRequest {
+                spec: Some(VolumeSpec {
+                    name: self.name,
+                    size: self.size,
+                }),
+            }))
+            .await?
+            .into_inner();
+        println!("{}", reply.volume_id);
+        Ok(())
+    }
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
+enum VolumeListFormat {
+    Table,
+    Json,
+    JsonPretty,
+    Jsonl,
+    Yaml,
+    KeyValue,
+}
+
+#[derive(Parser)]
+#[command(about = "List the volumes on the hypervisor")]
+struct VolumeListCommand {
+    #[arg(short, long, default_value = "table", help = "Output format")]
+    format: VolumeListFormat,
+}
+
+impl VolumeListCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        let mut volumes = client
+            .list_volumes(Request::new(ListVolumesRequest {}))
+            .await?
+            .into_inner()
+            .volumes;
+
+        volumes.sort_by(|a, b| {
+            a.spec
+                .as_ref()
+                .map(|x| x.name.as_str())
+                .unwrap_or("")
+                .cmp(b.spec.as_ref().map(|x| x.name.as_str()).unwrap_or(""))
+        });
+
+        match self.format {
+            VolumeListFormat::Table => {
+                self.print_volume_table(volumes)?;
+            }
+
+            VolumeListFormat::Json | VolumeListFormat::JsonPretty | VolumeListFormat::Yaml => {
+                let mut values = Vec::new();
+                for volume in volumes {
+                    let message = proto2dynamic(volume)?;
+                    values.push(serde_json::to_value(message)?);
+                }
+                let value = Value::Array(values);
+                let encoded = if self.format == VolumeListFormat::JsonPretty {
+                    serde_json::to_string_pretty(&value)?
+                } else if self.format == VolumeListFormat::Yaml {
+                    serde_yaml::to_string(&value)?
+                } else {
+                    serde_json::to_string(&value)?
+                };
+                println!("{}", encoded.trim());
+            }
+
+            VolumeListFormat::Jsonl => {
+                for volume in volumes {
+                    let message = proto2dynamic(volume)?;
+                    println!("{}", serde_json::to_string(&message)?);
+                }
+            }
+
+            VolumeListFormat::KeyValue => {
+                for volume in volumes {
+                    let kvs = proto2kv(volume)?;
+                    println!("{}", kv2line(kvs));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_volume_table(&self, volumes: Vec<Volume>) -> Result<()> {
+        let mut table = Table::new();
+        table.load_preset(UTF8_FULL_CONDENSED);
+        table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+        table.set_header(vec!["name", "uuid", "size"]);
+        for volume in volumes {
+            let Some(spec) = volume.spec else {
+                continue;
+            };
+            table.add_row(vec![
+                Cell::new(spec.name),
+                Cell::new(volume.id),
+                Cell::new(format!("{} MB", spec.size)),
+            ]);
+        }
+        if table.is_empty() {
+            println!("no volumes have been created");
+        } else {
+            println!("{}", table);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Destroy a volume")]
+struct VolumeDestroyCommand {
+    #[arg(help = "Volume to destroy, either the name or the uuid")]
+    volume: String,
+}
+
+impl VolumeDestroyCommand {
+    async fn run(self, mut client: ControlServiceClient<Channel>) -> Result<()> {
+        client
+            .destroy_volume(Request::new(DestroyVolumeRequest {
+                volume_id: self.volume,
+            }))
+            .await?;
+        Ok(())
+    }
+}
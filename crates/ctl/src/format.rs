@@ -3,7 +3,9 @@ use std::{collections::HashMap, time::Duration};
 use anyhow::Result;
 use fancy_duration::FancyDuration;
 use human_bytes::human_bytes;
-use krata::v1::common::{Guest, GuestMetricFormat, GuestMetricNode, GuestStatus};
+use krata::v1::common::{
+    Guest, GuestHealthStatus, GuestMetricFormat, GuestMetricNode, GuestStatus,
+};
 use prost_reflect::{DynamicMessage, FieldDescriptor, ReflectMessage, Value as ReflectValue};
 use prost_types::Value;
 use termtree::Tree;
@@ -84,6 +86,8 @@ pub fn guest_status_text(status: GuestStatus) -> String {
     match status {
         GuestStatus::Starting => "starting",
         GuestStatus::Started => "started",
+        GuestStatus::Paused => "paused",
+        GuestStatus::Stopping => "stopping",
         GuestStatus::Destroying => "destroying",
         GuestStatus::Destroyed => "destroyed",
         GuestStatus::Exited => "exited",
@@ -93,6 +97,16 @@ pub fn guest_status_text(status: GuestStatus) -> String {
     .to_string()
 }
 
+pub fn guest_health_text(status: GuestHealthStatus) -> String {
+    match status {
+        GuestHealthStatus::Starting => "health: starting",
+        GuestHealthStatus::Healthy => "healthy",
+        GuestHealthStatus::Unhealthy => "unhealthy",
+        _ => "health: unknown",
+    }
+    .to_string()
+}
+
 pub fn guest_simple_line(guest: &Guest) -> String {
     let state = guest_status_text(
         guest
@@ -105,7 +119,23 @@ pub fn guest_simple_line(guest: &Guest) -> String {
     let network = guest.state.as_ref().and_then(|x| x.network.as_ref());
     let ipv4 = network.map(|x| x.guest_ipv4.as_str()).unwrap_or("");
     let ipv6 = network.map(|x| x.guest_ipv6.as_str()).unwrap_or("");
-    format!("{}\t{}\t{}\t{}\t{}", guest.id, state, name, ipv4, ipv6)
+    let digest = guest
+        .state
+        .as_ref()
+        .map(|x| x.image_digest.as_str())
+        .unwrap_or("");
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        guest.id, state, name, ipv4, ipv6, digest
+    )
+}
+
+/// Shortens an image digest for display, `sha256:0123456789ab`.
+pub fn image_digest_short(digest: &str) -> String {
+    match digest.split_once(':') {
+        Some((algorithm, hex)) if hex.len() > 12 => format!("{}:{}", algorithm, &hex[..12]),
+        _ => digest.to_string(),
+    }
 }
 
 fn metrics_value_string(value: Value) -> String {
@@ -1,3 +1,4 @@
 pub mod cli;
 pub mod console;
 pub mod format;
+pub mod manifest;
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use krata::v1::common::{GuestNetworkPolicy, GuestSpec};
+use prost_reflect::{DynamicMessage, ReflectMessage};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{fs, io::AsyncReadExt};
+
+/// Loads the guest specs described by a manifest file.
+/// TOML files hold a single guest, YAML files hold one guest per document.
+pub async fn load_guest_manifests(path: &str) -> Result<Vec<GuestSpec>> {
+    let mut specs = Vec::new();
+    for value in load_manifest_values(path).await? {
+        let spec = message_from_value::<GuestSpec>(value)?;
+        if spec.name.is_empty() {
+            return Err(anyhow!("guest manifest in {} does not have a name", path));
+        }
+        specs.push(spec);
+    }
+    Ok(specs)
+}
+
+pub async fn load_network_policy(path: &str) -> Result<GuestNetworkPolicy> {
+    let mut values = load_manifest_values(path).await?;
+    if values.len() != 1 {
+        return Err(anyhow!(
+            "expected a single network policy in {}, found {}",
+            path,
+            values.len()
+        ));
+    }
+    message_from_value::<GuestNetworkPolicy>(values.remove(0))
+}
+
+async fn load_manifest_values(path: &str) -> Result<Vec<Value>> {
+    let content = if path == "-" {
+        let mut content = String::new();
+        tokio::io::stdin().read_to_string(&mut content).await?;
+        content
+    } else {
+        fs::read_to_string(path).await?
+    };
+
+    let is_toml = Path::new(path)
+        .extension()
+        .map(|x| x == "toml")
+        .unwrap_or(false);
+    if is_toml {
+        return Ok(vec![toml::from_str::<Value>(&content)?]);
+    }
+
+    let mut values = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&content) {
+        let value = Value::deserialize(document)?;
+        if !value.is_null() {
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+fn message_from_value<T: ReflectMessage + Default>(value: Value) -> Result<T> {
+    let message = DynamicMessage::deserialize(T::default().descriptor(), value)?;
+    Ok(message.transcode_to::<T>()?)
+}
@@ -1,21 +1,85 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use env_logger::Env;
+use ipnetwork::{Ipv4Network, Ipv6Network};
 use krata::dial::ControlDialAddress;
-use kratad::Daemon;
-use kratart::Runtime;
-use log::LevelFilter;
+use kratad::{config::DaemonConfig, Daemon, DaemonReloadHandle};
+use kratart::{Runtime, RuntimeGuestFiles};
+use log::{error, info, LevelFilter};
 use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
     str::FromStr,
-    sync::{atomic::AtomicBool, Arc},
 };
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
-#[derive(Parser)]
+const DEFAULT_LISTEN_ADDRESS: &str = "unix:///var/lib/krata/daemon.socket";
+
+#[derive(Parser, Clone)]
 struct DaemonCommand {
-    #[arg(short, long, default_value = "unix:///var/lib/krata/daemon.socket")]
-    listen: String,
+    #[arg(
+        short,
+        long,
+        help = "Addresses to listen on, may be specified multiple times [default: unix:///var/lib/krata/daemon.socket]"
+    )]
+    listen: Vec<String>,
     #[arg(short, long, default_value = "/var/lib/krata")]
     store: String,
+    #[arg(
+        short,
+        long,
+        help = "Path to the daemon config file, defaults to daemon.toml in the store"
+    )]
+    config: Option<String>,
+    #[arg(long, help = "IPv4 subnet to allocate guest addresses from")]
+    network_ipv4_subnet: Option<Ipv4Network>,
+    #[arg(long, help = "IPv4 gateway address for guests")]
+    network_ipv4_gateway: Option<Ipv4Addr>,
+    #[arg(long, help = "IPv6 subnet to allocate guest addresses from")]
+    network_ipv6_subnet: Option<Ipv6Network>,
+    #[arg(long, help = "IPv6 gateway address for guests")]
+    network_ipv6_gateway: Option<Ipv6Addr>,
+    #[arg(long, help = "DNS servers for guests")]
+    network_nameserver: Vec<IpAddr>,
+}
+
+impl DaemonCommand {
+    async fn load_config(&self) -> Result<DaemonConfig> {
+        let path = self
+            .config
+            .clone()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(&self.store).join("daemon.toml"));
+        if self.config.is_some() && !path.exists() {
+            return Err(anyhow!("config file {:?} does not exist", path));
+        }
+        let mut config = DaemonConfig::load(&path).await?;
+        if !self.listen.is_empty() {
+            config.listen.clone_from(&self.listen);
+        }
+        if config.listen.is_empty() {
+            config.listen = vec![DEFAULT_LISTEN_ADDRESS.to_string()];
+        }
+        if let Some(subnet) = self.network_ipv4_subnet {
+            config.network.ipv4.subnet = subnet;
+        }
+        if let Some(gateway) = self.network_ipv4_gateway {
+            config.network.ipv4.gateway = gateway;
+        }
+        if let Some(subnet) = self.network_ipv6_subnet {
+            config.network.ipv6.subnet = subnet;
+        }
+        if let Some(gateway) = self.network_ipv6_gateway {
+            config.network.ipv6.gateway = gateway;
+        }
+        if !self.network_nameserver.is_empty() {
+            config
+                .network
+                .nameservers
+                .clone_from(&self.network_nameserver);
+        }
+        Ok(config)
+    }
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
@@ -23,18 +87,42 @@ async fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info"))
         .filter(Some("backhand::filesystem::writer"), LevelFilter::Warn)
         .init();
-    mask_sighup()?;
+    let hangup = signal(SignalKind::hangup())?;
 
     let args = DaemonCommand::parse();
-    let addr = ControlDialAddress::from_str(&args.listen)?;
-    let runtime = Runtime::new(args.store.clone()).await?;
-    let mut daemon = Daemon::new(args.store.clone(), runtime).await?;
-    daemon.listen(addr).await?;
+    let config = args.load_config().await?;
+    let addrs = config
+        .listen
+        .iter()
+        .map(|address| ControlDialAddress::from_str(address))
+        .collect::<Result<Vec<_>>>()?;
+    let runtime = Runtime::new(
+        args.store.clone(),
+        RuntimeGuestFiles {
+            kernel: config.runtime.kernel.clone(),
+            initrd: config.runtime.initrd.clone(),
+        },
+    )
+    .await?;
+    let mut daemon = Daemon::new(args.store.clone(), config, runtime).await?;
+    tokio::task::spawn(reload_on_sighup(hangup, args, daemon.reload_handle()));
+    daemon.listen(addrs).await?;
     Ok(())
 }
 
-fn mask_sighup() -> Result<()> {
-    let flag = Arc::new(AtomicBool::new(false));
-    signal_hook::flag::register(signal_hook::consts::SIGHUP, flag)?;
-    Ok(())
+async fn reload_on_sighup(mut hangup: Signal, args: DaemonCommand, handle: DaemonReloadHandle) {
+    while hangup.recv().await.is_some() {
+        info!("received SIGHUP, reloading daemon config");
+        let config = match args.load_config().await {
+            Ok(config) => config,
+            Err(error) => {
+                error!("failed to load daemon config: {}", error);
+                continue;
+            }
+        };
+
+        if let Err(error) = handle.reload(config).await {
+            error!("failed to reload daemon config: {}", error);
+        }
+    }
 }
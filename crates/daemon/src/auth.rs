@@ -0,0 +1,341 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tonic::{
+    service::Interceptor,
+    transport::server::{TcpConnectInfo, UdsConnectInfo},
+    Request, Status,
+};
+use x509_parser::{
+    certificate::X509Certificate,
+    der_parser::asn1_rs::Tag,
+    objects::{oid2abbrev, oid_registry},
+    oid_registry::OID_X509_COMMON_NAME,
+    prelude::FromDer,
+    x509::AttributeTypeAndValue,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum DaemonRole {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl Display for DaemonRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonRole::Viewer => write!(f, "viewer"),
+            DaemonRole::Operator => write!(f, "operator"),
+            DaemonRole::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DaemonPolicy {
+    pub default_role: Option<DaemonRole>,
+    pub rules: Vec<DaemonPolicyRule>,
+}
+
+/// A rule grants its role to peers matching every selector it specifies.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DaemonPolicyRule {
+    pub role: DaemonRole,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub common_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DaemonPeer {
+    Unix {
+        uid: u32,
+        gid: u32,
+    },
+    Certificate {
+        subject: String,
+        common_name: Option<String>,
+    },
+    #[default]
+    Anonymous,
+}
+
+impl Display for DaemonPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonPeer::Unix { uid, gid } => write!(f, "uid={} gid={}", uid, gid),
+            DaemonPeer::Certificate { subject, .. } => write!(f, "subject={}", subject),
+            DaemonPeer::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DaemonCaller {
+    pub peer: DaemonPeer,
+    pub role: Option<DaemonRole>,
+}
+
+impl DaemonCaller {
+    #[allow(clippy::result_large_err)]
+    pub fn require<T>(request: &Request<T>, role: DaemonRole) -> Result<(), Status> {
+        let Some(caller) = request.extensions().get::<DaemonCaller>() else {
+            return Err(Status::permission_denied("caller identity is unknown"));
+        };
+
+        match caller.role {
+            Some(granted) if granted >= role => Ok(()),
+            Some(granted) => Err(Status::permission_denied(format!(
+                "peer {} has role {} but {} is required",
+                caller.peer, granted, role
+            ))),
+            None => Err(Status::permission_denied(format!(
+                "peer {} is not permitted by the daemon policy",
+                caller.peer
+            ))),
+        }
+    }
+}
+
+impl DaemonPolicy {
+    pub async fn load(path: &Path) -> Result<DaemonPolicy> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|error| anyhow!("failed to read policy {:?}: {}", path, error))?;
+        let policy: DaemonPolicy = toml::from_str(&content)
+            .map_err(|error| anyhow!("failed to parse policy {:?}: {}", path, error))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.uid.is_none()
+                && rule.gid.is_none()
+                && rule.subject.is_none()
+                && rule.common_name.is_none()
+            {
+                return Err(anyhow!("policy rule {} does not match any peer", index));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn role(&self, peer: &DaemonPeer) -> Option<DaemonRole> {
+        if let DaemonPeer::Unix { uid: 0, .. } = peer {
+            return Some(DaemonRole::Admin);
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(peer))
+            .map(|rule| rule.role)
+            .max()
+            .or(self.default_role)
+    }
+}
+
+impl DaemonPolicyRule {
+    fn matches(&self, peer: &DaemonPeer) -> bool {
+        match peer {
+            DaemonPeer::Unix { uid, gid } => {
+                (self.uid.is_some() || self.gid.is_some())
+                    && self.subject.is_none()
+                    && self.common_name.is_none()
+                    && self.uid.map(|x| x == *uid).unwrap_or(true)
+                    && self.gid.map(|x| x == *gid).unwrap_or(true)
+            }
+
+            DaemonPeer::Certificate {
+                subject,
+                common_name,
+            } => {
+                (self.subject.is_some() || self.common_name.is_some())
+                    && self.uid.is_none()
+                    && self.gid.is_none()
+                    && self.subject.as_ref().map(|x| x == subject).unwrap_or(true)
+                    && self
+                        .common_name
+                        .as_ref()
+                        .map(|x| Some(x) == common_name.as_ref())
+                        .unwrap_or(true)
+            }
+
+            DaemonPeer::Anonymous => false,
+        }
+    }
+}
+
+/// Resolves the identity of every control API caller and attaches a
+/// [DaemonCaller] to the request. Without a policy file every authenticated peer
+/// is an admin and anonymous peers are refused.
+#[derive(Clone)]
+pub struct DaemonAuthorizer {
+    path: Option<PathBuf>,
+    policy: Arc<RwLock<Option<DaemonPolicy>>>,
+}
+
+impl DaemonAuthorizer {
+    pub async fn new(path: Option<PathBuf>) -> Result<DaemonAuthorizer> {
+        let policy = match path {
+            Some(ref path) => Some(DaemonPolicy::load(path).await?),
+            None => None,
+        };
+        Ok(DaemonAuthorizer {
+            path,
+            policy: Arc::new(RwLock::new(policy)),
+        })
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let policy = DaemonPolicy::load(path).await?;
+        *self
+            .policy
+            .write()
+            .map_err(|_| anyhow!("policy lock was poisoned"))? = Some(policy);
+        Ok(())
+    }
+
+    fn peer(request: &Request<()>) -> DaemonPeer {
+        if let Some(info) = request.extensions().get::<UdsConnectInfo>() {
+            if let Some(ref cred) = info.peer_cred {
+                return DaemonPeer::Unix {
+                    uid: cred.uid(),
+                    gid: cred.gid(),
+                };
+            }
+        }
+
+        if request.extensions().get::<TcpConnectInfo>().is_some() {
+            return DaemonPeer::Anonymous;
+        }
+
+        let Some(certificates) = request.peer_certs() else {
+            return DaemonPeer::Anonymous;
+        };
+        let Some(certificate) = certificates.first() else {
+            return DaemonPeer::Anonymous;
+        };
+        match certificate_subject(certificate.get_ref()) {
+            Some((subject, common_name)) => DaemonPeer::Certificate {
+                subject,
+                common_name,
+            },
+            None => DaemonPeer::Anonymous,
+        }
+    }
+}
+
+impl Interceptor for DaemonAuthorizer {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let peer = DaemonAuthorizer::peer(&request);
+        let policy = self
+            .policy
+            .read()
+            .map_err(|_| Status::internal("policy lock was poisoned"))?;
+        let role = match *policy {
+            Some(ref policy) => policy.role(&peer),
+            // without a policy only authenticated peers are trusted, plain tcp callers get no role
+            None => match peer {
+                DaemonPeer::Anonymous => None,
+                _ => Some(DaemonRole::Admin),
+            },
+        };
+        drop(policy);
+        debug!("control api peer {} resolved to role {:?}", peer, role);
+        request.extensions_mut().insert(DaemonCaller { peer, role });
+        Ok(request)
+    }
+}
+
+/// Extracts the subject of a DER encoded X.509 certificate, formatted as an
+/// RFC 4514 distinguished name (e.g. `CN=build,O=krata`), and its common name.
+/// Every attribute is rendered, so a subject that cannot be rendered exactly
+/// yields `None` rather than a name that could collide with another.
+fn certificate_subject(der: &[u8]) -> Option<(String, Option<String>)> {
+    let (_, certificate) = X509Certificate::from_der(der).ok()?;
+    let registry = oid_registry();
+    let mut rdns = Vec::new();
+    let mut common_name = None;
+    for rdn in certificate.subject().iter_rdn() {
+        let mut attributes = Vec::new();
+        for attribute in rdn.iter() {
+            let value = attribute_value(attribute)?;
+            let name = match oid2abbrev(attribute.attr_type(), registry) {
+                Ok(name) => name.to_string(),
+                Err(_) => attribute.attr_type().to_id_string(),
+            };
+            if attribute.attr_type() == &OID_X509_COMMON_NAME {
+                common_name = Some(value.clone());
+            }
+            attributes.push(format!("{}={}", name, escape_dn_value(&value)));
+        }
+        rdns.push(attributes.join("+"));
+    }
+    rdns.reverse();
+    Some((rdns.join(","), common_name))
+}
+
+fn attribute_value(attribute: &AttributeTypeAndValue) -> Option<String> {
+    let value = attribute.attr_value();
+    match value.tag() {
+        Tag::BmpString => {
+            let data = value.as_bytes();
+            if data.len() % 2 != 0 {
+                return None;
+            }
+            let units = data
+                .chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                .collect::<Vec<_>>();
+            String::from_utf16(&units).ok()
+        }
+
+        Tag::UniversalString => {
+            let data = value.as_bytes();
+            if data.len() % 4 != 0 {
+                return None;
+            }
+            data.chunks_exact(4)
+                .map(|x| char::from_u32(u32::from_be_bytes([x[0], x[1], x[2], x[3]])))
+                .collect()
+        }
+
+        _ => attribute.as_str().ok().map(|x| x.to_string()),
+    }
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (index, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' if index == 0 => escaped.push_str("\\#"),
+            ' ' if index == 0 || index == last => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
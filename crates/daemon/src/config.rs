@@ -0,0 +1,341 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use ipnetwork::{Ipv4Network, Ipv6Network};
+use krata::dial::ControlDialAddress;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub listen: Vec<String>,
+    /// Permits tcp:// listen addresses, which are neither encrypted nor authenticated.
+    pub allow_insecure_tcp: bool,
+    pub reconciler: DaemonReconcilerConfig,
+    pub console: DaemonConsoleConfig,
+    pub logs: DaemonLogsConfig,
+    pub runtime: DaemonRuntimeConfig,
+    pub tls: DaemonTlsConfig,
+    pub auth: DaemonAuthConfig,
+    pub network: DaemonNetworkConfig,
+    pub registry: DaemonRegistryConfig,
+    pub mounts: DaemonMountsConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonReconcilerConfig {
+    pub parallel_limit: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConsoleConfig {
+    pub buffer_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonLogsConfig {
+    pub max_file_size: u64,
+    pub max_files: u64,
+    pub max_age_hours: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonRuntimeConfig {
+    pub kernel: Option<PathBuf>,
+    pub initrd: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonTlsConfig {
+    pub certificate: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonAuthConfig {
+    pub policy: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonRegistryConfig {
+    /// Docker style config.json holding registry credentials and credential helpers.
+    /// Credentials passed with a request are not persisted, only these survive a restart.
+    pub auth_file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonMountsConfig {
+    /// Host directories that guests may mount, along with everything beneath them.
+    /// Host mounts are refused when this is empty.
+    pub allowed_paths: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonNetworkConfig {
+    pub ipv4: DaemonIpv4NetworkConfig,
+    pub ipv6: DaemonIpv6NetworkConfig,
+    pub nameservers: Vec<IpAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonIpv4NetworkConfig {
+    pub subnet: Ipv4Network,
+    pub gateway: Ipv4Addr,
+    /// Address of the host on the krata0 interface, never assigned to guests.
+    pub host: Ipv4Addr,
+    pub reserved: Vec<Ipv4Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonIpv6NetworkConfig {
+    pub subnet: Ipv6Network,
+    pub gateway: Ipv6Addr,
+    pub reserved: Vec<Ipv6Addr>,
+}
+
+impl Default for DaemonReconcilerConfig {
+    fn default() -> Self {
+        Self { parallel_limit: 5 }
+    }
+}
+
+impl Default for DaemonConsoleConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Default for DaemonLogsConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 8 * 1024 * 1024,
+            max_files: 4,
+            max_age_hours: 24 * 7,
+        }
+    }
+}
+
+impl Default for DaemonNetworkConfig {
+    fn default() -> Self {
+        Self {
+            ipv4: DaemonIpv4NetworkConfig::default(),
+            ipv6: DaemonIpv6NetworkConfig::default(),
+            nameservers: vec![
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)),
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1001)),
+            ],
+        }
+    }
+}
+
+impl Default for DaemonIpv4NetworkConfig {
+    fn default() -> Self {
+        Self {
+            subnet: Ipv4Network::new(Ipv4Addr::new(10, 75, 0, 0), 16)
+                .expect("default ipv4 subnet is valid"),
+            gateway: Ipv4Addr::new(10, 75, 70, 1),
+            host: Ipv4Addr::new(10, 75, 0, 1),
+            reserved: vec![],
+        }
+    }
+}
+
+impl Default for DaemonIpv6NetworkConfig {
+    fn default() -> Self {
+        Self {
+            subnet: Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10)
+                .expect("default ipv6 subnet is valid"),
+            gateway: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            reserved: vec![],
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub async fn load(path: &Path) -> Result<DaemonConfig> {
+        if !path.exists() {
+            return Ok(DaemonConfig::default());
+        }
+        let content = fs::read_to_string(path).await?;
+        let config: DaemonConfig = toml::from_str(&content)
+            .map_err(|error| anyhow!("failed to parse config {:?}: {}", path, error))?;
+        Ok(config)
+    }
+
+    pub fn validate(&mut self) -> Result<()> {
+        for address in &self.listen {
+            let parsed = ControlDialAddress::from_str(address)
+                .map_err(|error| anyhow!("invalid listen address {}: {}", address, error))?;
+            if let ControlDialAddress::Tcp { .. } = parsed {
+                if !self.allow_insecure_tcp {
+                    return Err(anyhow!(
+                        "listen address {} is not encrypted or authenticated, use tls:// or set allow_insecure_tcp",
+                        address
+                    ));
+                }
+            }
+        }
+
+        if self.reconciler.parallel_limit == 0 {
+            return Err(anyhow!("reconciler parallel_limit must be at least 1"));
+        }
+
+        if self.console.buffer_size == 0 {
+            return Err(anyhow!("console buffer_size must be at least 1"));
+        }
+
+        if self.logs.max_file_size == 0 || self.logs.max_files == 0 {
+            return Err(anyhow!(
+                "logs max_file_size and max_files must be at least 1"
+            ));
+        }
+
+        for (name, path) in [
+            ("runtime kernel", &self.runtime.kernel),
+            ("runtime initrd", &self.runtime.initrd),
+            ("tls certificate", &self.tls.certificate),
+            ("tls key", &self.tls.key),
+            ("tls client_ca", &self.tls.client_ca),
+            ("auth policy", &self.auth.policy),
+            ("registry auth_file", &self.registry.auth_file),
+        ] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    return Err(anyhow!("{} {:?} does not exist", name, path));
+                }
+            }
+        }
+
+        if self.tls.certificate.is_some() != self.tls.key.is_some() {
+            return Err(anyhow!(
+                "tls certificate and key must be specified together"
+            ));
+        }
+
+        // mount host paths are canonicalized before they are checked, so the
+        // allowed paths have to be as well or a symlink would never match.
+        for path in &mut self.mounts.allowed_paths {
+            if !path.is_absolute() {
+                return Err(anyhow!("mounts allowed path {:?} must be absolute", path));
+            }
+            *path = path.canonicalize().map_err(|error| {
+                anyhow!(
+                    "mounts allowed path {:?} is not accessible: {}",
+                    path,
+                    error
+                )
+            })?;
+        }
+
+        self.network.validate()
+    }
+
+    /// Applies the settings that are safe to change while guests are running,
+    /// returning the names of any other settings that differ and were ignored.
+    pub fn reload(&mut self, updated: DaemonConfig) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        if self.listen != updated.listen || self.allow_insecure_tcp != updated.allow_insecure_tcp {
+            ignored.push("listen");
+        }
+        if self.reconciler != updated.reconciler {
+            ignored.push("reconciler");
+        }
+        if self.logs != updated.logs {
+            ignored.push("logs");
+        }
+        if self.runtime != updated.runtime {
+            ignored.push("runtime");
+        }
+        if self.tls != updated.tls {
+            ignored.push("tls");
+        }
+        if self.auth != updated.auth {
+            ignored.push("auth");
+        }
+        if self.mounts != updated.mounts {
+            ignored.push("mounts");
+        }
+        if self.network.ipv4.subnet != updated.network.ipv4.subnet
+            || self.network.ipv4.gateway != updated.network.ipv4.gateway
+            || self.network.ipv4.host != updated.network.ipv4.host
+        {
+            ignored.push("network.ipv4");
+        }
+        if self.network.ipv6.subnet != updated.network.ipv6.subnet
+            || self.network.ipv6.gateway != updated.network.ipv6.gateway
+        {
+            ignored.push("network.ipv6");
+        }
+
+        self.console = updated.console;
+        self.registry = updated.registry;
+        self.network.nameservers = updated.network.nameservers;
+        self.network.ipv4.reserved = updated.network.ipv4.reserved;
+        self.network.ipv6.reserved = updated.network.ipv6.reserved;
+        ignored
+    }
+}
+
+impl DaemonNetworkConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.ipv4.subnet.contains(self.ipv4.gateway) {
+            return Err(anyhow!(
+                "ipv4 gateway {} is not inside subnet {}",
+                self.ipv4.gateway,
+                self.ipv4.subnet
+            ));
+        }
+
+        if !self.ipv4.subnet.contains(self.ipv4.host) || self.ipv4.host == self.ipv4.gateway {
+            return Err(anyhow!(
+                "ipv4 host {} must be inside subnet {} and differ from the gateway",
+                self.ipv4.host,
+                self.ipv4.subnet
+            ));
+        }
+
+        if self.ipv4.subnet.prefix() > 30 {
+            return Err(anyhow!(
+                "ipv4 subnet {} is too small to hold guests",
+                self.ipv4.subnet
+            ));
+        }
+
+        if !self.ipv6.subnet.contains(self.ipv6.gateway) {
+            return Err(anyhow!(
+                "ipv6 gateway {} is not inside subnet {}",
+                self.ipv6.gateway,
+                self.ipv6.subnet
+            ));
+        }
+
+        if self.ipv6.subnet.prefix() > 126 {
+            return Err(anyhow!(
+                "ipv6 subnet {} is too small to hold guests",
+                self.ipv6.subnet
+            ));
+        }
+        Ok(())
+    }
+}
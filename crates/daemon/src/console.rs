@@ -1,9 +1,14 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::Result;
-use circular_buffer::CircularBuffer;
-use kratart::channel::ChannelService;
-use log::error;
+use kratart::{channel::ChannelService, Runtime};
+use log::{error, warn};
 use tokio::{
     sync::{
         mpsc::{error::TrySendError, Receiver, Sender},
@@ -11,10 +16,11 @@ use tokio::{
     },
     task::JoinHandle,
 };
+use uuid::Uuid;
 
-const CONSOLE_BUFFER_SIZE: usize = 1024 * 1024;
-type RawConsoleBuffer = CircularBuffer<CONSOLE_BUFFER_SIZE, u8>;
-type ConsoleBuffer = Box<RawConsoleBuffer>;
+use crate::logs::{DaemonLogStore, LOG_STREAM_CONSOLE};
+
+type ConsoleBuffer = VecDeque<u8>;
 
 type ListenerMap = Arc<Mutex<HashMap<u32, Vec<Sender<Vec<u8>>>>>>;
 type BufferMap = Arc<Mutex<HashMap<u32, ConsoleBuffer>>>;
@@ -23,6 +29,8 @@ type BufferMap = Arc<Mutex<HashMap<u32, ConsoleBuffer>>>;
 pub struct DaemonConsoleHandle {
     listeners: ListenerMap,
     buffers: BufferMap,
+    buffer_size: Arc<AtomicUsize>,
+    logs: DaemonLogStore,
     sender: Sender<(u32, Vec<u8>)>,
     task: Arc<JoinHandle<()>>,
 }
@@ -54,7 +62,10 @@ impl DaemonConsoleHandle {
         sender: Sender<Vec<u8>>,
     ) -> Result<DaemonConsoleAttachHandle> {
         let buffers = self.buffers.lock().await;
-        let buffer = buffers.get(&domid).map(|x| x.to_vec()).unwrap_or_default();
+        let buffer = buffers
+            .get(&domid)
+            .map(|x| x.iter().copied().collect())
+            .unwrap_or_default();
         drop(buffers);
         let mut listeners = self.listeners.lock().await;
         let senders = listeners.entry(domid).or_default();
@@ -66,6 +77,43 @@ impl DaemonConsoleHandle {
             domid,
         })
     }
+
+    /// Reads the persisted console log of a guest. When `domid` is provided the
+    /// sender is subscribed to further output without missing or repeating any.
+    pub async fn read_log(
+        &self,
+        uuid: Uuid,
+        domid: Option<u32>,
+        since: u64,
+        tail: u64,
+        sender: Sender<Vec<u8>>,
+    ) -> Result<(Vec<u8>, Option<DaemonConsoleAttachHandle>)> {
+        let buffers = self.buffers.lock().await;
+        let history = self
+            .logs
+            .read(uuid, LOG_STREAM_CONSOLE, since, tail)
+            .await?;
+        let Some(domid) = domid else {
+            return Ok((history, None));
+        };
+        let mut listeners = self.listeners.lock().await;
+        listeners.entry(domid).or_default().push(sender);
+        drop(listeners);
+        drop(buffers);
+        Ok((
+            history,
+            Some(DaemonConsoleAttachHandle {
+                initial: Vec::new(),
+                sender: self.sender.clone(),
+                listeners: self.listeners.clone(),
+                domid,
+            }),
+        ))
+    }
+
+    pub fn set_buffer_size(&self, size: usize) {
+        self.buffer_size.store(size, Ordering::Relaxed);
+    }
 }
 
 impl Drop for DaemonConsoleHandle {
@@ -79,13 +127,21 @@ impl Drop for DaemonConsoleHandle {
 pub struct DaemonConsole {
     listeners: ListenerMap,
     buffers: BufferMap,
+    buffer_size: Arc<AtomicUsize>,
+    logs: DaemonLogStore,
+    runtime: Runtime,
+    uuids: HashMap<u32, Uuid>,
     receiver: Receiver<(u32, Option<Vec<u8>>)>,
     sender: Sender<(u32, Vec<u8>)>,
     task: JoinHandle<()>,
 }
 
 impl DaemonConsole {
-    pub async fn new() -> Result<DaemonConsole> {
+    pub async fn new(
+        buffer_size: usize,
+        logs: DaemonLogStore,
+        runtime: Runtime,
+    ) -> Result<DaemonConsole> {
         let (service, sender, receiver) =
             ChannelService::new("krata-console".to_string(), Some(0)).await?;
         let task = service.launch().await?;
@@ -94,6 +150,10 @@ impl DaemonConsole {
         Ok(DaemonConsole {
             listeners,
             buffers,
+            buffer_size: Arc::new(AtomicUsize::new(buffer_size)),
+            logs,
+            runtime,
+            uuids: HashMap::new(),
             receiver,
             sender,
             task,
@@ -103,6 +163,8 @@ impl DaemonConsole {
     pub async fn launch(mut self) -> Result<DaemonConsoleHandle> {
         let listeners = self.listeners.clone();
         let buffers = self.buffers.clone();
+        let buffer_size = self.buffer_size.clone();
+        let logs = self.logs.clone();
         let sender = self.sender.clone();
         let task = tokio::task::spawn(async move {
             if let Err(error) = self.process().await {
@@ -112,6 +174,8 @@ impl DaemonConsole {
         Ok(DaemonConsoleHandle {
             listeners,
             buffers,
+            buffer_size,
+            logs,
             sender,
             task: Arc::new(task),
         })
@@ -123,13 +187,36 @@ impl DaemonConsole {
                 break;
             };
 
+            let uuid = match self.uuids.get(&domid) {
+                Some(uuid) => Some(*uuid),
+                None if data.is_some() => match self.runtime.resolve_domid(domid).await {
+                    Ok(Some(uuid)) => {
+                        self.uuids.insert(domid, uuid);
+                        Some(uuid)
+                    }
+                    Ok(None) => None,
+                    Err(error) => {
+                        warn!("failed to resolve guest for domain {}: {}", domid, error);
+                        None
+                    }
+                },
+                None => None,
+            };
+
             let mut buffers = self.buffers.lock().await;
             if let Some(data) = data {
-                let buffer = buffers
-                    .entry(domid)
-                    .or_insert_with_key(|_| RawConsoleBuffer::boxed());
-                buffer.extend_from_slice(&data);
-                drop(buffers);
+                if let Some(uuid) = uuid {
+                    if let Err(error) = self.logs.append(uuid, LOG_STREAM_CONSOLE, &data).await {
+                        warn!("failed to write console log for guest {}: {}", uuid, error);
+                    }
+                }
+                let buffer_size = self.buffer_size.load(Ordering::Relaxed);
+                let buffer = buffers.entry(domid).or_default();
+                buffer.extend(data.iter().copied());
+                if buffer.len() > buffer_size {
+                    let excess = buffer.len() - buffer_size;
+                    buffer.drain(0..excess);
+                }
                 let mut listeners = self.listeners.lock().await;
                 if let Some(senders) = listeners.get_mut(&domid) {
                     senders.retain(|sender| {
@@ -137,6 +224,9 @@ impl DaemonConsole {
                     });
                 }
             } else {
+                if let Some(uuid) = self.uuids.remove(&domid) {
+                    self.logs.close(uuid).await;
+                }
                 buffers.remove(&domid);
                 let mut listeners = self.listeners.lock().await;
                 listeners.remove(&domid);
@@ -1,35 +1,74 @@
-use std::{pin::Pin, str::FromStr};
+use std::{
+    pin::Pin,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use async_stream::try_stream;
 use futures::Stream;
+use ipnetwork::IpNetwork;
 use krata::{
     idm::protocol::{
+        idm_event::Event as IdmEventType, idm_exec_request::Request as IdmExecRequestType,
         idm_request::Request as IdmRequestType, idm_response::Response as IdmResponseType,
-        IdmMetricsRequest,
+        IdmEvent, IdmExecEnvVar, IdmExecRequest, IdmExecStartRequest, IdmExecStdinRequest,
+        IdmLogStream, IdmMetricsRequest, IdmShutdownRequest,
     },
     v1::{
-        common::{Guest, GuestState, GuestStatus},
+        common::{
+            Guest, GuestLogEntry, GuestLogStream, GuestNetworkPolicy, GuestSpec, GuestState,
+            GuestStatus, GuestStopInfo,
+        },
         control::{
             control_service_server::ControlService, ConsoleDataReply, ConsoleDataRequest,
-            CreateGuestReply, CreateGuestRequest, DestroyGuestReply, DestroyGuestRequest,
-            ListGuestsReply, ListGuestsRequest, ReadGuestMetricsReply, ReadGuestMetricsRequest,
-            ResolveGuestReply, ResolveGuestRequest, WatchEventsReply, WatchEventsRequest,
+            CreateGuestGroupReply, CreateGuestGroupRequest, CreateGuestReply, CreateGuestRequest,
+            CreateVolumeReply, CreateVolumeRequest, DestroyGuestGroupReply,
+            DestroyGuestGroupRequest, DestroyGuestReply, DestroyGuestRequest, DestroyVolumeReply,
+            DestroyVolumeRequest, ExecGuestReply, ExecGuestRequest, GetHostNetworkReply,
+            GetHostNetworkRequest, ImportImageReply, ImportImageRequest, ListGuestsReply,
+            ListGuestsRequest, ListImagesReply, ListImagesRequest, ListVolumesReply,
+            ListVolumesRequest, PauseGuestReply, PauseGuestRequest, PruneImagesReply,
+            PruneImagesRequest, PullImageReply, PullImageRequest, ReadGuestConsoleLogReply,
+            ReadGuestConsoleLogRequest, ReadGuestLogsReply, ReadGuestLogsRequest,
+            ReadGuestMetricsReply, ReadGuestMetricsRequest, RemoveImageReply, RemoveImageRequest,
+            ResolveGuestReply, ResolveGuestRequest, ResumeGuestReply, ResumeGuestRequest,
+            UpdateGuestNetworkPolicyReply, UpdateGuestNetworkPolicyRequest,
+            UpdateGuestResourcesReply, UpdateGuestResourcesRequest, WatchEventsReply,
+            WatchEventsRequest,
         },
     },
 };
+use krataoci::auth::OciRegistryAuth;
+use kratart::Runtime;
+use log::warn;
 use tokio::{
     select,
-    sync::mpsc::{channel, Sender},
+    sync::{
+        broadcast,
+        mpsc::{channel, Sender},
+    },
 };
 use tokio_stream::StreamExt;
 use tonic::{Request, Response, Status, Streaming};
 use uuid::Uuid;
 
 use crate::{
-    console::DaemonConsoleHandle, db::GuestStore, event::DaemonEventContext, idm::DaemonIdmHandle,
+    auth::{DaemonCaller, DaemonRole},
+    console::DaemonConsoleHandle,
+    db::GuestStore,
+    event::DaemonEventContext,
+    idm::DaemonIdmHandle,
+    image::{image_progress_to_api, DaemonImages},
+    logs::{DaemonLogStore, LOG_STREAM_STDERR, LOG_STREAM_STDOUT},
     metrics::idm_metric_to_api,
+    network::DaemonNetworkAssignment,
+    registry::{registry_auth_from_proto, DaemonRegistryCredentials},
+    volume::DaemonVolumes,
 };
 
+const LOG_ENTRIES_PER_REPLY: usize = 100;
+const DEFAULT_STOP_TIMEOUT_SECONDS: u64 = 10;
+
 pub struct ApiError {
     message: String,
 }
@@ -54,24 +93,244 @@ pub struct RuntimeControlService {
     console: DaemonConsoleHandle,
     idm: DaemonIdmHandle,
     guests: GuestStore,
+    volumes: DaemonVolumes,
     guest_reconciler_notify: Sender<Uuid>,
+    logs: DaemonLogStore,
+    runtime: Runtime,
+    registry: DaemonRegistryCredentials,
+    network: DaemonNetworkAssignment,
+    images: DaemonImages,
 }
 
 impl RuntimeControlService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         events: DaemonEventContext,
         console: DaemonConsoleHandle,
         idm: DaemonIdmHandle,
         guests: GuestStore,
+        volumes: DaemonVolumes,
         guest_reconciler_notify: Sender<Uuid>,
+        logs: DaemonLogStore,
+        runtime: Runtime,
+        registry: DaemonRegistryCredentials,
+        network: DaemonNetworkAssignment,
+        images: DaemonImages,
     ) -> Self {
         Self {
             events,
             console,
             idm,
             guests,
+            volumes,
             guest_reconciler_notify,
+            logs,
+            runtime,
+            registry,
+            network,
+            images,
+        }
+    }
+
+    async fn create_guest_from_spec(
+        &self,
+        spec: GuestSpec,
+        auth: OciRegistryAuth,
+    ) -> Result<Uuid, ApiError> {
+        let uuid = self.store_guest_from_spec(spec, auth).await?;
+        self.notify_guest_reconciler(uuid).await?;
+        Ok(uuid)
+    }
+
+    /// Records a new guest without waking the reconciler.
+    async fn store_guest_from_spec(
+        &self,
+        spec: GuestSpec,
+        auth: OciRegistryAuth,
+    ) -> Result<Uuid, ApiError> {
+        if let Some(ref policy) = spec.network_policy {
+            validate_network_policy(policy)?;
+        }
+        if spec.max_vcpus != 0 && spec.max_vcpus < spec.vcpus {
+            return Err(ApiError {
+                message: "maximum vcpus must not be less than the guest vcpus".to_string(),
+            });
+        }
+        if spec.max_mem != 0 && spec.max_mem < spec.mem {
+            return Err(ApiError {
+                message: "maximum memory must not be less than the guest memory".to_string(),
+            });
         }
+        let uuid = Uuid::new_v4();
+        self.registry.set_guest_auth(uuid, auth).await;
+        self.guests
+            .update(
+                uuid,
+                Guest {
+                    id: uuid.to_string(),
+                    state: Some(GuestState {
+                        status: GuestStatus::Starting.into(),
+                        network: None,
+                        exit_info: None,
+                        error_info: None,
+                        domid: u32::MAX,
+                        restart_info: None,
+                        health_info: None,
+                        stop_info: None,
+                        oom_kills: 0,
+                        resources: None,
+                        image_digest: String::new(),
+                        domain_paused: false,
+                    }),
+                    spec: Some(spec),
+                },
+            )
+            .await?;
+        Ok(uuid)
+    }
+
+    async fn notify_guest_reconciler(&self, uuid: Uuid) -> Result<(), ApiError> {
+        self.guest_reconciler_notify
+            .send(uuid)
+            .await
+            .map_err(|x| ApiError {
+                message: x.to_string(),
+            })
+    }
+
+    async fn destroy_guest_by_uuid(
+        &self,
+        uuid: Uuid,
+        timeout_seconds: u64,
+        force: bool,
+    ) -> Result<(), ApiError> {
+        let Some(mut guest) = self.guests.read(uuid).await? else {
+            return Err(ApiError {
+                message: "guest not found".to_string(),
+            });
+        };
+
+        guest.state = Some(guest.state.as_mut().cloned().unwrap_or_default());
+
+        if guest.state.as_ref().unwrap().status() == GuestStatus::Destroyed {
+            return Err(ApiError {
+                message: "guest already destroyed".to_string(),
+            });
+        }
+
+        let status = guest.state.as_ref().unwrap().status();
+        if status == GuestStatus::Stopping && !force {
+            return Ok(());
+        }
+
+        let graceful = status == GuestStatus::Started && !force;
+        if graceful {
+            let timeout_seconds = if timeout_seconds == 0 {
+                DEFAULT_STOP_TIMEOUT_SECONDS
+            } else {
+                timeout_seconds
+            };
+            let deadline = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|error| ApiError {
+                    message: error.to_string(),
+                })?
+                .saturating_add(Duration::from_secs(timeout_seconds))
+                .as_millis() as u64;
+            let state = guest.state.as_mut().unwrap();
+            state.status = GuestStatus::Stopping.into();
+            state.stop_info = Some(GuestStopInfo { deadline });
+        } else {
+            guest.state.as_mut().unwrap().status = GuestStatus::Destroying.into();
+        }
+        let domid = guest.state.as_ref().unwrap().domid;
+        self.guests.update(uuid, guest).await?;
+
+        if graceful {
+            if let Err(error) = self.request_shutdown(domid).await {
+                warn!(
+                    "failed to request shutdown of guest {}, destroying: {}",
+                    uuid, error
+                );
+                if let Some(mut guest) = self.guests.read(uuid).await? {
+                    if let Some(ref mut state) = guest.state {
+                        if state.status() == GuestStatus::Stopping {
+                            state.set_status(GuestStatus::Destroying);
+                            self.guests.update(uuid, guest).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.guest_reconciler_notify
+            .send(uuid)
+            .await
+            .map_err(|x| ApiError {
+                message: x.to_string(),
+            })?;
+        Ok(())
+    }
+
+    async fn guest_group_members(&self, group: &str) -> Result<Vec<Uuid>, ApiError> {
+        Ok(self
+            .guests
+            .list()
+            .await?
+            .into_iter()
+            .filter(|(_, guest)| {
+                guest
+                    .spec
+                    .as_ref()
+                    .map(|spec| spec.group == group)
+                    .unwrap_or(false)
+            })
+            .map(|(uuid, _)| uuid)
+            .collect())
+    }
+
+    async fn transition_guest(
+        &self,
+        guest_id: &str,
+        from: GuestStatus,
+        to: GuestStatus,
+        error: &str,
+    ) -> Result<(), ApiError> {
+        let uuid = Uuid::from_str(guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        let Some(mut guest) = self.guests.read(uuid).await? else {
+            return Err(ApiError {
+                message: "guest not found".to_string(),
+            });
+        };
+        let Some(ref mut state) = guest.state else {
+            return Err(ApiError {
+                message: "guest did not have state".to_string(),
+            });
+        };
+        if state.status() != from {
+            return Err(ApiError {
+                message: error.to_string(),
+            });
+        }
+        state.set_status(to);
+        self.guests.update(uuid, guest).await?;
+        self.guest_reconciler_notify
+            .send(uuid)
+            .await
+            .map_err(|x| ApiError {
+                message: x.to_string(),
+            })?;
+        Ok(())
+    }
+
+    async fn request_shutdown(&self, domid: u32) -> anyhow::Result<()> {
+        let client = self.idm.client(domid).await?;
+        client
+            .send(IdmRequestType::Shutdown(IdmShutdownRequest {}))
+            .await?;
+        Ok(())
     }
 }
 
@@ -80,18 +339,36 @@ enum ConsoleDataSelect {
     Write(Option<Result<ConsoleDataRequest, tonic::Status>>),
 }
 
+enum ExecGuestSelect {
+    Event(Result<IdmEvent, broadcast::error::RecvError>),
+    Input(Option<Result<ExecGuestRequest, tonic::Status>>),
+}
+
 #[tonic::async_trait]
 impl ControlService for RuntimeControlService {
     type ConsoleDataStream =
         Pin<Box<dyn Stream<Item = Result<ConsoleDataReply, Status>> + Send + 'static>>;
 
+    type ReadGuestConsoleLogStream =
+        Pin<Box<dyn Stream<Item = Result<ReadGuestConsoleLogReply, Status>> + Send + 'static>>;
+
+    type ReadGuestLogsStream =
+        Pin<Box<dyn Stream<Item = Result<ReadGuestLogsReply, Status>> + Send + 'static>>;
+
     type WatchEventsStream =
         Pin<Box<dyn Stream<Item = Result<WatchEventsReply, Status>> + Send + 'static>>;
 
+    type ExecGuestStream =
+        Pin<Box<dyn Stream<Item = Result<ExecGuestReply, Status>> + Send + 'static>>;
+
+    type PullImageStream =
+        Pin<Box<dyn Stream<Item = Result<PullImageReply, Status>> + Send + 'static>>;
+
     async fn create_guest(
         &self,
         request: Request<CreateGuestRequest>,
     ) -> Result<Response<CreateGuestReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
         let request = request.into_inner();
         let Some(spec) = request.spec else {
             return Err(ApiError {
@@ -99,22 +376,224 @@ impl ControlService for RuntimeControlService {
             }
             .into());
         };
-        let uuid = Uuid::new_v4();
+        // groups are created as a whole by CreateGuestGroup, guests cannot join one later
+        if !spec.group.is_empty() && !self.guest_group_members(&spec.group).await?.is_empty() {
+            return Err(ApiError {
+                message: format!("guest group {} already exists", spec.group),
+            }
+            .into());
+        }
+        let auth = registry_auth_from_proto(request.registry_auth.as_ref());
+        let uuid = self.create_guest_from_spec(spec, auth).await?;
+        Ok(Response::new(CreateGuestReply {
+            guest_id: uuid.to_string(),
+        }))
+    }
+
+    async fn destroy_guest(
+        &self,
+        request: Request<DestroyGuestRequest>,
+    ) -> Result<Response<DestroyGuestReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        self.destroy_guest_by_uuid(uuid, request.timeout_seconds, request.force)
+            .await?;
+        Ok(Response::new(DestroyGuestReply {}))
+    }
+
+    async fn create_guest_group(
+        &self,
+        request: Request<CreateGuestGroupRequest>,
+    ) -> Result<Response<CreateGuestGroupReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        if request.name.is_empty() {
+            return Err(ApiError {
+                message: "guest group name not provided".to_string(),
+            }
+            .into());
+        }
+        if request.guests.is_empty() {
+            return Err(ApiError {
+                message: "guest group has no guests".to_string(),
+            }
+            .into());
+        }
+        if !self.guest_group_members(&request.name).await?.is_empty() {
+            return Err(ApiError {
+                message: format!("guest group {} already exists", request.name),
+            }
+            .into());
+        }
+
+        let mut names = Vec::new();
+        for spec in &request.guests {
+            if spec.name.is_empty() {
+                return Err(ApiError {
+                    message: "guests in a group must have a name".to_string(),
+                }
+                .into());
+            }
+            if names.contains(&spec.name) {
+                return Err(ApiError {
+                    message: format!("guest name {} is used more than once", spec.name),
+                }
+                .into());
+            }
+            names.push(spec.name.clone());
+        }
+
+        // every member is recorded before any is started, so the network service
+        // never sees a partial group
+        let auth = registry_auth_from_proto(request.registry_auth.as_ref());
+        let mut uuids = Vec::new();
+        for mut spec in request.guests {
+            spec.group = request.name.clone();
+            match self.store_guest_from_spec(spec, auth.clone()).await {
+                Ok(uuid) => uuids.push(uuid),
+                Err(error) => {
+                    for uuid in uuids {
+                        if let Err(error) = self.guests.remove(uuid).await {
+                            warn!("failed to remove guest {} from group: {}", uuid, error);
+                        }
+                        self.registry.release(uuid).await;
+                    }
+                    return Err(error.into());
+                }
+            }
+        }
+        for uuid in &uuids {
+            self.notify_guest_reconciler(*uuid).await?;
+        }
+        let guest_ids = uuids.iter().map(|x| x.to_string()).collect();
+        Ok(Response::new(CreateGuestGroupReply { guest_ids }))
+    }
+
+    async fn destroy_guest_group(
+        &self,
+        request: Request<DestroyGuestGroupRequest>,
+    ) -> Result<Response<DestroyGuestGroupReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        if request.name.is_empty() {
+            return Err(ApiError {
+                message: "guest group name not provided".to_string(),
+            }
+            .into());
+        }
+        let members = self.guest_group_members(&request.name).await?;
+        if members.is_empty() {
+            return Err(ApiError {
+                message: format!("guest group {} not found", request.name),
+            }
+            .into());
+        }
+
+        let mut guest_ids = Vec::new();
+        for uuid in members {
+            if let Err(error) = self
+                .destroy_guest_by_uuid(uuid, request.timeout_seconds, request.force)
+                .await
+            {
+                warn!(
+                    "failed to destroy guest {} in group: {}",
+                    uuid, error.message
+                );
+                continue;
+            }
+            guest_ids.push(uuid.to_string());
+        }
+        Ok(Response::new(DestroyGuestGroupReply { guest_ids }))
+    }
+
+    async fn pause_guest(
+        &self,
+        request: Request<PauseGuestRequest>,
+    ) -> Result<Response<PauseGuestReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        self.transition_guest(
+            &request.guest_id,
+            GuestStatus::Started,
+            GuestStatus::Paused,
+            "only started guests can be paused",
+        )
+        .await?;
+        Ok(Response::new(PauseGuestReply {}))
+    }
+
+    async fn resume_guest(
+        &self,
+        request: Request<ResumeGuestRequest>,
+    ) -> Result<Response<ResumeGuestReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        self.transition_guest(
+            &request.guest_id,
+            GuestStatus::Paused,
+            GuestStatus::Started,
+            "only paused guests can be resumed",
+        )
+        .await?;
+        Ok(Response::new(ResumeGuestReply {}))
+    }
+
+    async fn update_guest_resources(
+        &self,
+        request: Request<UpdateGuestResourcesRequest>,
+    ) -> Result<Response<UpdateGuestResourcesReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        let Some(mut guest) = self.guests.read(uuid).await.map_err(ApiError::from)? else {
+            return Err(ApiError {
+                message: "guest not found".to_string(),
+            }
+            .into());
+        };
+        let Some(ref mut state) = guest.state else {
+            return Err(ApiError {
+                message: "guest did not have state".to_string(),
+            }
+            .into());
+        };
+        if !matches!(state.status(), GuestStatus::Started | GuestStatus::Paused) {
+            return Err(ApiError {
+                message: "only running guests can have their resources updated".to_string(),
+            }
+            .into());
+        }
+
+        let vcpus = if request.vcpus == 0 {
+            None
+        } else {
+            Some(request.vcpus)
+        };
+        let mem = if request.mem == 0 {
+            None
+        } else {
+            Some(request.mem)
+        };
+        self.runtime
+            .update_resources(uuid, vcpus, mem)
+            .await
+            .map_err(ApiError::from)?;
+
+        let mut resources = state.resources.clone().unwrap_or_default();
+        if let Some(vcpus) = vcpus {
+            resources.vcpus = vcpus;
+        }
+        if let Some(mem) = mem {
+            resources.mem = mem;
+        }
+        state.resources = Some(resources.clone());
         self.guests
-            .update(
-                uuid,
-                Guest {
-                    id: uuid.to_string(),
-                    state: Some(GuestState {
-                        status: GuestStatus::Starting.into(),
-                        network: None,
-                        exit_info: None,
-                        error_info: None,
-                        domid: u32::MAX,
-                    }),
-                    spec: Some(spec),
-                },
-            )
+            .update(uuid, guest)
             .await
             .map_err(ApiError::from)?;
         self.guest_reconciler_notify
@@ -123,36 +602,36 @@ impl ControlService for RuntimeControlService {
             .map_err(|x| ApiError {
                 message: x.to_string(),
             })?;
-        Ok(Response::new(CreateGuestReply {
-            guest_id: uuid.to_string(),
+        Ok(Response::new(UpdateGuestResourcesReply {
+            resources: Some(resources),
         }))
     }
 
-    async fn destroy_guest(
+    async fn update_guest_network_policy(
         &self,
-        request: Request<DestroyGuestRequest>,
-    ) -> Result<Response<DestroyGuestReply>, Status> {
+        request: Request<UpdateGuestNetworkPolicyRequest>,
+    ) -> Result<Response<UpdateGuestNetworkPolicyReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
         let request = request.into_inner();
         let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
             message: error.to_string(),
         })?;
+        if let Some(ref policy) = request.policy {
+            validate_network_policy(policy)?;
+        }
         let Some(mut guest) = self.guests.read(uuid).await.map_err(ApiError::from)? else {
             return Err(ApiError {
                 message: "guest not found".to_string(),
             }
             .into());
         };
-
-        guest.state = Some(guest.state.as_mut().cloned().unwrap_or_default());
-
-        if guest.state.as_ref().unwrap().status() == GuestStatus::Destroyed {
+        let Some(ref mut spec) = guest.spec else {
             return Err(ApiError {
-                message: "guest already destroyed".to_string(),
+                message: "guest did not have a spec".to_string(),
             }
             .into());
-        }
-
-        guest.state.as_mut().unwrap().status = GuestStatus::Destroying.into();
+        };
+        spec.network_policy = request.policy;
         self.guests
             .update(uuid, guest)
             .await
@@ -163,13 +642,14 @@ impl ControlService for RuntimeControlService {
             .map_err(|x| ApiError {
                 message: x.to_string(),
             })?;
-        Ok(Response::new(DestroyGuestReply {}))
+        Ok(Response::new(UpdateGuestNetworkPolicyReply {}))
     }
 
     async fn list_guests(
         &self,
         request: Request<ListGuestsRequest>,
     ) -> Result<Response<ListGuestsReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
         let _ = request.into_inner();
         let guests = self.guests.list().await.map_err(ApiError::from)?;
         let guests = guests.into_values().collect::<Vec<Guest>>();
@@ -180,6 +660,7 @@ impl ControlService for RuntimeControlService {
         &self,
         request: Request<ResolveGuestRequest>,
     ) -> Result<Response<ResolveGuestReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
         let request = request.into_inner();
         let guests = self.guests.list().await.map_err(ApiError::from)?;
         let guests = guests
@@ -199,6 +680,7 @@ impl ControlService for RuntimeControlService {
         &self,
         request: Request<Streaming<ConsoleDataRequest>>,
     ) -> Result<Response<Self::ConsoleDataStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
         let mut input = request.into_inner();
         let Some(request) = input.next().await else {
             return Err(ApiError {
@@ -281,10 +763,171 @@ impl ControlService for RuntimeControlService {
         Ok(Response::new(Box::pin(output) as Self::ConsoleDataStream))
     }
 
+    async fn read_guest_console_log(
+        &self,
+        request: Request<ReadGuestConsoleLogRequest>,
+    ) -> Result<Response<Self::ReadGuestConsoleLogStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
+        let request = request.into_inner();
+        let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+
+        let domid = if request.follow {
+            self.guests
+                .read(uuid)
+                .await
+                .map_err(ApiError::from)?
+                .and_then(|guest| guest.state)
+                .filter(|state| state.status() == GuestStatus::Started)
+                .map(|state| state.domid)
+                .filter(|domid| *domid != 0 && *domid != u32::MAX)
+        } else {
+            None
+        };
+
+        let (sender, mut receiver) = channel(100);
+        let (history, console) = self
+            .console
+            .read_log(uuid, domid, request.since, request.tail, sender)
+            .await
+            .map_err(|error| ApiError {
+                message: format!("failed to read console log: {}", error),
+            })?;
+
+        let output = try_stream! {
+            if !history.is_empty() {
+                yield ReadGuestConsoleLogReply { data: history };
+            }
+
+            if let Some(console) = console {
+                while let Some(data) = receiver.recv().await {
+                    yield ReadGuestConsoleLogReply { data };
+                }
+                let _ = console.unsubscribe().await;
+            }
+        };
+
+        Ok(Response::new(
+            Box::pin(output) as Self::ReadGuestConsoleLogStream
+        ))
+    }
+
+    async fn read_guest_logs(
+        &self,
+        request: Request<ReadGuestLogsRequest>,
+    ) -> Result<Response<Self::ReadGuestLogsStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
+        let request = request.into_inner();
+        let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        let mut streams = request.streams().collect::<Vec<_>>();
+        if streams.is_empty() {
+            streams = vec![GuestLogStream::Stdout, GuestLogStream::Stderr];
+        }
+        let until = if request.until == 0 {
+            u64::MAX
+        } else {
+            request.until
+        };
+
+        let domid = if request.follow && request.until == 0 {
+            self.guests
+                .read(uuid)
+                .await
+                .map_err(ApiError::from)?
+                .and_then(|guest| guest.state)
+                .filter(|state| state.status() == GuestStatus::Started)
+                .map(|state| state.domid)
+                .filter(|domid| *domid != 0 && *domid != u32::MAX)
+        } else {
+            None
+        };
+
+        let mut subscription = match domid {
+            Some(domid) => {
+                let client = self.idm.client(domid).await.map_err(ApiError::from)?;
+                Some(client.subscribe().await.map_err(ApiError::from)?)
+            }
+            None => None,
+        };
+
+        let mut entries = Vec::new();
+        let mut sequences = Vec::new();
+        for stream in &streams {
+            let name = match stream {
+                GuestLogStream::Stdout => LOG_STREAM_STDOUT,
+                GuestLogStream::Stderr => LOG_STREAM_STDERR,
+            };
+            let (records, sequence) = self
+                .logs
+                .read_records_sequenced(uuid, name, request.since, until)
+                .await
+                .map_err(ApiError::from)?;
+            sequences.push((*stream, sequence));
+            entries.extend(records.into_iter().map(|(timestamp, data)| GuestLogEntry {
+                stream: (*stream).into(),
+                timestamp,
+                data,
+            }));
+        }
+        entries.sort_by_key(|entry| entry.timestamp);
+        if request.tail > 0 && entries.len() as u64 > request.tail {
+            entries.drain(..entries.len() - request.tail as usize);
+        }
+
+        let output = try_stream! {
+            for chunk in entries.chunks(LOG_ENTRIES_PER_REPLY) {
+                yield ReadGuestLogsReply { entries: chunk.to_vec() };
+            }
+
+            if let Some(ref mut subscription) = subscription {
+                loop {
+                    let event = match subscription.recv().await {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+
+                    let log = match event.event {
+                        Some(IdmEventType::Log(log)) => log,
+                        Some(IdmEventType::Exit(_)) => break,
+                        _ => continue,
+                    };
+
+                    let stream = match log.stream() {
+                        IdmLogStream::Stdout => GuestLogStream::Stdout,
+                        IdmLogStream::Stderr => GuestLogStream::Stderr,
+                    };
+                    // records up to the snapshot sequence were already sent from the store
+                    let sent = log.sequence != 0
+                        && sequences
+                            .iter()
+                            .any(|(x, sequence)| *x == stream && log.sequence <= *sequence);
+                    if !streams.contains(&stream) || log.timestamp < request.since || sent {
+                        continue;
+                    }
+
+                    yield ReadGuestLogsReply {
+                        entries: vec![GuestLogEntry {
+                            stream: stream.into(),
+                            timestamp: log.timestamp,
+                            data: log.data,
+                        }],
+                    };
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::ReadGuestLogsStream))
+    }
+
     async fn read_guest_metrics(
         &self,
         request: Request<ReadGuestMetricsRequest>,
     ) -> Result<Response<ReadGuestMetricsReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
         let request = request.into_inner();
         let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
             message: error.to_string(),
@@ -333,10 +976,367 @@ impl ControlService for RuntimeControlService {
         Ok(Response::new(reply))
     }
 
+    async fn exec_guest(
+        &self,
+        request: Request<Streaming<ExecGuestRequest>>,
+    ) -> Result<Response<Self::ExecGuestStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let mut input = request.into_inner();
+        let Some(request) = input.next().await else {
+            return Err(ApiError {
+                message: "expected to have at least one request".to_string(),
+            }
+            .into());
+        };
+        let request = request?;
+        let Some(task) = request.task else {
+            return Err(ApiError {
+                message: "task is missing".to_string(),
+            }
+            .into());
+        };
+        let uuid = Uuid::from_str(&request.guest_id).map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        let guest = self
+            .guests
+            .read(uuid)
+            .await
+            .map_err(|error| ApiError {
+                message: error.to_string(),
+            })?
+            .ok_or_else(|| ApiError {
+                message: "guest did not exist in the database".to_string(),
+            })?;
+
+        let Some(ref state) = guest.state else {
+            return Err(ApiError {
+                message: "guest did not have state".to_string(),
+            }
+            .into());
+        };
+
+        let domid = state.domid;
+        if domid == 0 || domid == u32::MAX {
+            return Err(ApiError {
+                message: "invalid domid on the guest".to_string(),
+            }
+            .into());
+        }
+
+        let client = self.idm.client(domid).await.map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+        let mut events = client.subscribe().await.map_err(|error| ApiError {
+            message: error.to_string(),
+        })?;
+
+        let response = client
+            .send(IdmRequestType::Exec(IdmExecRequest {
+                request: Some(IdmExecRequestType::Start(IdmExecStartRequest {
+                    environment: task
+                        .environment
+                        .into_iter()
+                        .map(|x| IdmExecEnvVar {
+                            key: x.key,
+                            value: x.value,
+                        })
+                        .collect(),
+                    command: task.command,
+                    working_directory: request.working_directory,
+                    tty: request.tty || task.tty,
+                })),
+            }))
+            .await
+            .map_err(|error| ApiError {
+                message: error.to_string(),
+            })?;
+
+        let IdmResponseType::Exec(response) = response else {
+            return Err(ApiError {
+                message: "guest returned an unexpected response to exec".to_string(),
+            }
+            .into());
+        };
+
+        if !response.error.is_empty() {
+            return Err(ApiError {
+                message: response.error,
+            }
+            .into());
+        }
+
+        let exec_id = response.exec_id;
+        let mut input_open = true;
+        let output = try_stream! {
+            loop {
+                let what = if input_open {
+                    select! {
+                        x = events.recv() => ExecGuestSelect::Event(x),
+                        x = input.next() => ExecGuestSelect::Input(x),
+                    }
+                } else {
+                    ExecGuestSelect::Event(events.recv().await)
+                };
+
+                match what {
+                    ExecGuestSelect::Event(Ok(event)) => match event.event {
+                        Some(IdmEventType::ExecOutput(output)) if output.exec_id == exec_id => {
+                            yield ExecGuestReply {
+                                exited: false,
+                                error: String::new(),
+                                exit_code: 0,
+                                stdout: output.stdout,
+                                stderr: output.stderr,
+                            };
+                        },
+
+                        Some(IdmEventType::ExecExit(exit)) if exit.exec_id == exec_id => {
+                            yield ExecGuestReply {
+                                exited: true,
+                                error: exit.error,
+                                exit_code: exit.code,
+                                stdout: vec![],
+                                stderr: vec![],
+                            };
+                            break;
+                        },
+
+                        _ => {}
+                    },
+
+                    ExecGuestSelect::Event(Err(broadcast::error::RecvError::Lagged(_))) => {
+                        continue;
+                    },
+
+                    ExecGuestSelect::Event(Err(broadcast::error::RecvError::Closed)) => {
+                        Err(ApiError {
+                            message: "guest idm channel closed".to_string(),
+                        })?;
+                    },
+
+                    ExecGuestSelect::Input(Some(request)) => {
+                        let request = request?;
+                        if !request.data.is_empty() || request.stdin_closed {
+                            client
+                                .send(IdmRequestType::Exec(IdmExecRequest {
+                                    request: Some(IdmExecRequestType::Stdin(IdmExecStdinRequest {
+                                        exec_id,
+                                        data: request.data,
+                                        closed: request.stdin_closed,
+                                    })),
+                                }))
+                                .await
+                                .map_err(|error| ApiError {
+                                    message: error.to_string(),
+                                })?;
+                        }
+                    },
+
+                    ExecGuestSelect::Input(None) => {
+                        input_open = false;
+                    }
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::ExecGuestStream))
+    }
+
+    async fn create_volume(
+        &self,
+        request: Request<CreateVolumeRequest>,
+    ) -> Result<Response<CreateVolumeReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Admin)?;
+        let request = request.into_inner();
+        let Some(spec) = request.spec else {
+            return Err(ApiError {
+                message: "volume spec not provided".to_string(),
+            }
+            .into());
+        };
+        let uuid = self.volumes.create(spec).await.map_err(ApiError::from)?;
+        Ok(Response::new(CreateVolumeReply {
+            volume_id: uuid.to_string(),
+        }))
+    }
+
+    async fn destroy_volume(
+        &self,
+        request: Request<DestroyVolumeRequest>,
+    ) -> Result<Response<DestroyVolumeReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Admin)?;
+        let request = request.into_inner();
+        let Some((uuid, volume)) = self
+            .volumes
+            .resolve(&request.volume_id)
+            .await
+            .map_err(ApiError::from)?
+        else {
+            return Err(ApiError {
+                message: "volume not found".to_string(),
+            }
+            .into());
+        };
+
+        let name = volume.spec.map(|spec| spec.name).unwrap_or_default();
+        let guests = self.guests.list().await.map_err(ApiError::from)?;
+        for guest in guests.into_values() {
+            let status = guest.state.as_ref().map(|x| x.status()).unwrap_or_default();
+            if status == GuestStatus::Destroyed {
+                continue;
+            }
+            let Some(ref spec) = guest.spec else {
+                continue;
+            };
+            if spec
+                .volumes
+                .iter()
+                .any(|x| x.volume == volume.id || (!name.is_empty() && x.volume == name))
+            {
+                return Err(ApiError {
+                    message: format!("volume is in use by guest {}", guest.id),
+                }
+                .into());
+            }
+        }
+
+        self.volumes.destroy(uuid).await.map_err(ApiError::from)?;
+        Ok(Response::new(DestroyVolumeReply {}))
+    }
+
+    async fn get_host_network(
+        &self,
+        request: Request<GetHostNetworkRequest>,
+    ) -> Result<Response<GetHostNetworkReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
+        let _ = request.into_inner();
+        let host_ipv4 = self.network.host_ipv4().await.map_err(ApiError::from)?;
+        Ok(Response::new(GetHostNetworkReply {
+            host_ipv4: host_ipv4.to_string(),
+        }))
+    }
+
+    async fn list_volumes(
+        &self,
+        request: Request<ListVolumesRequest>,
+    ) -> Result<Response<ListVolumesReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
+        let _ = request.into_inner();
+        let volumes = self.volumes.list().await.map_err(ApiError::from)?;
+        Ok(Response::new(ListVolumesReply { volumes }))
+    }
+
+    async fn list_images(
+        &self,
+        request: Request<ListImagesRequest>,
+    ) -> Result<Response<ListImagesReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
+        let images = self.images.list().await.map_err(ApiError::from)?;
+        Ok(Response::new(ListImagesReply { images }))
+    }
+
+    async fn pull_image(
+        &self,
+        request: Request<PullImageRequest>,
+    ) -> Result<Response<Self::PullImageStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        if request.image.is_empty() {
+            return Err(ApiError {
+                message: "image must be specified".to_string(),
+            }
+            .into());
+        }
+        let auth = registry_auth_from_proto(request.registry_auth.as_ref());
+        let (mut progress, mut task) = self
+            .images
+            .pull(request.image, auth)
+            .await
+            .map_err(ApiError::from)?;
+        let output = try_stream! {
+            let result = loop {
+                let finished = select! {
+                    result = &mut task => Some(result),
+                    changed = progress.changed() => match changed {
+                        Ok(_) => None,
+                        Err(_) => Some((&mut task).await),
+                    },
+                };
+                if let Some(result) = finished {
+                    break result;
+                }
+                let update = image_progress_to_api(&progress.borrow_and_update());
+                yield PullImageReply { progress: Some(update), image: None };
+            };
+            let image = result
+                .map_err(|error| ApiError {
+                    message: error.to_string(),
+                })?
+                .map_err(ApiError::from)?;
+            let update = image_progress_to_api(&progress.borrow());
+            yield PullImageReply { progress: Some(update), image: Some(image) };
+        };
+        Ok(Response::new(Box::pin(output) as Self::PullImageStream))
+    }
+
+    async fn import_image(
+        &self,
+        request: Request<Streaming<ImportImageRequest>>,
+    ) -> Result<Response<ImportImageReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let mut input = request.into_inner();
+        let Some(request) = input.next().await else {
+            return Err(ApiError {
+                message: "expected to have at least one request".to_string(),
+            }
+            .into());
+        };
+        let request = request?;
+        let tag = (!request.tag.is_empty()).then_some(request.tag);
+        let data = tokio_stream::once(Ok(request.data))
+            .chain(input.map(|request| request.map(|x| x.data).map_err(anyhow::Error::from)));
+        let images = self
+            .images
+            .import(tag, data)
+            .await
+            .map_err(ApiError::from)?;
+        Ok(Response::new(ImportImageReply { images }))
+    }
+
+    async fn remove_image(
+        &self,
+        request: Request<RemoveImageRequest>,
+    ) -> Result<Response<RemoveImageReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        let removed = self
+            .images
+            .remove(&request.image, request.force)
+            .await
+            .map_err(ApiError::from)?;
+        Ok(Response::new(RemoveImageReply { removed }))
+    }
+
+    async fn prune_images(
+        &self,
+        request: Request<PruneImagesRequest>,
+    ) -> Result<Response<PruneImagesReply>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Operator)?;
+        let request = request.into_inner();
+        let (removed, reclaimed) = self
+            .images
+            .prune(request.unused_seconds)
+            .await
+            .map_err(ApiError::from)?;
+        Ok(Response::new(PruneImagesReply { removed, reclaimed }))
+    }
+
     async fn watch_events(
         &self,
         request: Request<WatchEventsRequest>,
     ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        DaemonCaller::require(&request, DaemonRole::Viewer)?;
         let _ = request.into_inner();
         let mut events = self.events.subscribe();
         let output = try_stream! {
@@ -347,3 +1347,19 @@ impl ControlService for RuntimeControlService {
         Ok(Response::new(Box::pin(output) as Self::WatchEventsStream))
     }
 }
+
+fn validate_network_policy(policy: &GuestNetworkPolicy) -> Result<(), ApiError> {
+    for rule in policy.ingress.iter().chain(policy.egress.iter()) {
+        if !rule.cidr.is_empty() && IpNetwork::from_str(&rule.cidr).is_err() {
+            return Err(ApiError {
+                message: format!("invalid network policy cidr: {}", rule.cidr),
+            });
+        }
+        if rule.port > u16::MAX as u32 {
+            return Err(ApiError {
+                message: format!("invalid network policy port: {}", rule.port),
+            });
+        }
+    }
+    Ok(())
+}
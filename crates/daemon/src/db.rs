@@ -1,13 +1,21 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::Result;
-use krata::v1::common::Guest;
+use krata::v1::common::{Guest, Volume};
 use log::error;
 use prost::Message;
 use redb::{Database, ReadableTable, TableDefinition};
 use uuid::Uuid;
 
 const GUESTS: TableDefinition<u128, &[u8]> = TableDefinition::new("guests");
+const VOLUMES: TableDefinition<u128, &[u8]> = TableDefinition::new("volumes");
+const IPV4_RESERVATIONS: TableDefinition<u32, u128> = TableDefinition::new("ipv4_reservations");
+const IPV6_RESERVATIONS: TableDefinition<u128, u128> = TableDefinition::new("ipv6_reservations");
 
 #[derive(Clone)]
 pub struct GuestStore {
@@ -78,3 +86,161 @@ impl GuestStore {
         Ok(())
     }
 }
+
+#[derive(Clone)]
+pub struct VolumeStore {
+    database: Arc<Database>,
+}
+
+impl VolumeStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let database = Database::create(path)?;
+        let write = database.begin_write()?;
+        let _ = write.open_table(VOLUMES);
+        write.commit()?;
+        Ok(VolumeStore {
+            database: Arc::new(database),
+        })
+    }
+
+    pub async fn read(&self, id: Uuid) -> Result<Option<Volume>> {
+        let read = self.database.begin_read()?;
+        let table = read.open_table(VOLUMES)?;
+        let Some(entry) = table.get(id.to_u128_le())? else {
+            return Ok(None);
+        };
+        let bytes = entry.value();
+        Ok(Some(Volume::decode(bytes)?))
+    }
+
+    pub async fn list(&self) -> Result<HashMap<Uuid, Volume>> {
+        let mut volumes: HashMap<Uuid, Volume> = HashMap::new();
+        let read = self.database.begin_read()?;
+        let table = read.open_table(VOLUMES)?;
+        for result in table.iter()? {
+            let (key, value) = result?;
+            let uuid = Uuid::from_u128_le(key.value());
+            let volume = match Volume::decode(value.value()) {
+                Ok(volume) => volume,
+                Err(error) => {
+                    error!(
+                        "found invalid volume in database for uuid {}: {}",
+                        uuid, error
+                    );
+                    continue;
+                }
+            };
+            volumes.insert(uuid, volume);
+        }
+        Ok(volumes)
+    }
+
+    pub async fn update(&self, id: Uuid, entry: Volume) -> Result<()> {
+        let write = self.database.begin_write()?;
+        {
+            let mut table = write.open_table(VOLUMES)?;
+            let bytes = entry.encode_to_vec();
+            table.insert(id.to_u128_le(), bytes.as_slice())?;
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    pub async fn remove(&self, id: Uuid) -> Result<()> {
+        let write = self.database.begin_write()?;
+        {
+            let mut table = write.open_table(VOLUMES)?;
+            table.remove(id.to_u128_le())?;
+        }
+        write.commit()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct NetworkReservationStore {
+    database: Arc<Database>,
+}
+
+impl NetworkReservationStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let database = Database::create(path)?;
+        let write = database.begin_write()?;
+        let _ = write.open_table(IPV4_RESERVATIONS);
+        let _ = write.open_table(IPV6_RESERVATIONS);
+        write.commit()?;
+        Ok(NetworkReservationStore {
+            database: Arc::new(database),
+        })
+    }
+
+    pub async fn list_ipv4(&self) -> Result<HashMap<Ipv4Addr, Uuid>> {
+        let mut reservations: HashMap<Ipv4Addr, Uuid> = HashMap::new();
+        let read = self.database.begin_read()?;
+        let table = read.open_table(IPV4_RESERVATIONS)?;
+        for result in table.iter()? {
+            let (key, value) = result?;
+            reservations.insert(
+                Ipv4Addr::from(key.value()),
+                Uuid::from_u128_le(value.value()),
+            );
+        }
+        Ok(reservations)
+    }
+
+    pub async fn list_ipv6(&self) -> Result<HashMap<Ipv6Addr, Uuid>> {
+        let mut reservations: HashMap<Ipv6Addr, Uuid> = HashMap::new();
+        let read = self.database.begin_read()?;
+        let table = read.open_table(IPV6_RESERVATIONS)?;
+        for result in table.iter()? {
+            let (key, value) = result?;
+            reservations.insert(
+                Ipv6Addr::from(key.value()),
+                Uuid::from_u128_le(value.value()),
+            );
+        }
+        Ok(reservations)
+    }
+
+    pub async fn reserve(&self, id: Uuid, ipv4: Ipv4Addr, ipv6: Ipv6Addr) -> Result<()> {
+        let write = self.database.begin_write()?;
+        {
+            let mut table = write.open_table(IPV4_RESERVATIONS)?;
+            table.insert(u32::from(ipv4), id.to_u128_le())?;
+            let mut table = write.open_table(IPV6_RESERVATIONS)?;
+            table.insert(u128::from(ipv6), id.to_u128_le())?;
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    pub async fn release(&self, id: Uuid) -> Result<()> {
+        let ipv4 = self
+            .list_ipv4()
+            .await?
+            .into_iter()
+            .filter(|(_, uuid)| *uuid == id)
+            .map(|(ip, _)| ip)
+            .collect::<Vec<_>>();
+        let ipv6 = self
+            .list_ipv6()
+            .await?
+            .into_iter()
+            .filter(|(_, uuid)| *uuid == id)
+            .map(|(ip, _)| ip)
+            .collect::<Vec<_>>();
+        let write = self.database.begin_write()?;
+        {
+            let mut table = write.open_table(IPV4_RESERVATIONS)?;
+            for ip in ipv4 {
+                table.remove(u32::from(ip))?;
+            }
+            let mut table = write.open_table(IPV6_RESERVATIONS)?;
+            for ip in ipv6 {
+                table.remove(u128::from(ip))?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+}
@@ -6,8 +6,11 @@ use std::{
 
 use anyhow::Result;
 use krata::{
-    idm::protocol::{idm_event::Event, IdmEvent},
-    v1::common::{GuestExitInfo, GuestState, GuestStatus},
+    idm::protocol::{
+        idm_event::Event, IdmEvent, IdmHealthEvent, IdmHealthStatus, IdmLogEvent, IdmLogStream,
+        IdmOomEvent,
+    },
+    v1::common::{GuestExitInfo, GuestHealthInfo, GuestHealthStatus, GuestState, GuestStatus},
 };
 use log::{error, warn};
 use tokio::{
@@ -21,7 +24,11 @@ use tokio::{
 };
 use uuid::Uuid;
 
-use crate::{db::GuestStore, idm::DaemonIdmHandle};
+use crate::{
+    db::GuestStore,
+    idm::DaemonIdmHandle,
+    logs::{DaemonLogStore, LOG_STREAM_STDERR, LOG_STREAM_STDOUT},
+};
 
 pub type DaemonEvent = krata::v1::control::watch_events_reply::Event;
 
@@ -49,6 +56,7 @@ pub struct DaemonEventGenerator {
     guest_reconciler_notify: Sender<Uuid>,
     feed: broadcast::Receiver<DaemonEvent>,
     idm: DaemonIdmHandle,
+    logs: DaemonLogStore,
     idms: HashMap<u32, (Uuid, JoinHandle<()>)>,
     idm_sender: Sender<(u32, IdmEvent)>,
     idm_receiver: Receiver<(u32, IdmEvent)>,
@@ -60,6 +68,7 @@ impl DaemonEventGenerator {
         guests: GuestStore,
         guest_reconciler_notify: Sender<Uuid>,
         idm: DaemonIdmHandle,
+        logs: DaemonLogStore,
     ) -> Result<(DaemonEventContext, DaemonEventGenerator)> {
         let (sender, _) = broadcast::channel(EVENT_CHANNEL_QUEUE_LEN);
         let (idm_sender, idm_receiver) = channel(IDM_EVENT_CHANNEL_QUEUE_LEN);
@@ -68,6 +77,7 @@ impl DaemonEventGenerator {
             guest_reconciler_notify,
             feed: sender.subscribe(),
             idm,
+            logs,
             idms: HashMap::new(),
             idm_sender,
             idm_receiver,
@@ -93,6 +103,18 @@ impl DaemonEventGenerator {
                 let domid = state.domid;
                 match status {
                     GuestStatus::Started => {
+                        let stale = self
+                            .idms
+                            .iter()
+                            .filter(|(other, (uuid, _))| **other != domid && *uuid == id)
+                            .map(|(other, _)| *other)
+                            .collect::<Vec<_>>();
+                        for other in stale {
+                            if let Some((_, handle)) = self.idms.remove(&other) {
+                                handle.abort();
+                            }
+                        }
+
                         if let Entry::Vacant(e) = self.idms.entry(domid) {
                             let client = self.idm.client(domid).await?;
                             let mut receiver = client.subscribe().await?;
@@ -128,18 +150,97 @@ impl DaemonEventGenerator {
     async fn handle_idm_event(&mut self, id: Uuid, event: IdmEvent) -> Result<()> {
         match event.event {
             Some(Event::Exit(exit)) => self.handle_exit_code(id, exit.code).await,
-            None => Ok(()),
+            Some(Event::Log(log)) => self.handle_log(id, log).await,
+            Some(Event::Health(health)) => self.handle_health(id, health).await,
+            Some(Event::Oom(oom)) => self.handle_oom(id, oom).await,
+            Some(Event::ExecOutput(_)) | Some(Event::ExecExit(_)) | None => Ok(()),
         }
     }
 
+    async fn handle_log(&mut self, id: Uuid, log: IdmLogEvent) -> Result<()> {
+        let stream = match log.stream() {
+            IdmLogStream::Stdout => LOG_STREAM_STDOUT,
+            IdmLogStream::Stderr => LOG_STREAM_STDERR,
+        };
+        self.logs
+            .append_at(id, stream, log.timestamp, log.sequence, &log.data)
+            .await
+    }
+
+    async fn handle_health(&mut self, id: Uuid, health: IdmHealthEvent) -> Result<()> {
+        let Some(mut guest) = self.guests.read(id).await? else {
+            return Ok(());
+        };
+        let Some(ref mut state) = guest.state else {
+            return Ok(());
+        };
+        if state.status() != GuestStatus::Started {
+            return Ok(());
+        }
+
+        let status = match health.status() {
+            IdmHealthStatus::Unknown => GuestHealthStatus::Unknown,
+            IdmHealthStatus::Starting => GuestHealthStatus::Starting,
+            IdmHealthStatus::Healthy => GuestHealthStatus::Healthy,
+            IdmHealthStatus::Unhealthy => GuestHealthStatus::Unhealthy,
+        };
+        state.health_info = Some(GuestHealthInfo {
+            status: status.into(),
+            failing_streak: health.failing_streak,
+            output: health.output,
+        });
+        self.guests.update(id, guest).await?;
+        self.guest_reconciler_notify.send(id).await?;
+        Ok(())
+    }
+
+    async fn handle_oom(&mut self, id: Uuid, oom: IdmOomEvent) -> Result<()> {
+        let Some(mut guest) = self.guests.read(id).await? else {
+            return Ok(());
+        };
+        let Some(ref mut state) = guest.state else {
+            return Ok(());
+        };
+        if state.oom_kills == oom.oom_kills {
+            return Ok(());
+        }
+
+        warn!(
+            "guest {} task cgroup hit its memory limit, {} processes killed so far",
+            id, oom.oom_kills
+        );
+        state.oom_kills = oom.oom_kills;
+        self.guests.update(id, guest).await?;
+        self.guest_reconciler_notify.send(id).await?;
+        Ok(())
+    }
+
     async fn handle_exit_code(&mut self, id: Uuid, code: i32) -> Result<()> {
         if let Some(mut guest) = self.guests.read(id).await? {
+            // a guest asked to stop is destroyed once its task exits instead of being restarted.
+            let status = match guest.state.as_ref().map(|x| x.status()) {
+                Some(GuestStatus::Stopping) | Some(GuestStatus::Destroying) => {
+                    GuestStatus::Destroying
+                }
+                _ => GuestStatus::Exited,
+            };
             guest.state = Some(GuestState {
-                status: GuestStatus::Exited.into(),
+                status: status.into(),
                 network: guest.state.clone().unwrap_or_default().network,
                 exit_info: Some(GuestExitInfo { code }),
                 error_info: None,
                 domid: guest.state.clone().map(|x| x.domid).unwrap_or(u32::MAX),
+                restart_info: guest.state.clone().and_then(|x| x.restart_info),
+                health_info: None,
+                stop_info: None,
+                oom_kills: guest.state.clone().map(|x| x.oom_kills).unwrap_or_default(),
+                resources: guest.state.clone().and_then(|x| x.resources),
+                image_digest: guest
+                    .state
+                    .clone()
+                    .map(|x| x.image_digest)
+                    .unwrap_or_default(),
+                domain_paused: false,
             });
 
             self.guests.update(id, guest).await?;
@@ -0,0 +1,279 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use krata::v1::{
+    common::{guest_image_spec::Image, CachedImage, GuestStatus},
+    control::{ImagePullLayerPhase, ImagePullLayerProgress, ImagePullPhase, ImagePullProgress},
+};
+use krataoci::{
+    auth::OciRegistryAuth,
+    cache::ImageCacheEntry,
+    progress::{OciProgress, OciProgressContext, OciProgressLayerPhase, OciProgressPhase},
+};
+use kratart::Runtime;
+use log::{info, warn};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+    sync::watch,
+    task::JoinHandle,
+};
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::{db::GuestStore, registry::DaemonRegistryCredentials};
+
+#[derive(Clone)]
+pub struct DaemonImages {
+    runtime: Runtime,
+    guests: GuestStore,
+    registry: DaemonRegistryCredentials,
+    staging: PathBuf,
+}
+
+impl DaemonImages {
+    pub fn new(
+        runtime: Runtime,
+        guests: GuestStore,
+        registry: DaemonRegistryCredentials,
+        staging: PathBuf,
+    ) -> DaemonImages {
+        DaemonImages {
+            runtime,
+            guests,
+            registry,
+            staging,
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<CachedImage>> {
+        let entries = self.runtime.image_cache().list().await?;
+        let in_use = self.images_in_use(&entries).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| cached_image_to_api(&entry, &in_use))
+            .collect())
+    }
+
+    /// Starts pulling an image into the cache, the task resolves to the cached image.
+    pub async fn pull(
+        &self,
+        image: String,
+        auth: OciRegistryAuth,
+    ) -> Result<(
+        watch::Receiver<OciProgress>,
+        JoinHandle<Result<CachedImage>>,
+    )> {
+        let credentials = self.registry.request_store(&image, auth).await?;
+        let (context, receiver) = OciProgressContext::create();
+        let this = self.clone();
+        let task = tokio::task::spawn(async move {
+            let info = this
+                .runtime
+                .pull_image(&image, credentials, context)
+                .await?;
+            info!("pulled image {} digest={}", image, info.digest);
+            this.list()
+                .await?
+                .into_iter()
+                .find(|x| x.digest == info.digest)
+                .ok_or_else(|| anyhow!("pulled image {} is missing from the cache", image))
+        });
+        Ok((receiver, task))
+    }
+
+    /// Stages an uploaded image archive on disk and imports the images inside it.
+    pub async fn import<S>(&self, tag: Option<String>, mut data: S) -> Result<Vec<CachedImage>>
+    where
+        S: Stream<Item = Result<Vec<u8>>> + Unpin,
+    {
+        fs::create_dir_all(&self.staging).await?;
+        let path = self.staging.join(format!("{}.tar", Uuid::new_v4()));
+        let result = async {
+            let mut file = BufWriter::new(File::create(&path).await?);
+            while let Some(chunk) = data.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            drop(file);
+            let (context, _) = OciProgressContext::create();
+            self.runtime
+                .import_image(&path, tag.as_deref(), context)
+                .await
+        }
+        .await;
+        if let Err(error) = fs::remove_file(&path).await {
+            warn!(
+                "failed to remove staged image archive {:?}: {}",
+                path, error
+            );
+        }
+
+        let digests = result?
+            .into_iter()
+            .map(|info| info.digest)
+            .collect::<HashSet<_>>();
+        info!("imported {} images", digests.len());
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|x| digests.contains(&x.digest))
+            .collect())
+    }
+
+    pub async fn remove(&self, query: &str, force: bool) -> Result<Vec<CachedImage>> {
+        let cache = self.runtime.image_cache();
+        let in_use = self.images_in_use(&cache.list().await?).await?;
+        let entries = cache.find(query).await?;
+        if entries.is_empty() {
+            return Err(anyhow!("image {} not found", query));
+        }
+
+        if !force {
+            if let Some(entry) = entries.iter().find(|x| in_use.contains(&x.id)) {
+                return Err(anyhow!(
+                    "image {} is used by a guest, force is required to remove it",
+                    entry.id
+                ));
+            }
+        }
+
+        let mut removed = Vec::new();
+        for entry in entries {
+            if let Some(entry) = cache.remove(&entry.id).await? {
+                info!("removed image {}", entry.id);
+                removed.push(cached_image_to_api(&entry, &in_use));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes every image not used by a guest that has not been used for `unused_seconds`.
+    pub async fn prune(&self, unused_seconds: u64) -> Result<(Vec<CachedImage>, u64)> {
+        let cache = self.runtime.image_cache();
+        let entries = cache.list().await?;
+        let in_use = self.images_in_use(&entries).await?;
+        let cutoff = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs()
+            .saturating_sub(unused_seconds);
+        let mut removed = Vec::new();
+        let mut reclaimed = 0;
+        for entry in entries {
+            if in_use.contains(&entry.id) || entry.last_used > cutoff {
+                continue;
+            }
+            if let Some(entry) = cache.remove(&entry.id).await? {
+                reclaimed += entry.size;
+                removed.push(cached_image_to_api(&entry, &in_use));
+            }
+        }
+        info!(
+            "pruned {} images, reclaimed {} bytes",
+            removed.len(),
+            reclaimed
+        );
+        Ok((removed, reclaimed))
+    }
+
+    /// Returns the ids of the cache entries used by a guest. Guests that have not
+    /// recorded an image digest yet, such as starting guests, are matched by their image
+    /// reference. Entries without a digest cannot be matched to a running guest, so they
+    /// count as in use while any guest could be using one.
+    async fn images_in_use(&self, entries: &[ImageCacheEntry]) -> Result<HashSet<String>> {
+        let mut in_use = HashSet::new();
+        let mut legacy_in_use = false;
+        for guest in self.guests.list().await?.into_values() {
+            let Some(state) = guest.state else {
+                continue;
+            };
+            if state.status() == GuestStatus::Destroyed {
+                continue;
+            }
+
+            let reference = guest
+                .spec
+                .and_then(|spec| spec.image)
+                .and_then(|image| image.image)
+                .map(|image| match image {
+                    Image::Oci(oci) => oci.image,
+                })
+                .unwrap_or_default();
+            let matched = entries
+                .iter()
+                .filter(|x| {
+                    if state.image_digest.is_empty() {
+                        !reference.is_empty() && x.matches(&reference)
+                    } else {
+                        x.digest == state.image_digest
+                    }
+                })
+                .map(|x| x.id.clone())
+                .collect::<Vec<_>>();
+            if matched.is_empty() {
+                legacy_in_use = true;
+            }
+            in_use.extend(matched);
+        }
+
+        if legacy_in_use {
+            in_use.extend(
+                entries
+                    .iter()
+                    .filter(|x| x.digest.is_empty())
+                    .map(|x| x.id.clone()),
+            );
+        }
+        Ok(in_use)
+    }
+}
+
+fn cached_image_to_api(entry: &ImageCacheEntry, in_use: &HashSet<String>) -> CachedImage {
+    CachedImage {
+        id: entry.id.clone(),
+        digest: entry.digest.clone(),
+        references: entry.references.clone(),
+        size: entry.size,
+        created: entry.created,
+        last_used: entry.last_used,
+        in_use: in_use.contains(&entry.id),
+    }
+}
+
+pub fn image_progress_to_api(progress: &OciProgress) -> ImagePullProgress {
+    ImagePullProgress {
+        phase: match progress.phase {
+            OciProgressPhase::Resolving => ImagePullPhase::Resolving,
+            OciProgressPhase::Resolved => ImagePullPhase::Resolved,
+            OciProgressPhase::ConfigAcquire => ImagePullPhase::ConfigAcquire,
+            OciProgressPhase::LayerAcquire => ImagePullPhase::LayerAcquire,
+            OciProgressPhase::Packing => ImagePullPhase::Packing,
+            OciProgressPhase::Complete => ImagePullPhase::Complete,
+        }
+        .into(),
+        layers: progress
+            .layers
+            .iter()
+            .map(|layer| ImagePullLayerProgress {
+                id: layer.id.clone(),
+                phase: match layer.phase {
+                    OciProgressLayerPhase::Waiting => ImagePullLayerPhase::Waiting,
+                    OciProgressLayerPhase::Downloading => ImagePullLayerPhase::Downloading,
+                    OciProgressLayerPhase::Downloaded => ImagePullLayerPhase::Downloaded,
+                    OciProgressLayerPhase::Extracting => ImagePullLayerPhase::Extracting,
+                    OciProgressLayerPhase::Extracted => ImagePullLayerPhase::Extracted,
+                }
+                .into(),
+                value: layer.value,
+                total: layer.total,
+            })
+            .collect(),
+        value: progress.value,
+        total: progress.total,
+    }
+}
@@ -1,89 +1,193 @@
-use std::{net::SocketAddr, path::PathBuf, str::FromStr};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use auth::DaemonAuthorizer;
+use config::DaemonConfig;
 use console::{DaemonConsole, DaemonConsoleHandle};
 use control::RuntimeControlService;
-use db::GuestStore;
+use db::{GuestStore, NetworkReservationStore, VolumeStore};
 use event::{DaemonEventContext, DaemonEventGenerator};
+use futures::future::{try_join_all, BoxFuture};
 use idm::{DaemonIdm, DaemonIdmHandle};
+use image::DaemonImages;
 use krata::{dial::ControlDialAddress, v1::control::control_service_server::ControlServiceServer};
 use kratart::Runtime;
-use log::info;
+use log::{info, warn};
+use logs::DaemonLogStore;
+use network::DaemonNetworkAssignment;
 use reconcile::guest::GuestReconciler;
+use registry::DaemonRegistryCredentials;
 use tokio::{
     net::UnixListener,
-    sync::mpsc::{channel, Sender},
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
     task::JoinHandle,
 };
 use tokio_stream::wrappers::UnixListenerStream;
-use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use uuid::Uuid;
+use volume::DaemonVolumes;
 
+pub mod auth;
+pub mod config;
 pub mod console;
 pub mod control;
 pub mod db;
 pub mod event;
 pub mod idm;
+pub mod image;
+pub mod logs;
 pub mod metrics;
+pub mod network;
 pub mod reconcile;
+pub mod registry;
+pub mod volume;
 
 pub struct Daemon {
     store: String,
+    config: Arc<Mutex<DaemonConfig>>,
+    network: DaemonNetworkAssignment,
+    registry: DaemonRegistryCredentials,
+    authorizer: DaemonAuthorizer,
     guests: GuestStore,
+    volumes: DaemonVolumes,
     events: DaemonEventContext,
     guest_reconciler_task: JoinHandle<()>,
     guest_reconciler_notify: Sender<Uuid>,
     generator_task: JoinHandle<()>,
+    logs_cleanup_task: JoinHandle<()>,
     idm: DaemonIdmHandle,
     console: DaemonConsoleHandle,
+    logs: DaemonLogStore,
+    runtime: Runtime,
 }
 
 const GUEST_RECONCILER_QUEUE_LEN: usize = 1000;
 
 impl Daemon {
-    pub async fn new(store: String, runtime: Runtime) -> Result<Self> {
+    pub async fn new(store: String, mut config: DaemonConfig, runtime: Runtime) -> Result<Self> {
         let guests_db_path = format!("{}/guests.db", store);
         let guests = GuestStore::open(&PathBuf::from(guests_db_path))?;
+        let volumes_db_path = format!("{}/volumes.db", store);
+        let volumes = VolumeStore::open(&PathBuf::from(volumes_db_path))?;
+        let volumes =
+            DaemonVolumes::new(PathBuf::from(format!("{}/volumes", store)), volumes).await?;
+        let network_db_path = format!("{}/network.db", store);
+        let reservations = NetworkReservationStore::open(&PathBuf::from(network_db_path))?;
+        config.validate()?;
+        let authorizer = DaemonAuthorizer::new(config.auth.policy.clone()).await?;
+        let network = DaemonNetworkAssignment::new(config.network.clone(), reservations)?;
+        network.seed(&runtime.list().await?).await?;
+        let registry = DaemonRegistryCredentials::new(config.registry.clone());
         let (guest_reconciler_notify, guest_reconciler_receiver) =
             channel::<Uuid>(GUEST_RECONCILER_QUEUE_LEN);
         let idm = DaemonIdm::new().await?;
         let idm = idm.launch().await?;
-        let console = DaemonConsole::new().await?;
+        let logs = DaemonLogStore::new(
+            PathBuf::from(format!("{}/logs", store)),
+            config.logs.clone(),
+        )
+        .await?;
+        let logs_cleanup_task = logs.launch_cleanup().await?;
+        let console = DaemonConsole::new(
+            config.console.buffer_size,
+            logs.clone(),
+            runtime.dupe().await?,
+        )
+        .await?;
         let console = console.launch().await?;
-        let (events, generator) =
-            DaemonEventGenerator::new(guests.clone(), guest_reconciler_notify.clone(), idm.clone())
-                .await?;
+        let (events, generator) = DaemonEventGenerator::new(
+            guests.clone(),
+            guest_reconciler_notify.clone(),
+            idm.clone(),
+            logs.clone(),
+        )
+        .await?;
         let runtime_for_reconciler = runtime.dupe().await?;
         let guest_reconciler = GuestReconciler::new(
             guests.clone(),
+            volumes.clone(),
+            network.clone(),
+            registry.clone(),
             events.clone(),
             runtime_for_reconciler,
             guest_reconciler_notify.clone(),
+            config.reconciler.parallel_limit,
+            config.mounts.clone(),
         )?;
 
         let guest_reconciler_task = guest_reconciler.launch(guest_reconciler_receiver).await?;
         let generator_task = generator.launch().await?;
         Ok(Self {
             store,
+            config: Arc::new(Mutex::new(config)),
+            network,
+            registry,
+            authorizer,
             guests,
+            volumes,
             events,
             guest_reconciler_task,
             guest_reconciler_notify,
             generator_task,
+            logs_cleanup_task,
             idm,
             console,
+            logs,
+            runtime,
         })
     }
 
-    pub async fn listen(&mut self, addr: ControlDialAddress) -> Result<()> {
+    pub fn reload_handle(&self) -> DaemonReloadHandle {
+        DaemonReloadHandle {
+            config: self.config.clone(),
+            network: self.network.clone(),
+            registry: self.registry.clone(),
+            authorizer: self.authorizer.clone(),
+            console: self.console.clone(),
+        }
+    }
+
+    pub async fn listen(&mut self, addrs: Vec<ControlDialAddress>) -> Result<()> {
+        if addrs.is_empty() {
+            return Err(anyhow!("no listen addresses were specified"));
+        }
+
         let control_service = RuntimeControlService::new(
             self.events.clone(),
             self.console.clone(),
             self.idm.clone(),
             self.guests.clone(),
+            self.volumes.clone(),
             self.guest_reconciler_notify.clone(),
+            self.logs.clone(),
+            self.runtime.dupe().await?,
+            self.registry.clone(),
+            self.network.clone(),
+            DaemonImages::new(
+                self.runtime.dupe().await?,
+                self.guests.clone(),
+                self.registry.clone(),
+                PathBuf::from(format!("{}/cache/import", self.store)),
+            ),
         );
 
+        let mut servers = Vec::new();
+        for addr in addrs {
+            let server = self.serve(addr, control_service.clone()).await?;
+            servers.push(server);
+        }
+        try_join_all(servers).await?;
+        Ok(())
+    }
+
+    async fn serve(
+        &self,
+        addr: ControlDialAddress,
+        control_service: RuntimeControlService,
+    ) -> Result<BoxFuture<'static, Result<()>>> {
         let mut server = Server::builder();
 
         if let ControlDialAddress::Tls {
@@ -92,18 +196,34 @@ impl Daemon {
             insecure,
         } = &addr
         {
-            let mut tls_config = ServerTlsConfig::new();
-            if !insecure {
-                let certificate_path = format!("{}/tls/daemon.pem", self.store);
-                let key_path = format!("{}/tls/daemon.key", self.store);
-                tls_config = tls_config.identity(Identity::from_pem(certificate_path, key_path));
+            if *insecure {
+                return Err(anyhow!(
+                    "listen address {} must use tls:// since the daemon requires a server identity",
+                    addr
+                ));
             }
-            server = server.tls_config(tls_config)?;
+            server = server.tls_config(self.server_tls_config().await?)?;
         }
 
-        let server = server.add_service(ControlServiceServer::new(control_service));
+        if let ControlDialAddress::Tcp { .. } = &addr {
+            if !self.config.lock().await.allow_insecure_tcp {
+                return Err(anyhow!(
+                    "listen address {} is not encrypted or authenticated, use tls:// or set allow_insecure_tcp",
+                    addr
+                ));
+            }
+            warn!(
+                "listen address {} is not authenticated, clients are treated as anonymous peers",
+                addr
+            );
+        }
+
+        let router = server.add_service(ControlServiceServer::with_interceptor(
+            control_service,
+            self.authorizer.clone(),
+        ));
         info!("listening on address {}", addr);
-        match addr {
+        let future: BoxFuture<'static, Result<()>> = match addr {
             ControlDialAddress::UnixSocket { path } => {
                 let path = PathBuf::from(path);
                 if path.exists() {
@@ -111,23 +231,77 @@ impl Daemon {
                 }
                 let listener = UnixListener::bind(path)?;
                 let stream = UnixListenerStream::new(listener);
-                server.serve_with_incoming(stream).await?;
-            }
-
-            ControlDialAddress::Tcp { host, port } => {
-                let address = format!("{}:{}", host, port);
-                server.serve(SocketAddr::from_str(&address)?).await?;
+                Box::pin(async move { Ok(router.serve_with_incoming(stream).await?) })
             }
 
-            ControlDialAddress::Tls {
+            ControlDialAddress::Tcp { host, port }
+            | ControlDialAddress::Tls {
                 host,
                 port,
                 insecure: _,
             } => {
-                let address = format!("{}:{}", host, port);
-                server.serve(SocketAddr::from_str(&address)?).await?;
+                let address = SocketAddr::from_str(&format!("{}:{}", host, port))?;
+                Box::pin(async move { Ok(router.serve(address).await?) })
             }
+        };
+        Ok(future)
+    }
+
+    async fn server_tls_config(&self) -> Result<ServerTlsConfig> {
+        let config = self.config.lock().await;
+        let certificate_path = config
+            .tls
+            .certificate
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("{}/tls/daemon.pem", self.store)));
+        let key_path = config
+            .tls
+            .key
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("{}/tls/daemon.key", self.store)));
+        let Some(client_ca_path) = config.tls.client_ca.clone() else {
+            return Err(anyhow!(
+                "tls listeners require tls.client_ca to be set in the daemon config"
+            ));
+        };
+        drop(config);
+
+        let certificate = tokio::fs::read(&certificate_path).await?;
+        let key = tokio::fs::read(&key_path).await?;
+        let client_ca = tokio::fs::read(&client_ca_path).await?;
+        Ok(ServerTlsConfig::new()
+            .identity(Identity::from_pem(certificate, key))
+            .client_ca_root(Certificate::from_pem(client_ca)))
+    }
+}
+
+#[derive(Clone)]
+pub struct DaemonReloadHandle {
+    config: Arc<Mutex<DaemonConfig>>,
+    network: DaemonNetworkAssignment,
+    registry: DaemonRegistryCredentials,
+    authorizer: DaemonAuthorizer,
+    console: DaemonConsoleHandle,
+}
+
+impl DaemonReloadHandle {
+    pub async fn reload(&self, mut updated: DaemonConfig) -> Result<()> {
+        updated.validate()?;
+        let mut config = self.config.lock().await;
+        let mut reloaded = config.clone();
+        let ignored = reloaded.reload(updated);
+        self.authorizer.reload().await?;
+        self.network.update_config(reloaded.network.clone()).await?;
+        self.registry.update_config(reloaded.registry.clone()).await;
+        self.console.set_buffer_size(reloaded.console.buffer_size);
+        *config = reloaded;
+        for section in ignored {
+            warn!(
+                "config section {} changed but requires a daemon restart to apply",
+                section
+            );
         }
+        info!("reloaded daemon config");
         Ok(())
     }
 }
@@ -136,5 +310,6 @@ impl Drop for Daemon {
     fn drop(&mut self) {
         self.guest_reconciler_task.abort();
         self.generator_task.abort();
+        self.logs_cleanup_task.abort();
     }
 }
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use crate::config::DaemonLogsConfig;
+
+pub const LOG_STREAM_CONSOLE: &str = "console";
+pub const LOG_STREAM_STDOUT: &str = "stdout";
+pub const LOG_STREAM_STDERR: &str = "stderr";
+
+const LOG_RECORD_HEADER_LEN: usize = 12;
+const LOG_CLEANUP_INTERVAL_SECS: u64 = 3600;
+
+struct LogWriter {
+    file: File,
+    size: u64,
+    sequence: u64,
+}
+
+/// Spools guest output streams into rotating files at `{path}/{uuid}/{stream}.log`.
+/// Each record is framed as a little-endian unix millisecond timestamp and length
+/// followed by the raw bytes, so reads can be filtered by time.
+#[derive(Clone)]
+pub struct DaemonLogStore {
+    path: PathBuf,
+    config: DaemonLogsConfig,
+    writers: Arc<Mutex<HashMap<(Uuid, String), LogWriter>>>,
+}
+
+impl DaemonLogStore {
+    pub async fn new(path: PathBuf, config: DaemonLogsConfig) -> Result<DaemonLogStore> {
+        fs::create_dir_all(&path).await?;
+        Ok(DaemonLogStore {
+            path,
+            config,
+            writers: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub async fn append(&self, uuid: Uuid, stream: &str, data: &[u8]) -> Result<()> {
+        self.append_at(uuid, stream, unix_time_millis(), 0, data)
+            .await
+    }
+
+    /// Appends a record, `sequence` is the position of the record in its stream as
+    /// reported by the guest and is remembered while the stream stays open.
+    pub async fn append_at(
+        &self,
+        uuid: Uuid,
+        stream: &str,
+        timestamp: u64,
+        sequence: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut writers = self.writers.lock().await;
+        let key = (uuid, stream.to_string());
+        let mut last_sequence = 0;
+        if let Some(writer) = writers.get(&key) {
+            if writer.size >= self.config.max_file_size {
+                last_sequence = writer.sequence;
+                writers.remove(&key);
+                self.rotate(uuid, stream).await?;
+            }
+        }
+
+        let writer = match writers.get_mut(&key) {
+            Some(writer) => writer,
+            None => {
+                let directory = self.path.join(uuid.to_string());
+                fs::create_dir_all(&directory).await?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.file_path(uuid, stream, 0))
+                    .await?;
+                let size = file.metadata().await?.len();
+                writers.entry(key).or_insert(LogWriter {
+                    file,
+                    size,
+                    sequence: last_sequence,
+                })
+            }
+        };
+
+        let mut record = Vec::with_capacity(LOG_RECORD_HEADER_LEN + data.len());
+        record.extend_from_slice(&timestamp.to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        writer.file.write_all(&record).await?;
+        writer.size += record.len() as u64;
+        writer.sequence = sequence;
+        Ok(())
+    }
+
+    /// Closes any open files for the guest, which happens when the guest is gone.
+    pub async fn close(&self, uuid: Uuid) {
+        let mut writers = self.writers.lock().await;
+        writers.retain(|(owner, _), _| *owner != uuid);
+    }
+
+    /// Reads records at or after `since` (unix ms) from the oldest file to the newest,
+    /// keeping only the last `tail` lines when `tail` is non-zero.
+    pub async fn read(&self, uuid: Uuid, stream: &str, since: u64, tail: u64) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        for (_, data) in self.read_records(uuid, stream, since, u64::MAX).await? {
+            output.extend_from_slice(&data);
+        }
+
+        if tail > 0 {
+            let mut lines = 0;
+            let end = if output.ends_with(b"\n") {
+                output.len() - 1
+            } else {
+                output.len()
+            };
+            for (index, byte) in output[..end].iter().enumerate().rev() {
+                if *byte == b'\n' {
+                    lines += 1;
+                    if lines == tail {
+                        output.drain(..=index);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Reads records with a timestamp in `since..=until` (unix ms), oldest first.
+    pub async fn read_records(
+        &self,
+        uuid: Uuid,
+        stream: &str,
+        since: u64,
+        until: u64,
+    ) -> Result<Vec<(u64, Vec<u8>)>> {
+        let mut records = Vec::new();
+        for index in (0..self.config.max_files.max(1)).rev() {
+            let path = self.file_path(uuid, stream, index);
+            if !path.exists() {
+                continue;
+            }
+            let content = fs::read(&path).await?;
+            for (timestamp, data) in LogRecords::new(&content) {
+                if timestamp >= since && timestamp <= until {
+                    records.push((timestamp, data.to_vec()));
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Reads records like `read_records` along with the sequence of the last record
+    /// appended to the open stream, zero when it is not open. No record is appended
+    /// while the snapshot is taken, so live records can be matched against it.
+    pub async fn read_records_sequenced(
+        &self,
+        uuid: Uuid,
+        stream: &str,
+        since: u64,
+        until: u64,
+    ) -> Result<(Vec<(u64, Vec<u8>)>, u64)> {
+        let mut writers = self.writers.lock().await;
+        let sequence = match writers.get_mut(&(uuid, stream.to_string())) {
+            Some(writer) => {
+                writer.file.flush().await?;
+                writer.sequence
+            }
+            None => 0,
+        };
+        let records = self.read_records(uuid, stream, since, until).await?;
+        Ok((records, sequence))
+    }
+
+    pub async fn launch_cleanup(&self) -> Result<JoinHandle<()>> {
+        let store = self.clone();
+        Ok(tokio::task::spawn(async move {
+            loop {
+                if let Err(error) = store.cleanup().await {
+                    warn!("failed to clean up guest logs: {}", error);
+                }
+                tokio::time::sleep(Duration::from_secs(LOG_CLEANUP_INTERVAL_SECS)).await;
+            }
+        }))
+    }
+
+    async fn cleanup(&self) -> Result<()> {
+        let max_age = Duration::from_secs(self.config.max_age_hours * 3600);
+        let active = self
+            .writers
+            .lock()
+            .await
+            .keys()
+            .map(|(uuid, stream)| self.file_path(*uuid, stream, 0))
+            .collect::<Vec<_>>();
+        let mut directories = fs::read_dir(&self.path).await?;
+        while let Some(directory) = directories.next_entry().await? {
+            if !directory.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut remaining = 0;
+            let mut files = fs::read_dir(directory.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let modified = file.metadata().await?.modified()?;
+                let age = SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default();
+                if age > max_age && !active.contains(&file.path()) {
+                    debug!("removing expired guest log {:?}", file.path());
+                    fs::remove_file(file.path()).await?;
+                } else {
+                    remaining += 1;
+                }
+            }
+
+            if remaining == 0 {
+                fs::remove_dir(directory.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn rotate(&self, uuid: Uuid, stream: &str) -> Result<()> {
+        let last = self.config.max_files.max(1) - 1;
+        let oldest = self.file_path(uuid, stream, last);
+        if oldest.exists() {
+            fs::remove_file(&oldest).await?;
+        }
+        for index in (0..last).rev() {
+            let from = self.file_path(uuid, stream, index);
+            if from.exists() {
+                fs::rename(&from, self.file_path(uuid, stream, index + 1)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file_path(&self, uuid: Uuid, stream: &str, index: u64) -> PathBuf {
+        let directory = self.path.join(uuid.to_string());
+        if index == 0 {
+            directory.join(format!("{}.log", stream))
+        } else {
+            directory.join(format!("{}.log.{}", stream, index))
+        }
+    }
+}
+
+struct LogRecords<'a> {
+    content: &'a [u8],
+}
+
+impl<'a> LogRecords<'a> {
+    fn new(content: &'a [u8]) -> Self {
+        Self { content }
+    }
+}
+
+impl<'a> Iterator for LogRecords<'a> {
+    type Item = (u64, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.content.len() < LOG_RECORD_HEADER_LEN {
+            return None;
+        }
+        let timestamp = u64::from_le_bytes(self.content[0..8].try_into().ok()?);
+        let len = u32::from_le_bytes(self.content[8..12].try_into().ok()?) as usize;
+        let data = self
+            .content
+            .get(LOG_RECORD_HEADER_LEN..LOG_RECORD_HEADER_LEN + len)?;
+        self.content = &self.content[LOG_RECORD_HEADER_LEN + len..];
+        Some((timestamp, data))
+    }
+}
+
+fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
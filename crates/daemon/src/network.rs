@@ -0,0 +1,180 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use ipnetwork::{IpNetwork, Ipv4Network};
+use kratart::{launch::GuestLaunchNetwork, GuestInfo};
+use log::{info, warn};
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::{config::DaemonNetworkConfig, db::NetworkReservationStore};
+
+#[derive(Clone)]
+pub struct DaemonNetworkAssignment {
+    config: Arc<RwLock<DaemonNetworkConfig>>,
+    reservations: NetworkReservationStore,
+    lock: Arc<Mutex<()>>,
+}
+
+impl DaemonNetworkAssignment {
+    pub fn new(
+        config: DaemonNetworkConfig,
+        reservations: NetworkReservationStore,
+    ) -> Result<DaemonNetworkAssignment> {
+        config.validate()?;
+        Ok(DaemonNetworkAssignment {
+            config: Arc::new(RwLock::new(config)),
+            reservations,
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub async fn assign(&self, uuid: Uuid) -> Result<GuestLaunchNetwork> {
+        let _lock = self.lock.lock().await;
+        let config = self.config.read().await.clone();
+        let ipv4_reservations = self.reservations.list_ipv4().await?;
+        let ipv6_reservations = self.reservations.list_ipv6().await?;
+
+        let existing_ipv4 = ipv4_reservations
+            .iter()
+            .find(|(ip, owner)| **owner == uuid && config.ipv4.subnet.contains(**ip))
+            .map(|(ip, _)| *ip);
+        let existing_ipv6 = ipv6_reservations
+            .iter()
+            .find(|(ip, owner)| **owner == uuid && config.ipv6.subnet.contains(**ip))
+            .map(|(ip, _)| *ip);
+
+        let (ipv4, ipv6) = if let (Some(ipv4), Some(ipv6)) = (existing_ipv4, existing_ipv6) {
+            (ipv4, ipv6)
+        } else {
+            self.reservations.release(uuid).await?;
+            let ipv4 = DaemonNetworkAssignment::allocate_ipv4(&config, |ip| {
+                ipv4_reservations
+                    .get(&ip)
+                    .map(|owner| *owner != uuid)
+                    .unwrap_or(false)
+            })?;
+            let ipv6 = DaemonNetworkAssignment::allocate_ipv6(&config, |ip| {
+                ipv6_reservations
+                    .get(&ip)
+                    .map(|owner| *owner != uuid)
+                    .unwrap_or(false)
+            })?;
+            self.reservations.reserve(uuid, ipv4, ipv6).await?;
+            info!("assigned guest {} addresses {} and {}", uuid, ipv4, ipv6);
+            (ipv4, ipv6)
+        };
+
+        Ok(GuestLaunchNetwork {
+            ipv4,
+            ipv4_prefix: config.ipv4.subnet.prefix(),
+            ipv6,
+            ipv6_prefix: config.ipv6.subnet.prefix(),
+            gateway_ipv4: config.ipv4.gateway,
+            gateway_ipv6: config.ipv6.gateway,
+            nameservers: config.nameservers.iter().map(|x| x.to_string()).collect(),
+            hosts: Vec::new(),
+        })
+    }
+
+    /// Reserves the addresses of running guests that have no reservation, so guests
+    /// launched before their reservations were recorded are never given out again.
+    pub async fn seed(&self, guests: &[GuestInfo]) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        let ipv4_reservations = self.reservations.list_ipv4().await?;
+        let ipv6_reservations = self.reservations.list_ipv6().await?;
+        for guest in guests {
+            let (Some(IpNetwork::V4(ipv4)), Some(IpNetwork::V6(ipv6))) =
+                (guest.guest_ipv4, guest.guest_ipv6)
+            else {
+                continue;
+            };
+            let (ipv4, ipv6) = (ipv4.ip(), ipv6.ip());
+            if ipv4_reservations.values().any(|x| *x == guest.uuid)
+                || ipv6_reservations.values().any(|x| *x == guest.uuid)
+            {
+                continue;
+            }
+            if ipv4_reservations.contains_key(&ipv4) || ipv6_reservations.contains_key(&ipv6) {
+                warn!(
+                    "running guest {} uses addresses {} and {} reserved by another guest",
+                    guest.uuid, ipv4, ipv6
+                );
+                continue;
+            }
+            self.reservations.reserve(guest.uuid, ipv4, ipv6).await?;
+            info!(
+                "reserved addresses {} and {} of running guest {}",
+                ipv4, ipv6, guest.uuid
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn host_ipv4(&self) -> Result<Ipv4Network> {
+        let config = self.config.read().await;
+        Ok(Ipv4Network::new(
+            config.ipv4.host,
+            config.ipv4.subnet.prefix(),
+        )?)
+    }
+
+    pub async fn update_config(&self, config: DaemonNetworkConfig) -> Result<()> {
+        config.validate()?;
+        let _lock = self.lock.lock().await;
+        *self.config.write().await = config;
+        Ok(())
+    }
+
+    pub async fn release(&self, uuid: Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        self.reservations.release(uuid).await
+    }
+
+    fn allocate_ipv4(
+        config: &DaemonNetworkConfig,
+        used: impl Fn(Ipv4Addr) -> bool,
+    ) -> Result<Ipv4Addr> {
+        let subnet = config.ipv4.subnet;
+        for ip in subnet.iter() {
+            if ip == subnet.network()
+                || ip == subnet.broadcast()
+                || ip == config.ipv4.gateway
+                || ip == config.ipv4.host
+                || config.ipv4.reserved.contains(&ip)
+                || used(ip)
+            {
+                continue;
+            }
+            return Ok(ip);
+        }
+        Err(anyhow!(
+            "unable to find ipv4 to allocate to guest, ipv4 addresses in {} are exhausted",
+            subnet
+        ))
+    }
+
+    fn allocate_ipv6(
+        config: &DaemonNetworkConfig,
+        used: impl Fn(Ipv6Addr) -> bool,
+    ) -> Result<Ipv6Addr> {
+        let subnet = config.ipv6.subnet;
+        for ip in subnet.iter() {
+            if ip == subnet.network()
+                || ip == config.ipv6.gateway
+                || config.ipv6.reserved.contains(&ip)
+                || used(ip)
+            {
+                continue;
+            }
+            return Ok(ip);
+        }
+        Err(anyhow!(
+            "unable to find ipv6 to allocate to guest, ipv6 addresses in {} are exhausted",
+            subnet
+        ))
+    }
+}
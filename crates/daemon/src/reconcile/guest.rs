@@ -1,19 +1,26 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
+    path::Path,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Result};
+use krata::launchcfg::{LaunchHealthCheck, LaunchHealthCheckKind, LaunchResources};
 use krata::v1::{
     common::{
-        guest_image_spec::Image, Guest, GuestErrorInfo, GuestExitInfo, GuestNetworkState,
-        GuestState, GuestStatus,
+        guest_health_check_spec::Check, guest_image_spec::Image, Guest, GuestErrorInfo,
+        GuestExitInfo, GuestHealthCheckSpec, GuestHealthInfo, GuestHealthStatus, GuestMountSpec,
+        GuestNetworkState, GuestPortSpec, GuestResourceState, GuestRestartPolicy,
+        GuestRestartPolicyMode, GuestState, GuestStatus, GuestTaskResources, GuestVolumeSpec,
     },
     control::GuestChangedEvent,
 };
-use kratart::{launch::GuestLaunchRequest, GuestInfo, Runtime};
-use log::{error, info, trace, warn};
+use kratart::{
+    launch::{GuestLaunchMount, GuestLaunchNetworkHost, GuestLaunchRequest, GuestLaunchVolume},
+    GuestInfo, Runtime,
+};
+use log::{debug, error, info, trace, warn};
 use tokio::{
     select,
     sync::{
@@ -26,11 +33,21 @@ use tokio::{
 use uuid::Uuid;
 
 use crate::{
+    config::DaemonMountsConfig,
     db::GuestStore,
     event::{DaemonEvent, DaemonEventContext},
+    network::DaemonNetworkAssignment,
+    registry::DaemonRegistryCredentials,
+    volume::DaemonVolumes,
 };
 
-const PARALLEL_LIMIT: u32 = 5;
+const DEFAULT_MAX_RESTART_BACKOFF_SECONDS: u64 = 300;
+const MIN_RESTART_BACKOFF_SECONDS: u64 = 1;
+// a guest that stays up this long has its restart count reset
+const RESTART_STABLE_SECONDS: u64 = 600;
+const DEFAULT_HEALTH_CHECK_INTERVAL_SECONDS: u64 = 30;
+const DEFAULT_HEALTH_CHECK_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_HEALTH_CHECK_RETRIES: u32 = 3;
 
 #[derive(Debug)]
 enum GuestReconcilerResult {
@@ -52,27 +69,41 @@ impl Drop for GuestReconcilerEntry {
 #[derive(Clone)]
 pub struct GuestReconciler {
     guests: GuestStore,
+    volumes: DaemonVolumes,
+    network: DaemonNetworkAssignment,
+    registry: DaemonRegistryCredentials,
     events: DaemonEventContext,
     runtime: Runtime,
     tasks: Arc<Mutex<HashMap<Uuid, GuestReconcilerEntry>>>,
     guest_reconciler_notify: Sender<Uuid>,
     reconcile_lock: Arc<RwLock<()>>,
+    mounts: DaemonMountsConfig,
 }
 
 impl GuestReconciler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         guests: GuestStore,
+        volumes: DaemonVolumes,
+        network: DaemonNetworkAssignment,
+        registry: DaemonRegistryCredentials,
         events: DaemonEventContext,
         runtime: Runtime,
         guest_reconciler_notify: Sender<Uuid>,
+        parallel_limit: u32,
+        mounts: DaemonMountsConfig,
     ) -> Result<Self> {
         Ok(Self {
             guests,
+            volumes,
+            network,
+            registry,
             events,
             runtime,
             tasks: Arc::new(Mutex::new(HashMap::new())),
             guest_reconciler_notify,
-            reconcile_lock: Arc::new(RwLock::with_max_readers((), PARALLEL_LIMIT)),
+            reconcile_lock: Arc::new(RwLock::with_max_readers((), parallel_limit)),
+            mounts,
         })
     }
 
@@ -132,13 +163,24 @@ impl GuestReconciler {
 
                 Some(runtime) => {
                     let mut state = stored_guest.state.as_mut().cloned().unwrap_or_default();
+                    let status = state.status();
                     if let Some(code) = runtime.state.exit_code {
-                        state.status = GuestStatus::Exited.into();
                         state.exit_info = Some(GuestExitInfo { code });
-                    } else {
+                        match status {
+                            GuestStatus::Stopping => state.set_status(GuestStatus::Destroying),
+                            GuestStatus::Destroying => {}
+                            _ => state.set_status(GuestStatus::Exited),
+                        }
+                    } else if !matches!(
+                        status,
+                        GuestStatus::Stopping | GuestStatus::Destroying | GuestStatus::Paused
+                    ) {
                         state.status = GuestStatus::Started.into();
                     }
                     state.network = Some(guestinfo_to_networkstate(runtime));
+                    if let Some(ref digest) = runtime.image_digest {
+                        state.image_digest.clone_from(digest);
+                    }
                     stored_guest.state = Some(state);
                 }
             }
@@ -173,7 +215,10 @@ impl GuestReconciler {
         let start_status = guest.state.as_ref().map(|x| x.status()).unwrap_or_default();
         let result = match start_status {
             GuestStatus::Starting => self.start(uuid, &mut guest).await,
-            GuestStatus::Exited => self.exited(&mut guest).await,
+            GuestStatus::Exited => self.exited(uuid, &mut guest).await,
+            GuestStatus::Stopping => self.stopping(uuid, &mut guest).await,
+            GuestStatus::Started => self.started(uuid, &mut guest).await,
+            GuestStatus::Paused => self.paused(uuid, &mut guest).await,
             GuestStatus::Destroying => self.destroy(uuid, &mut guest).await,
             _ => Ok(GuestReconcilerResult::Unchanged),
         };
@@ -233,7 +278,40 @@ impl GuestReconciler {
             }
         };
 
+        let restart_at = guest
+            .state
+            .as_ref()
+            .and_then(|x| x.restart_info.as_ref())
+            .map(|x| x.restart_at)
+            .unwrap_or_default() as u128;
+        let now = unix_time_millis()?;
+        if restart_at > now {
+            let delay = Duration::from_millis((restart_at - now) as u64);
+            let notify = self.guest_reconciler_notify.clone();
+            tokio::task::spawn(async move {
+                sleep(delay).await;
+                let _ = notify.send(uuid).await;
+            });
+            return Ok(GuestReconcilerResult::Unchanged);
+        }
+
         let task = spec.task.as_ref().cloned().unwrap_or_default();
+        let volumes = self.resolve_volumes(uuid, &spec.volumes).await?;
+        let mounts = resolve_mounts(&self.mounts, &spec.mounts)?;
+        self.validate_ports(uuid, &spec.ports).await?;
+        let healthcheck = spec
+            .healthcheck
+            .as_ref()
+            .map(resolve_healthcheck)
+            .transpose()?;
+        let resources = resolve_resources(
+            &task.resources.clone().unwrap_or_default(),
+            spec.vcpus,
+            spec.mem,
+        )?;
+        let mut network = self.network.assign(uuid).await?;
+        network.hosts = self.resolve_group_hosts(uuid, &spec.group).await?;
+        let credentials = self.registry.guest_store(uuid, &oci.image).await?;
 
         let info = self
             .runtime
@@ -247,6 +325,8 @@ impl GuestReconciler {
                 image: &oci.image,
                 vcpus: spec.vcpus,
                 mem: spec.mem,
+                max_vcpus: spec.max_vcpus.max(spec.vcpus),
+                max_mem: spec.max_mem.max(spec.mem),
                 env: task
                     .environment
                     .iter()
@@ -254,6 +334,13 @@ impl GuestReconciler {
                     .collect::<HashMap<_, _>>(),
                 run: empty_vec_optional(task.command.clone()),
                 debug: false,
+                volumes,
+                mounts,
+                network,
+                tty: task.tty,
+                healthcheck: healthcheck.clone(),
+                resources,
+                credentials,
             })
             .await?;
         info!("started guest {}", uuid);
@@ -263,23 +350,329 @@ impl GuestReconciler {
             exit_info: None,
             error_info: None,
             domid: info.domid,
+            restart_info: guest.state.as_ref().and_then(|x| x.restart_info.clone()),
+            health_info: healthcheck.map(|_| GuestHealthInfo {
+                status: GuestHealthStatus::Starting.into(),
+                failing_streak: 0,
+                output: String::new(),
+            }),
+            stop_info: None,
+            oom_kills: 0,
+            resources: Some(GuestResourceState {
+                vcpus: spec.vcpus,
+                mem: spec.mem,
+            }),
+            image_digest: info.image_digest.clone().unwrap_or_default(),
+            domain_paused: false,
         });
         Ok(GuestReconcilerResult::Changed { rerun: false })
     }
 
-    async fn exited(&self, guest: &mut Guest) -> Result<GuestReconcilerResult> {
-        if let Some(ref mut state) = guest.state {
+    async fn resolve_group_hosts(
+        &self,
+        uuid: Uuid,
+        group: &str,
+    ) -> Result<Vec<GuestLaunchNetworkHost>> {
+        let mut hosts = Vec::new();
+        if group.is_empty() {
+            return Ok(hosts);
+        }
+
+        for (peer, guest) in self.guests.list().await? {
+            if peer == uuid {
+                continue;
+            }
+            let Some(ref spec) = guest.spec else {
+                continue;
+            };
+            if spec.group != group || spec.name.is_empty() {
+                continue;
+            }
+            let status = guest.state.as_ref().map(|x| x.status()).unwrap_or_default();
+            if matches!(status, GuestStatus::Destroying | GuestStatus::Destroyed) {
+                continue;
+            }
+            // peers may not have started yet, reserve their addresses now so the names resolve
+            let network = self.network.assign(peer).await?;
+            hosts.push(GuestLaunchNetworkHost {
+                name: spec.name.clone(),
+                ipv4: network.ipv4,
+                ipv6: network.ipv6,
+            });
+        }
+        Ok(hosts)
+    }
+
+    async fn resolve_volumes(
+        &self,
+        uuid: Uuid,
+        specs: &[GuestVolumeSpec],
+    ) -> Result<Vec<GuestLaunchVolume>> {
+        let mut volumes = Vec::new();
+        if specs.is_empty() {
+            return Ok(volumes);
+        }
+
+        let guests = self.guests.list().await?;
+        for spec in specs {
+            if spec.path.is_empty() || !spec.path.starts_with('/') {
+                return Err(anyhow!(
+                    "volume {} must be mounted at an absolute path",
+                    spec.volume
+                ));
+            }
+
+            let Some((volume_uuid, volume)) = self.volumes.resolve(&spec.volume).await? else {
+                return Err(anyhow!("volume {} not found", spec.volume));
+            };
+            let name = volume.spec.map(|x| x.name).unwrap_or_default();
+
+            for (other_uuid, other) in &guests {
+                if *other_uuid == uuid {
+                    continue;
+                }
+                let status = other.state.as_ref().map(|x| x.status()).unwrap_or_default();
+                if matches!(status, GuestStatus::Exited | GuestStatus::Destroyed) {
+                    continue;
+                }
+                let Some(ref other_spec) = other.spec else {
+                    continue;
+                };
+                if other_spec
+                    .volumes
+                    .iter()
+                    .any(|x| x.volume == volume.id || (!name.is_empty() && x.volume == name))
+                {
+                    return Err(anyhow!(
+                        "volume {} is already attached to guest {}",
+                        spec.volume,
+                        other.id
+                    ));
+                }
+            }
+
+            let image = self.volumes.image_path(volume_uuid);
+            let image = image
+                .to_str()
+                .ok_or_else(|| anyhow!("failed to convert volume image path to string"))?;
+            volumes.push(GuestLaunchVolume {
+                image: image.to_string(),
+                path: spec.path.clone(),
+                read_only: spec.read_only,
+            });
+        }
+        Ok(volumes)
+    }
+
+    async fn validate_ports(&self, uuid: Uuid, ports: &[GuestPortSpec]) -> Result<()> {
+        if ports.is_empty() {
+            return Ok(());
+        }
+
+        for (index, port) in ports.iter().enumerate() {
+            if port.host_port == 0 || port.host_port > u16::MAX as u32 {
+                return Err(anyhow!("invalid host port {}", port.host_port));
+            }
+            if port.guest_port == 0 || port.guest_port > u16::MAX as u32 {
+                return Err(anyhow!("invalid guest port {}", port.guest_port));
+            }
+            if ports[..index]
+                .iter()
+                .any(|x| x.host_port == port.host_port && x.protocol == port.protocol)
+            {
+                return Err(anyhow!("host port {} is specified twice", port.host_port));
+            }
+        }
+
+        let guests = self.guests.list().await?;
+        for (other_uuid, other) in &guests {
+            if *other_uuid == uuid {
+                continue;
+            }
+            let status = other.state.as_ref().map(|x| x.status()).unwrap_or_default();
+            if matches!(status, GuestStatus::Exited | GuestStatus::Destroyed) {
+                continue;
+            }
+            let Some(ref other_spec) = other.spec else {
+                continue;
+            };
+            for port in ports {
+                if other_spec
+                    .ports
+                    .iter()
+                    .any(|x| x.host_port == port.host_port && x.protocol == port.protocol)
+                {
+                    return Err(anyhow!(
+                        "host port {} is already forwarded to guest {}",
+                        port.host_port,
+                        other.id
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn exited(&self, uuid: Uuid, guest: &mut Guest) -> Result<GuestReconcilerResult> {
+        let policy = guest
+            .spec
+            .as_ref()
+            .and_then(|x| x.restart_policy.clone())
+            .unwrap_or_default();
+        let Some(ref mut state) = guest.state else {
+            return Ok(GuestReconcilerResult::Unchanged);
+        };
+
+        let code = state.exit_info.as_ref().map(|x| x.code).unwrap_or_default();
+        let mut restart_info = state.restart_info.clone().unwrap_or_default();
+        let now = unix_time_millis()?;
+        let uptime = now.saturating_sub(restart_info.restart_at as u128);
+        if restart_info.count > 0 && uptime >= RESTART_STABLE_SECONDS as u128 * 1000 {
+            debug!(
+                "guest {} was stable for {}s, resetting restart count",
+                uuid,
+                uptime / 1000
+            );
+            restart_info.count = 0;
+        }
+        let restart = match policy.mode() {
+            GuestRestartPolicyMode::Never => false,
+            GuestRestartPolicyMode::OnFailure => {
+                code != 0 && (policy.max_retries == 0 || restart_info.count < policy.max_retries)
+            }
+            GuestRestartPolicyMode::Always => true,
+        };
+
+        if !restart {
             state.set_status(GuestStatus::Destroying);
-            Ok(GuestReconcilerResult::Changed { rerun: true })
-        } else {
-            Ok(GuestReconcilerResult::Unchanged)
+            return Ok(GuestReconcilerResult::Changed { rerun: true });
+        }
+
+        self.destroy_runtime(uuid).await;
+
+        let backoff = restart_backoff(&policy, restart_info.count);
+        restart_info.count += 1;
+        restart_info.last_exit_code = code;
+        restart_info.restart_at = (now + backoff.as_millis()) as u64;
+        info!(
+            "restarting guest {} after exit code {} (restart {}, backoff {:?})",
+            uuid, code, restart_info.count, backoff
+        );
+        guest.state = Some(GuestState {
+            status: GuestStatus::Starting.into(),
+            network: None,
+            exit_info: None,
+            error_info: None,
+            domid: u32::MAX,
+            restart_info: Some(restart_info),
+            health_info: None,
+            stop_info: None,
+            oom_kills: 0,
+            resources: None,
+            image_digest: String::new(),
+            domain_paused: false,
+        });
+        Ok(GuestReconcilerResult::Changed { rerun: true })
+    }
+
+    async fn started(&self, uuid: Uuid, guest: &mut Guest) -> Result<GuestReconcilerResult> {
+        let Some(ref mut state) = guest.state else {
+            return Ok(GuestReconcilerResult::Unchanged);
+        };
+        if !state.domain_paused {
+            return Ok(GuestReconcilerResult::Unchanged);
+        }
+        if self.runtime.resolve(uuid).await?.is_none() {
+            return Ok(domain_missing(uuid, state));
+        }
+        match self.runtime.resume(uuid).await {
+            Ok(()) => {
+                state.domain_paused = false;
+                state.error_info = None;
+            }
+            Err(error) => {
+                warn!("failed to resume guest {}: {}", uuid, error);
+                state.set_status(GuestStatus::Paused);
+                state.error_info = Some(GuestErrorInfo {
+                    message: format!("failed to resume guest: {}", error),
+                });
+            }
+        }
+        Ok(GuestReconcilerResult::Changed { rerun: false })
+    }
+
+    async fn paused(&self, uuid: Uuid, guest: &mut Guest) -> Result<GuestReconcilerResult> {
+        let Some(ref mut state) = guest.state else {
+            return Ok(GuestReconcilerResult::Unchanged);
+        };
+        if state.domain_paused {
+            return Ok(GuestReconcilerResult::Unchanged);
+        }
+        if self.runtime.resolve(uuid).await?.is_none() {
+            return Ok(domain_missing(uuid, state));
+        }
+        match self.runtime.pause(uuid).await {
+            Ok(()) => {
+                state.domain_paused = true;
+                state.error_info = None;
+            }
+            Err(error) => {
+                warn!("failed to pause guest {}: {}", uuid, error);
+                state.set_status(GuestStatus::Started);
+                state.error_info = Some(GuestErrorInfo {
+                    message: format!("failed to pause guest: {}", error),
+                });
+            }
+        }
+        Ok(GuestReconcilerResult::Changed { rerun: false })
+    }
+
+    async fn stopping(&self, uuid: Uuid, guest: &mut Guest) -> Result<GuestReconcilerResult> {
+        let Some(ref mut state) = guest.state else {
+            return Ok(GuestReconcilerResult::Unchanged);
+        };
+
+        let deadline = state.stop_info.as_ref().map(|x| x.deadline).unwrap_or(0);
+        let now = unix_time_millis()? as u64;
+        if now >= deadline {
+            info!(
+                "guest {} did not stop within the grace period, destroying",
+                uuid
+            );
+            state.set_status(GuestStatus::Destroying);
+            return Ok(GuestReconcilerResult::Changed { rerun: true });
+        }
+
+        let notify = self.guest_reconciler_notify.clone();
+        tokio::task::spawn(async move {
+            sleep(Duration::from_millis(deadline - now)).await;
+            let _ = notify.send(uuid).await;
+        });
+        Ok(GuestReconcilerResult::Unchanged)
+    }
+
+    /// Destroys the domain of a guest if it still exists.
+    async fn destroy_runtime(&self, uuid: Uuid) {
+        match self.runtime.resolve(uuid).await {
+            Ok(Some(_)) => {
+                if let Err(error) = self.runtime.destroy(uuid).await {
+                    warn!("failed to destroy runtime guest {}: {}", uuid, error);
+                }
+            }
+            Ok(None) => {}
+            Err(error) => {
+                warn!("failed to resolve runtime guest {}: {}", uuid, error);
+            }
         }
     }
 
     async fn destroy(&self, uuid: Uuid, guest: &mut Guest) -> Result<GuestReconcilerResult> {
-        if let Err(error) = self.runtime.destroy(uuid).await {
-            trace!("failed to destroy runtime guest {}: {}", uuid, error);
+        self.destroy_runtime(uuid).await;
+
+        if let Err(error) = self.network.release(uuid).await {
+            warn!("failed to release network for guest {}: {}", uuid, error);
         }
+        self.registry.release(uuid).await;
 
         info!("destroyed guest {}", uuid);
         guest.state = Some(GuestState {
@@ -288,6 +681,21 @@ impl GuestReconciler {
             exit_info: None,
             error_info: None,
             domid: guest.state.as_ref().map(|x| x.domid).unwrap_or(u32::MAX),
+            restart_info: guest.state.as_ref().and_then(|x| x.restart_info.clone()),
+            health_info: None,
+            stop_info: None,
+            oom_kills: guest
+                .state
+                .as_ref()
+                .map(|x| x.oom_kills)
+                .unwrap_or_default(),
+            resources: None,
+            image_digest: guest
+                .state
+                .as_ref()
+                .map(|x| x.image_digest.clone())
+                .unwrap_or_default(),
+            domain_paused: false,
         });
         Ok(GuestReconcilerResult::Changed { rerun: false })
     }
@@ -332,6 +740,17 @@ impl GuestReconciler {
     }
 }
 
+fn domain_missing(uuid: Uuid, state: &mut GuestState) -> GuestReconcilerResult {
+    warn!(
+        "guest {} domain no longer exists, treating it as exited",
+        uuid
+    );
+    state.set_status(GuestStatus::Exited);
+    state.domain_paused = false;
+    state.exit_info = Some(GuestExitInfo { code: -1 });
+    GuestReconcilerResult::Changed { rerun: true }
+}
+
 fn empty_vec_optional<T>(value: Vec<T>) -> Option<Vec<T>> {
     if value.is_empty() {
         None
@@ -340,6 +759,160 @@ fn empty_vec_optional<T>(value: Vec<T>) -> Option<Vec<T>> {
     }
 }
 
+fn restart_backoff(policy: &GuestRestartPolicy, count: u32) -> Duration {
+    let max_backoff = if policy.max_backoff_seconds == 0 {
+        DEFAULT_MAX_RESTART_BACKOFF_SECONDS
+    } else {
+        policy.max_backoff_seconds
+    };
+    let backoff = policy
+        .backoff_seconds
+        .max(MIN_RESTART_BACKOFF_SECONDS)
+        .saturating_mul(1u64 << count.min(16))
+        .min(max_backoff);
+    Duration::from_secs(backoff)
+}
+
+fn unix_time_millis() -> Result<u128> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis())
+}
+
+fn resolve_mounts(
+    config: &DaemonMountsConfig,
+    specs: &[GuestMountSpec],
+) -> Result<Vec<GuestLaunchMount>> {
+    let mut mounts = Vec::new();
+    for spec in specs {
+        if !spec.guest_path.starts_with('/') {
+            return Err(anyhow!(
+                "mount guest path {} must be absolute",
+                spec.guest_path
+            ));
+        }
+        let host_path = Path::new(&spec.host_path);
+        if !host_path.is_absolute() {
+            return Err(anyhow!(
+                "mount host path {} must be absolute",
+                spec.host_path
+            ));
+        }
+        if !host_path.is_dir() {
+            return Err(anyhow!(
+                "mount host path {} is not a directory",
+                spec.host_path
+            ));
+        }
+        // resolve symlinks and .. components before checking the allowlist
+        let host_path = host_path.canonicalize()?;
+        if !config
+            .allowed_paths
+            .iter()
+            .any(|allowed| host_path.starts_with(allowed))
+        {
+            return Err(anyhow!(
+                "mount host path {} is not inside any of the allowed mount paths",
+                spec.host_path
+            ));
+        }
+        mounts.push(GuestLaunchMount {
+            host_path: host_path.to_string_lossy().to_string(),
+            guest_path: spec.guest_path.clone(),
+            read_only: spec.read_only,
+        });
+    }
+    Ok(mounts)
+}
+
+fn resolve_healthcheck(spec: &GuestHealthCheckSpec) -> Result<LaunchHealthCheck> {
+    let port = |port: u32| -> Result<u16> {
+        match u16::try_from(port) {
+            Ok(port) if port != 0 => Ok(port),
+            _ => Err(anyhow!("health check port {} is invalid", port)),
+        }
+    };
+
+    let kind = match spec.check {
+        Some(Check::Command(ref command)) => {
+            if command.command.is_empty() {
+                return Err(anyhow!("health check command is empty"));
+            }
+            LaunchHealthCheckKind::Command {
+                command: command.command.clone(),
+            }
+        }
+
+        Some(Check::Tcp(ref tcp)) => LaunchHealthCheckKind::Tcp {
+            port: port(tcp.port)?,
+        },
+
+        Some(Check::Http(ref http)) => LaunchHealthCheckKind::Http {
+            port: port(http.port)?,
+            path: if http.path.is_empty() {
+                "/".to_string()
+            } else {
+                http.path.clone()
+            },
+        },
+
+        None => return Err(anyhow!("health check did not specify a check")),
+    };
+
+    Ok(LaunchHealthCheck {
+        kind,
+        interval_seconds: non_zero_or(spec.interval_seconds, DEFAULT_HEALTH_CHECK_INTERVAL_SECONDS),
+        timeout_seconds: non_zero_or(spec.timeout_seconds, DEFAULT_HEALTH_CHECK_TIMEOUT_SECONDS),
+        retries: if spec.retries == 0 {
+            DEFAULT_HEALTH_CHECK_RETRIES
+        } else {
+            spec.retries
+        },
+        start_period_seconds: spec.start_period_seconds,
+    })
+}
+
+fn resolve_resources(spec: &GuestTaskResources, vcpus: u32, mem: u64) -> Result<LaunchResources> {
+    if spec.memory_max > mem * 1024 * 1024 {
+        return Err(anyhow!(
+            "task memory limit of {} bytes exceeds guest memory of {} MB",
+            spec.memory_max,
+            mem
+        ));
+    }
+
+    if spec.cpu_millis as u64 > vcpus as u64 * 1000 {
+        return Err(anyhow!(
+            "task cpu limit of {} millis exceeds {} guest vcpus",
+            spec.cpu_millis,
+            vcpus
+        ));
+    }
+
+    if spec.io_weight > 10000 {
+        return Err(anyhow!(
+            "task io weight {} is outside of the range 1-10000",
+            spec.io_weight
+        ));
+    }
+
+    let non_zero = |value: u64| if value == 0 { None } else { Some(value) };
+    Ok(LaunchResources {
+        pids_max: non_zero(spec.pids_max),
+        memory_max: non_zero(spec.memory_max),
+        cpu_millis: non_zero(spec.cpu_millis as u64).map(|x| x as u32),
+        io_weight: non_zero(spec.io_weight as u64).map(|x| x as u16),
+        io_read_bps: non_zero(spec.io_read_bps),
+        io_write_bps: non_zero(spec.io_write_bps),
+    })
+}
+
+fn non_zero_or(value: u64, default: u64) -> u64 {
+    if value == 0 {
+        default
+    } else {
+        value
+    }
+}
+
 fn guestinfo_to_networkstate(info: &GuestInfo) -> GuestNetworkState {
     GuestNetworkState {
         guest_ipv4: info.guest_ipv4.map(|x| x.to_string()).unwrap_or_default(),
@@ -350,3 +923,59 @@ fn guestinfo_to_networkstate(info: &GuestInfo) -> GuestNetworkState {
         gateway_mac: info.gateway_mac.as_ref().cloned().unwrap_or_default(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff_seconds: u64, max_backoff_seconds: u64) -> GuestRestartPolicy {
+        GuestRestartPolicy {
+            backoff_seconds,
+            max_backoff_seconds,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn restart_backoff_doubles_per_restart() {
+        let policy = policy(2, 60);
+        let backoffs = (0..6)
+            .map(|count| restart_backoff(&policy, count).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, vec![2, 4, 8, 16, 32, 60]);
+    }
+
+    #[test]
+    fn restart_backoff_enforces_the_minimum() {
+        let policy = policy(0, 60);
+        assert_eq!(
+            restart_backoff(&policy, 0).as_secs(),
+            MIN_RESTART_BACKOFF_SECONDS
+        );
+        assert_eq!(
+            restart_backoff(&policy, 3).as_secs(),
+            MIN_RESTART_BACKOFF_SECONDS * 8
+        );
+    }
+
+    #[test]
+    fn restart_backoff_defaults_the_maximum() {
+        let policy = policy(10, 0);
+        assert_eq!(restart_backoff(&policy, 4).as_secs(), 160);
+        assert_eq!(
+            restart_backoff(&policy, 5).as_secs(),
+            DEFAULT_MAX_RESTART_BACKOFF_SECONDS
+        );
+    }
+
+    #[test]
+    fn restart_backoff_does_not_overflow() {
+        let unbounded = policy(u64::MAX, u64::MAX);
+        assert_eq!(restart_backoff(&unbounded, u32::MAX).as_secs(), u64::MAX);
+        let defaulted = policy(1, 0);
+        assert_eq!(
+            restart_backoff(&defaulted, u32::MAX).as_secs(),
+            DEFAULT_MAX_RESTART_BACKOFF_SECONDS
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use krata::v1::control::RegistryAuth;
+use krataoci::{
+    auth::{OciCredentialStore, OciRegistryAuth},
+    name::ImageName,
+};
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::config::DaemonRegistryConfig;
+
+/// Registry credentials used when pulling guest images. Credentials passed along
+/// with a request are held in memory for the lifetime of the guest and never persisted,
+/// so a guest relaunched by its restart policy after the daemon restarts pulls with
+/// `registry.auth_file` only. Guests that must survive a daemon restart should take
+/// their credentials from there.
+#[derive(Clone)]
+pub struct DaemonRegistryCredentials {
+    auth_file: Arc<RwLock<Option<PathBuf>>>,
+    guests: Arc<Mutex<HashMap<Uuid, OciRegistryAuth>>>,
+}
+
+impl DaemonRegistryCredentials {
+    pub fn new(config: DaemonRegistryConfig) -> DaemonRegistryCredentials {
+        DaemonRegistryCredentials {
+            auth_file: Arc::new(RwLock::new(config.auth_file)),
+            guests: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn update_config(&self, config: DaemonRegistryConfig) {
+        *self.auth_file.write().await = config.auth_file;
+    }
+
+    pub async fn store(&self) -> OciCredentialStore {
+        OciCredentialStore::with_docker_config(self.auth_file.read().await.clone())
+    }
+
+    pub async fn set_guest_auth(&self, uuid: Uuid, auth: OciRegistryAuth) {
+        let mut guests = self.guests.lock().await;
+        if auth == OciRegistryAuth::Anonymous {
+            guests.remove(&uuid);
+        } else {
+            guests.insert(uuid, auth);
+        }
+    }
+
+    pub async fn release(&self, uuid: Uuid) {
+        self.guests.lock().await.remove(&uuid);
+    }
+
+    /// Credentials for pulling `image` on behalf of a guest, preferring the ones it was created with.
+    pub async fn guest_store(&self, uuid: Uuid, image: &str) -> Result<OciCredentialStore> {
+        let auth = self
+            .guests
+            .lock()
+            .await
+            .get(&uuid)
+            .cloned()
+            .unwrap_or_default();
+        self.request_store(image, auth).await
+    }
+
+    /// Credentials for pulling `image`, preferring `auth` unless it is anonymous.
+    pub async fn request_store(
+        &self,
+        image: &str,
+        auth: OciRegistryAuth,
+    ) -> Result<OciCredentialStore> {
+        let mut store = self.store().await;
+        if auth != OciRegistryAuth::Anonymous {
+            let image = ImageName::parse(image)?;
+            store.insert(&image.credential_host(), auth);
+        }
+        Ok(store)
+    }
+}
+
+pub fn registry_auth_from_proto(auth: Option<&RegistryAuth>) -> OciRegistryAuth {
+    let Some(auth) = auth else {
+        return OciRegistryAuth::Anonymous;
+    };
+    if !auth.registry_token.is_empty() {
+        OciRegistryAuth::RegistryToken(auth.registry_token.clone())
+    } else if !auth.identity_token.is_empty() {
+        OciRegistryAuth::IdentityToken(auth.identity_token.clone())
+    } else if !auth.username.is_empty() {
+        OciRegistryAuth::Basic {
+            username: auth.username.clone(),
+            password: auth.password.clone(),
+        }
+    } else {
+        OciRegistryAuth::Anonymous
+    }
+}
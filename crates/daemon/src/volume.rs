@@ -0,0 +1,113 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use krata::v1::common::{Volume, VolumeSpec};
+use log::{info, warn};
+use tokio::{fs, process::Command};
+use uuid::Uuid;
+
+use crate::db::VolumeStore;
+
+#[derive(Clone)]
+pub struct DaemonVolumes {
+    path: PathBuf,
+    volumes: VolumeStore,
+}
+
+impl DaemonVolumes {
+    pub async fn new(path: PathBuf, volumes: VolumeStore) -> Result<Self> {
+        fs::create_dir_all(&path).await?;
+        Ok(DaemonVolumes { path, volumes })
+    }
+
+    pub fn image_path(&self, uuid: Uuid) -> PathBuf {
+        self.path.join(format!("{}.img", uuid))
+    }
+
+    pub async fn list(&self) -> Result<Vec<Volume>> {
+        Ok(self.volumes.list().await?.into_values().collect())
+    }
+
+    pub async fn resolve(&self, name_or_id: &str) -> Result<Option<(Uuid, Volume)>> {
+        if let Ok(uuid) = Uuid::from_str(name_or_id) {
+            if let Some(volume) = self.volumes.read(uuid).await? {
+                return Ok(Some((uuid, volume)));
+            }
+        }
+
+        Ok(self.volumes.list().await?.into_iter().find(|(_, volume)| {
+            volume
+                .spec
+                .as_ref()
+                .map(|spec| spec.name == name_or_id)
+                .unwrap_or(false)
+        }))
+    }
+
+    pub async fn create(&self, spec: VolumeSpec) -> Result<Uuid> {
+        if spec.name.is_empty() {
+            return Err(anyhow!("volume name must not be empty"));
+        }
+
+        if spec.size == 0 {
+            return Err(anyhow!("volume size must be greater than zero"));
+        }
+
+        if self.resolve(&spec.name).await?.is_some() {
+            return Err(anyhow!("volume {} already exists", spec.name));
+        }
+
+        let uuid = Uuid::new_v4();
+        let image_path = self.image_path(uuid);
+        let file = fs::File::create(&image_path).await?;
+        file.set_len(spec.size * 1024 * 1024).await?;
+        drop(file);
+
+        let output = Command::new("mkfs.ext4")
+            .arg("-q")
+            .arg("-F")
+            .arg(&image_path)
+            .output()
+            .await;
+        let output = match output {
+            Ok(output) => output,
+            Err(error) => {
+                let _ = fs::remove_file(&image_path).await;
+                return Err(anyhow!("failed to run mkfs.ext4: {}", error));
+            }
+        };
+
+        if !output.status.success() {
+            let _ = fs::remove_file(&image_path).await;
+            return Err(anyhow!(
+                "failed to format volume {}: {}",
+                spec.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("created volume {} ({})", spec.name, uuid);
+        self.volumes
+            .update(
+                uuid,
+                Volume {
+                    id: uuid.to_string(),
+                    spec: Some(spec),
+                },
+            )
+            .await?;
+        Ok(uuid)
+    }
+
+    pub async fn destroy(&self, uuid: Uuid) -> Result<()> {
+        let image_path = self.image_path(uuid);
+        if image_path.exists() {
+            if let Err(error) = fs::remove_file(&image_path).await {
+                warn!("failed to remove volume image {:?}: {}", image_path, error);
+            }
+        }
+        self.volumes.remove(uuid).await?;
+        info!("destroyed volume {}", uuid);
+        Ok(())
+    }
+}
@@ -1,41 +1,92 @@
+use std::{collections::HashMap, time::Duration};
+
 use crate::{
     childwait::{ChildEvent, ChildWait},
     death,
+    exec::GuestExec,
+    health::{GuestChildWaiters, GuestHealthCheck},
     metrics::MetricsCollector,
+    resources::GuestTaskResources,
 };
 use anyhow::Result;
 use cgroups_rs::Cgroup;
-use krata::idm::{
-    client::IdmClient,
-    protocol::{
-        idm_event::Event, idm_request::Request, idm_response::Response, IdmEvent, IdmExitEvent,
-        IdmMetricsResponse, IdmPingResponse, IdmRequest,
+use krata::{
+    idm::{
+        client::IdmClient,
+        protocol::{
+            idm_event::Event, idm_exec_request::Request as ExecRequest, idm_request::Request,
+            idm_response::Response, IdmEvent, IdmExecRequest, IdmExecResponse, IdmExitEvent,
+            IdmMetricsResponse, IdmPingResponse, IdmRequest, IdmShutdownResponse,
+        },
     },
+    launchcfg::LaunchHealthCheck,
+};
+use log::{debug, warn};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
 };
-use log::debug;
-use nix::unistd::Pid;
-use tokio::{select, sync::broadcast};
+use tokio::{select, sync::broadcast, task::JoinHandle, time::timeout};
+
+const OUTPUT_DRAIN_TIMEOUT_MS: u64 = 1000;
 
 pub struct GuestBackground {
     idm: IdmClient,
     child: Pid,
-    _cgroup: Cgroup,
+    cgroup: Cgroup,
     wait: ChildWait,
+    exec_env: HashMap<String, String>,
+    working_dir: String,
+    execs: HashMap<u64, GuestExec>,
+    next_exec_id: u64,
+    output: Vec<JoinHandle<()>>,
+    healthcheck: Option<LaunchHealthCheck>,
+    waiters: GuestChildWaiters,
+    stop_signal: Signal,
 }
 
 impl GuestBackground {
-    pub async fn new(idm: IdmClient, cgroup: Cgroup, child: Pid) -> Result<GuestBackground> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        idm: IdmClient,
+        cgroup: Cgroup,
+        child: Pid,
+        exec_env: HashMap<String, String>,
+        working_dir: String,
+        output: Vec<JoinHandle<()>>,
+        healthcheck: Option<LaunchHealthCheck>,
+        stop_signal: Signal,
+    ) -> Result<GuestBackground> {
         Ok(GuestBackground {
             idm,
             child,
-            _cgroup: cgroup,
+            cgroup,
             wait: ChildWait::new()?,
+            exec_env,
+            working_dir,
+            execs: HashMap::new(),
+            next_exec_id: 0,
+            output,
+            healthcheck,
+            waiters: GuestChildWaiters::default(),
+            stop_signal,
         })
     }
 
     pub async fn run(&mut self) -> Result<()> {
         let mut event_subscription = self.idm.subscribe().await?;
         let mut requests_subscription = self.idm.requests().await?;
+        let healthcheck = self.healthcheck.take().map(|check| {
+            GuestHealthCheck::new(
+                self.idm.clone(),
+                check,
+                self.exec_env.clone(),
+                self.working_dir.clone(),
+                self.waiters.clone(),
+            )
+            .launch()
+        });
+        let oom = GuestTaskResources::monitor_oom(self.idm.clone());
         loop {
             select! {
                 x = event_subscription.recv() => match x {
@@ -76,6 +127,10 @@ impl GuestBackground {
                 }
             };
         }
+        if let Some(healthcheck) = healthcheck {
+            healthcheck.abort();
+        }
+        oom.abort();
         Ok(())
     }
 
@@ -97,13 +152,111 @@ impl GuestBackground {
                 self.idm.respond(id, Response::Metrics(response)).await?;
             }
 
+            Some(Request::Exec(exec)) => {
+                let response = self.handle_exec_request(exec).await;
+                self.idm.respond(id, Response::Exec(response)).await?;
+            }
+
+            Some(Request::Shutdown(_)) => {
+                debug!("delivering {} to guest task", self.stop_signal);
+                if let Err(error) = kill(self.child, self.stop_signal) {
+                    warn!("failed to signal guest task: {}", error);
+                }
+                self.idm
+                    .respond(id, Response::Shutdown(IdmShutdownResponse {}))
+                    .await?;
+            }
+
             None => {}
         }
         Ok(())
     }
 
+    async fn handle_exec_request(&mut self, request: IdmExecRequest) -> IdmExecResponse {
+        match request.request {
+            Some(ExecRequest::Start(start)) => {
+                let id = self.next_exec_id;
+                self.next_exec_id = self.next_exec_id.wrapping_add(1);
+                match GuestExec::spawn(
+                    self.idm.clone(),
+                    &self.cgroup,
+                    id,
+                    start,
+                    &self.exec_env,
+                    &self.working_dir,
+                ) {
+                    Ok(exec) => {
+                        self.execs.insert(id, exec);
+                        IdmExecResponse {
+                            exec_id: id,
+                            error: String::new(),
+                        }
+                    }
+
+                    Err(error) => {
+                        warn!("failed to spawn exec process: {}", error);
+                        IdmExecResponse {
+                            exec_id: id,
+                            error: error.to_string(),
+                        }
+                    }
+                }
+            }
+
+            Some(ExecRequest::Stdin(stdin)) => {
+                let Some(exec) = self.execs.get_mut(&stdin.exec_id) else {
+                    return IdmExecResponse {
+                        exec_id: stdin.exec_id,
+                        error: "exec process not found".to_string(),
+                    };
+                };
+
+                let error = match exec.write_stdin(stdin.data, stdin.closed).await {
+                    Ok(()) => String::new(),
+                    Err(error) => error.to_string(),
+                };
+                IdmExecResponse {
+                    exec_id: stdin.exec_id,
+                    error,
+                }
+            }
+
+            None => IdmExecResponse {
+                exec_id: 0,
+                error: "exec request was empty".to_string(),
+            },
+        }
+    }
+
     async fn child_event(&mut self, event: ChildEvent) -> Result<()> {
+        if let Some(waiter) = self.waiters.lock().await.remove(&event.pid) {
+            let _ = waiter.send(event.status);
+            return Ok(());
+        }
+
+        if let Some(id) = self
+            .execs
+            .values()
+            .find(|exec| exec.pid == event.pid)
+            .map(|exec| exec.id)
+        {
+            if let Some(mut exec) = self.execs.remove(&id) {
+                exec.exited(event.status);
+            }
+            return Ok(());
+        }
+
         if event.pid == self.child {
+            for output in self.output.drain(..) {
+                let abort = output.abort_handle();
+                if timeout(Duration::from_millis(OUTPUT_DRAIN_TIMEOUT_MS), output)
+                    .await
+                    .is_err()
+                {
+                    abort.abort();
+                }
+            }
+
             self.idm
                 .emit(IdmEvent {
                     event: Some(Event::Exit(IdmExitEvent { code: event.status })),
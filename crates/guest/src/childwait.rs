@@ -8,7 +8,7 @@ use std::{
 };
 
 use anyhow::Result;
-use libc::{c_int, waitpid, WEXITSTATUS, WIFEXITED};
+use libc::{c_int, waitpid, WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG};
 use log::warn;
 use nix::unistd::Pid;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
@@ -63,10 +63,15 @@ impl ChildWaitTask {
             let mut status: c_int = 0;
             let pid = unsafe { waitpid(-1, addr_of_mut!(status), 0) };
 
-            if WIFEXITED(status) {
+            if WIFEXITED(status) || WIFSIGNALED(status) {
+                let code = if WIFEXITED(status) {
+                    WEXITSTATUS(status)
+                } else {
+                    128 + WTERMSIG(status)
+                };
                 let event = ChildEvent {
                     pid: Pid::from_raw(pid),
-                    status: WEXITSTATUS(status),
+                    status: code,
                 };
                 let _ = self.sender.try_send(event);
 
@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    io,
+    os::{fd::OwnedFd, raw::c_int, unix::process::CommandExt},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use cgroups_rs::{Cgroup, CgroupPid};
+use krata::idm::{
+    client::IdmClient,
+    protocol::{
+        idm_event::Event, IdmEvent, IdmExecExitEvent, IdmExecOutputEvent, IdmExecStartRequest,
+    },
+};
+use log::debug;
+use nix::{pty::openpty, unistd::Pid};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::timeout,
+};
+
+const EXEC_STDIN_QUEUE_LEN: usize = 100;
+const EXEC_OUTPUT_BUFFER_LEN: usize = 8192;
+const EXEC_OUTPUT_DRAIN_TIMEOUT_MS: u64 = 1000;
+
+pub struct GuestExec {
+    pub id: u64,
+    pub pid: Pid,
+    stdin: Option<Sender<Vec<u8>>>,
+    exit: Option<oneshot::Sender<c_int>>,
+}
+
+impl GuestExec {
+    pub fn spawn(
+        idm: IdmClient,
+        cgroup: &Cgroup,
+        id: u64,
+        request: IdmExecStartRequest,
+        env: &HashMap<String, String>,
+        working_dir: &str,
+    ) -> Result<GuestExec> {
+        let Some(program) = request.command.first() else {
+            return Err(anyhow!("exec command was not specified"));
+        };
+
+        let mut env = env.clone();
+        for var in &request.environment {
+            env.insert(var.key.clone(), var.value.clone());
+        }
+
+        let working_dir = if request.working_directory.is_empty() {
+            working_dir
+        } else {
+            &request.working_directory
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(&request.command[1..])
+            .env_clear()
+            .envs(env)
+            .current_dir(working_dir);
+
+        let (pid, stdin, stdout, stderr) = if request.tty {
+            let pty = openpty(None, None)?;
+            command
+                .stdin(Stdio::from(pty.slave.try_clone()?))
+                .stdout(Stdio::from(pty.slave.try_clone()?))
+                .stderr(Stdio::from(pty.slave));
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            let child = command.spawn()?;
+            let stdin = pty.master.try_clone()?;
+            (child.id(), stdin, pty.master, None)
+        } else {
+            command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let mut child = command.spawn()?;
+            let stdin: OwnedFd = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow!("exec stdin was not available"))?
+                .into();
+            let stdout: OwnedFd = child
+                .stdout
+                .take()
+                .ok_or_else(|| anyhow!("exec stdout was not available"))?
+                .into();
+            let stderr: OwnedFd = child
+                .stderr
+                .take()
+                .ok_or_else(|| anyhow!("exec stderr was not available"))?
+                .into();
+            (child.id(), stdin, stdout, Some(stderr))
+        };
+        drop(command);
+
+        if let Err(error) = cgroup.add_task_by_tgid(CgroupPid::from(pid as u64)) {
+            debug!("failed to add exec {} to task cgroup: {}", id, error);
+        }
+
+        let (stdin_sender, stdin_receiver) = channel(EXEC_STDIN_QUEUE_LEN);
+        tokio::task::spawn(GuestExec::process_stdin(
+            File::from_std(stdin.into()),
+            stdin_receiver,
+        ));
+
+        let mut readers = vec![GuestExec::process_output(
+            idm.clone(),
+            id,
+            File::from_std(stdout.into()),
+            false,
+        )];
+        if let Some(stderr) = stderr {
+            readers.push(GuestExec::process_output(
+                idm.clone(),
+                id,
+                File::from_std(stderr.into()),
+                true,
+            ));
+        }
+
+        let (exit_sender, exit_receiver) = oneshot::channel();
+        tokio::task::spawn(GuestExec::process_exit(idm, id, readers, exit_receiver));
+
+        Ok(GuestExec {
+            id,
+            pid: Pid::from_raw(pid as i32),
+            stdin: Some(stdin_sender),
+            exit: Some(exit_sender),
+        })
+    }
+
+    pub async fn write_stdin(&mut self, data: Vec<u8>, closed: bool) -> Result<()> {
+        if let Some(ref stdin) = self.stdin {
+            if !data.is_empty() {
+                stdin.send(data).await?;
+            }
+        }
+
+        if closed {
+            self.stdin = None;
+        }
+        Ok(())
+    }
+
+    pub fn exited(&mut self, code: c_int) {
+        self.stdin = None;
+        if let Some(exit) = self.exit.take() {
+            let _ = exit.send(code);
+        }
+    }
+
+    async fn process_stdin(mut file: File, mut receiver: Receiver<Vec<u8>>) {
+        while let Some(data) = receiver.recv().await {
+            if file.write_all(&data).await.is_err() || file.flush().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn process_output(idm: IdmClient, id: u64, mut file: File, stderr: bool) -> JoinHandle<()> {
+        tokio::task::spawn(async move {
+            let mut buffer = vec![0u8; EXEC_OUTPUT_BUFFER_LEN];
+            loop {
+                let size = match file.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(size) => size,
+                };
+                let data = buffer[0..size].to_vec();
+                let output = if stderr {
+                    IdmExecOutputEvent {
+                        exec_id: id,
+                        stdout: vec![],
+                        stderr: data,
+                    }
+                } else {
+                    IdmExecOutputEvent {
+                        exec_id: id,
+                        stdout: data,
+                        stderr: vec![],
+                    }
+                };
+                let event = IdmEvent {
+                    event: Some(Event::ExecOutput(output)),
+                };
+                if let Err(error) = idm.emit(event).await {
+                    debug!("failed to emit exec {} output: {}", id, error);
+                    break;
+                }
+            }
+        })
+    }
+
+    async fn process_exit(
+        idm: IdmClient,
+        id: u64,
+        readers: Vec<JoinHandle<()>>,
+        exit: oneshot::Receiver<c_int>,
+    ) {
+        let (code, error) = match exit.await {
+            Ok(code) => (code, String::new()),
+            Err(_) => (-1, "exec process was lost".to_string()),
+        };
+
+        for reader in readers {
+            let abort = reader.abort_handle();
+            if timeout(Duration::from_millis(EXEC_OUTPUT_DRAIN_TIMEOUT_MS), reader)
+                .await
+                .is_err()
+            {
+                abort.abort();
+            }
+        }
+
+        let event = IdmEvent {
+            event: Some(Event::ExecExit(IdmExecExitEvent {
+                exec_id: id,
+                code,
+                error,
+            })),
+        };
+        if let Err(error) = idm.emit(event).await {
+            debug!("failed to emit exec {} exit: {}", id, error);
+        }
+    }
+}
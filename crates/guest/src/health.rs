@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    os::{fd::OwnedFd, raw::c_int},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use krata::{
+    idm::{
+        client::IdmClient,
+        protocol::{idm_event::Event, IdmEvent, IdmHealthEvent, IdmHealthStatus},
+    },
+    launchcfg::{LaunchHealthCheck, LaunchHealthCheckKind},
+};
+use log::debug;
+use nix::unistd::Pid;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{oneshot, Mutex},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+const HEALTH_OUTPUT_MAX_LEN: usize = 4096;
+
+/// Exit notifications for children spawned outside of the main task, keyed by pid.
+pub type GuestChildWaiters = Arc<Mutex<HashMap<Pid, oneshot::Sender<c_int>>>>;
+
+pub struct GuestHealthCheck {
+    idm: IdmClient,
+    check: LaunchHealthCheck,
+    env: HashMap<String, String>,
+    working_dir: String,
+    waiters: GuestChildWaiters,
+}
+
+impl GuestHealthCheck {
+    pub fn new(
+        idm: IdmClient,
+        check: LaunchHealthCheck,
+        env: HashMap<String, String>,
+        working_dir: String,
+        waiters: GuestChildWaiters,
+    ) -> GuestHealthCheck {
+        GuestHealthCheck {
+            idm,
+            check,
+            env,
+            working_dir,
+            waiters,
+        }
+    }
+
+    pub fn launch(self) -> JoinHandle<()> {
+        tokio::task::spawn(async move { self.run().await })
+    }
+
+    async fn run(self) {
+        let started = Instant::now();
+        let start_period = Duration::from_secs(self.check.start_period_seconds);
+        let interval = Duration::from_secs(self.check.interval_seconds.max(1));
+        let probe_timeout = Duration::from_secs(self.check.timeout_seconds.max(1));
+        let mut status = IdmHealthStatus::Starting;
+        let mut failing_streak = 0;
+        self.emit(status, failing_streak, String::new()).await;
+
+        loop {
+            sleep(interval).await;
+            let result = self.probe(probe_timeout).await;
+
+            let (next, output) = match result {
+                Ok(output) => {
+                    failing_streak = 0;
+                    (IdmHealthStatus::Healthy, output)
+                }
+
+                Err(error) => {
+                    if status == IdmHealthStatus::Starting && started.elapsed() < start_period {
+                        continue;
+                    }
+                    failing_streak += 1;
+                    if failing_streak >= self.check.retries {
+                        (IdmHealthStatus::Unhealthy, error.to_string())
+                    } else {
+                        (status, error.to_string())
+                    }
+                }
+            };
+
+            let changed = next != status || failing_streak > 0;
+            status = next;
+            if changed {
+                self.emit(status, failing_streak, output).await;
+            }
+        }
+    }
+
+    async fn emit(&self, status: IdmHealthStatus, failing_streak: u32, output: String) {
+        let event = IdmEvent {
+            event: Some(Event::Health(IdmHealthEvent {
+                status: status.into(),
+                failing_streak,
+                output,
+            })),
+        };
+        if let Err(error) = self.idm.emit(event).await {
+            debug!("failed to emit health event: {}", error);
+        }
+    }
+
+    async fn probe(&self, probe_timeout: Duration) -> Result<String> {
+        let result = match self.check.kind {
+            LaunchHealthCheckKind::Command { ref command } => {
+                return self.probe_command(command, probe_timeout).await;
+            }
+            LaunchHealthCheckKind::Tcp { port } => {
+                timeout(probe_timeout, async {
+                    TcpStream::connect(("127.0.0.1", port)).await?;
+                    Ok(String::new())
+                })
+                .await
+            }
+            LaunchHealthCheckKind::Http { port, ref path } => {
+                timeout(probe_timeout, self.probe_http(port, path)).await
+            }
+        };
+        result.unwrap_or_else(|_| Err(anyhow!("health check timed out after {:?}", probe_timeout)))
+    }
+
+    async fn probe_command(&self, command: &[String], probe_timeout: Duration) -> Result<String> {
+        let Some(program) = command.first() else {
+            return Err(anyhow!("health check command is empty"));
+        };
+
+        let mut waiters = self.waiters.lock().await;
+        let mut child = Command::new(program)
+            .args(&command[1..])
+            .env_clear()
+            .envs(&self.env)
+            .current_dir(&self.working_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        let (sender, receiver) = oneshot::channel();
+        waiters.insert(pid, sender);
+        drop(waiters);
+
+        let stdout: Option<OwnedFd> = child.stdout.take().map(|x| x.into());
+        match timeout(
+            probe_timeout,
+            GuestHealthCheck::wait_command(stdout, receiver),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                let _ = child.kill();
+                Err(anyhow!("health check timed out after {:?}", probe_timeout))
+            }
+        }
+    }
+
+    async fn wait_command(
+        stdout: Option<OwnedFd>,
+        exit: oneshot::Receiver<c_int>,
+    ) -> Result<String> {
+        let mut output = Vec::new();
+        if let Some(stdout) = stdout {
+            let mut file = File::from_std(stdout.into());
+            let mut buffer = vec![0u8; HEALTH_OUTPUT_MAX_LEN];
+            loop {
+                let size = file.read(&mut buffer).await?;
+                if size == 0 {
+                    break;
+                }
+                let remaining = HEALTH_OUTPUT_MAX_LEN.saturating_sub(output.len());
+                output.extend_from_slice(&buffer[0..size.min(remaining)]);
+            }
+        }
+        let output = String::from_utf8_lossy(&output).trim().to_string();
+        let code = exit.await?;
+        if code == 0 {
+            Ok(output)
+        } else {
+            Err(anyhow!(
+                "health check exited with code {}: {}",
+                code,
+                output
+            ))
+        }
+    }
+
+    async fn probe_http(&self, port: u16, path: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut buffer = vec![0u8; HEALTH_OUTPUT_MAX_LEN];
+        let mut response = Vec::new();
+        while !response.contains(&b'\n') && response.len() < HEALTH_OUTPUT_MAX_LEN {
+            let size = stream.read(&mut buffer).await?;
+            if size == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[0..size]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let code = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|x| x.parse::<u16>().ok())
+            .ok_or_else(|| anyhow!("invalid http response: {}", status_line))?;
+        if (200..400).contains(&code) {
+            Ok(status_line)
+        } else {
+            Err(anyhow!("http health check failed: {}", status_line))
+        }
+    }
+}
@@ -4,10 +4,13 @@ use futures::stream::TryStreamExt;
 use ipnetwork::IpNetwork;
 use krata::ethtool::EthtoolHandle;
 use krata::idm::client::IdmClient;
-use krata::launchcfg::{LaunchInfo, LaunchNetwork};
+use krata::launchcfg::{
+    LaunchHealthCheck, LaunchInfo, LaunchMount, LaunchNetwork, LaunchResources, LaunchVolume,
+};
 use libc::{sethostname, setsid, TIOCSCTTY};
 use log::{trace, warn};
 use nix::ioctl_write_int_bad;
+use nix::sys::signal::Signal;
 use nix::unistd::{dup2, execve, fork, ForkResult, Pid};
 use oci_spec::image::{Config, ImageConfiguration};
 use path_absolutize::Absolutize;
@@ -22,9 +25,13 @@ use std::os::unix::fs::{chroot, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use sys_mount::{FilesystemType, Mount, MountFlags};
-use tokio::fs;
+use tokio::{fs, task::JoinHandle};
 
-use crate::background::GuestBackground;
+use crate::{
+    background::GuestBackground,
+    output::GuestOutputPipes,
+    resources::{GuestTaskResources, TASK_CGROUP_NAME},
+};
 
 const IMAGE_BLOCK_DEVICE_PATH: &str = "/dev/xvda";
 const CONFIG_BLOCK_DEVICE_PATH: &str = "/dev/xvdb";
@@ -87,6 +94,8 @@ impl GuestInit {
 
         self.mount_new_root().await?;
         self.bind_new_root().await?;
+        self.mount_volumes(&launch.volumes).await?;
+        self.mount_filesystems(&launch.mounts).await?;
 
         if let Some(hostname) = launch.hostname.clone() {
             let result = unsafe {
@@ -287,6 +296,41 @@ impl GuestInit {
         Ok(())
     }
 
+    async fn mount_volumes(&mut self, volumes: &[LaunchVolume]) -> Result<()> {
+        for volume in volumes {
+            trace!("mounting volume {} to {}", volume.device, volume.path);
+            fs::create_dir_all(&volume.path).await?;
+            let flags = if volume.read_only {
+                MountFlags::RDONLY
+            } else {
+                MountFlags::empty()
+            };
+            Mount::builder()
+                .fstype(FilesystemType::Manual("ext4"))
+                .flags(flags)
+                .mount(&volume.device, &volume.path)?;
+        }
+        Ok(())
+    }
+
+    async fn mount_filesystems(&mut self, mounts: &[LaunchMount]) -> Result<()> {
+        for mount in mounts {
+            trace!("mounting 9p filesystem {} to {}", mount.tag, mount.path);
+            fs::create_dir_all(&mount.path).await?;
+            let flags = if mount.read_only {
+                MountFlags::RDONLY
+            } else {
+                MountFlags::empty()
+            };
+            Mount::builder()
+                .fstype(FilesystemType::Manual("9p"))
+                .flags(flags)
+                .data("trans=xen,version=9p2000.L")
+                .mount(&mount.tag, &mount.path)?;
+        }
+        Ok(())
+    }
+
     async fn network_setup(&mut self, network: &LaunchNetwork) -> Result<()> {
         trace!("setting up network for link");
 
@@ -303,11 +347,35 @@ impl GuestInit {
         let mut conf = lines.join("\n");
         conf.push('\n');
         fs::write(resolv, conf).await?;
+        self.network_configure_hosts(network).await?;
         self.network_configure_ethtool(network).await?;
         self.network_configure_link(network).await?;
         Ok(())
     }
 
+    async fn network_configure_hosts(&mut self, network: &LaunchNetwork) -> Result<()> {
+        if network.hosts.is_empty() {
+            return Ok(());
+        }
+
+        let hosts = PathBuf::from_str("/etc/hosts")?;
+        let mut content = if hosts.exists() {
+            fs::read_to_string(&hosts).await?
+        } else {
+            "127.0.0.1 localhost\n::1 localhost\n".to_string()
+        };
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str("# krata guest group\n");
+        for host in &network.hosts {
+            content.push_str(&format!("{} {}\n", host.ipv4, host.name));
+            content.push_str(&format!("{} {}\n", host.ipv6, host.name));
+        }
+        fs::write(hosts, content).await?;
+        Ok(())
+    }
+
     async fn network_configure_link(&mut self, network: &LaunchNetwork) -> Result<()> {
         let (connection, handle, _) = rtnetlink::new_connection()?;
         tokio::spawn(connection);
@@ -447,21 +515,61 @@ impl GuestInit {
             working_dir = "/".to_string();
         }
 
-        let cgroup = self.init_cgroup().await?;
-        self.fork_and_exec(idm, cgroup, working_dir, path, cmd, env)
-            .await?;
+        let stop_signal = GuestInit::resolve_stop_signal(config);
+        let cgroup = self.init_cgroup(&launch.resources).await?;
+        let output = if launch.tty {
+            None
+        } else {
+            Some(GuestOutputPipes::new()?)
+        };
+        self.fork_and_exec(
+            idm,
+            cgroup,
+            working_dir,
+            path,
+            cmd,
+            env,
+            output,
+            launch.healthcheck.clone(),
+            stop_signal,
+        )
+        .await?;
         Ok(())
     }
 
-    async fn init_cgroup(&self) -> Result<Cgroup> {
+    async fn init_cgroup(&self, limits: &LaunchResources) -> Result<Cgroup> {
         trace!("initializing cgroup");
         let hierarchy = cgroups_rs::hierarchies::auto();
-        let cgroup = Cgroup::new(hierarchy, "krata-guest-task")?;
-        cgroup.set_cgroup_type("threaded")?;
+        let cgroup = Cgroup::new(hierarchy, TASK_CGROUP_NAME)?;
+        GuestTaskResources::new(limits.clone())
+            .apply(&cgroup)
+            .await?;
         trace!("initialized cgroup");
         Ok(cgroup)
     }
 
+    fn resolve_stop_signal(config: &Config) -> Signal {
+        let Some(value) = config.stop_signal() else {
+            return Signal::SIGTERM;
+        };
+
+        let signal = match value.parse::<i32>() {
+            Ok(number) => Signal::try_from(number).ok(),
+            Err(_) => {
+                let name = value.to_uppercase();
+                if name.starts_with("SIG") {
+                    Signal::from_str(&name).ok()
+                } else {
+                    Signal::from_str(&format!("SIG{}", name)).ok()
+                }
+            }
+        };
+        signal.unwrap_or_else(|| {
+            warn!("unknown stop signal '{}', using SIGTERM", value);
+            Signal::SIGTERM
+        })
+    }
+
     fn strings_as_cstrings(values: Vec<String>) -> Result<Vec<CString>> {
         let mut results: Vec<CString> = vec![];
         for value in values {
@@ -507,6 +615,7 @@ impl GuestInit {
             .collect::<Vec<String>>()
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn fork_and_exec(
         &mut self,
         idm: IdmClient,
@@ -515,10 +624,38 @@ impl GuestInit {
         path: CString,
         cmd: Vec<CString>,
         env: Vec<CString>,
+        output: Option<GuestOutputPipes>,
+        healthcheck: Option<LaunchHealthCheck>,
+        stop_signal: Signal,
     ) -> Result<()> {
         match unsafe { fork()? } {
-            ForkResult::Parent { child } => self.background(idm, cgroup, child).await,
-            ForkResult::Child => self.foreground(cgroup, working_dir, path, cmd, env).await,
+            ForkResult::Parent { child } => {
+                let output = output
+                    .map(|output| output.capture(idm.clone()))
+                    .unwrap_or_default();
+                let exec_env = env
+                    .iter()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .collect::<Vec<_>>();
+                let exec_env = GuestInit::env_map(&exec_env);
+                self.background(
+                    idm,
+                    cgroup,
+                    child,
+                    exec_env,
+                    working_dir,
+                    output,
+                    healthcheck,
+                    stop_signal,
+                )
+                .await
+            }
+            ForkResult::Child => {
+                if let Some(output) = output {
+                    output.attach()?;
+                }
+                self.foreground(cgroup, working_dir, path, cmd, env).await
+            }
         }
     }
 
@@ -532,7 +669,7 @@ impl GuestInit {
     ) -> Result<()> {
         GuestInit::set_controlling_terminal()?;
         std::env::set_current_dir(working_dir)?;
-        cgroup.add_task(CgroupPid::from(std::process::id() as u64))?;
+        cgroup.add_task_by_tgid(CgroupPid::from(std::process::id() as u64))?;
         execve(&path, &cmd, &env)?;
         Ok(())
     }
@@ -545,8 +682,29 @@ impl GuestInit {
         Ok(())
     }
 
-    async fn background(&mut self, idm: IdmClient, cgroup: Cgroup, executed: Pid) -> Result<()> {
-        let mut background = GuestBackground::new(idm, cgroup, executed).await?;
+    #[allow(clippy::too_many_arguments)]
+    async fn background(
+        &mut self,
+        idm: IdmClient,
+        cgroup: Cgroup,
+        executed: Pid,
+        exec_env: HashMap<String, String>,
+        working_dir: String,
+        output: Vec<JoinHandle<()>>,
+        healthcheck: Option<LaunchHealthCheck>,
+        stop_signal: Signal,
+    ) -> Result<()> {
+        let mut background = GuestBackground::new(
+            idm,
+            cgroup,
+            executed,
+            exec_env,
+            working_dir,
+            output,
+            healthcheck,
+            stop_signal,
+        )
+        .await?;
         background.run().await?;
         Ok(())
     }
@@ -6,8 +6,12 @@ use xenstore::{XsdClient, XsdInterface};
 
 pub mod background;
 pub mod childwait;
+pub mod exec;
+pub mod health;
 pub mod init;
 pub mod metrics;
+pub mod output;
+pub mod resources;
 
 pub async fn death(code: c_int) -> Result<()> {
     let store = XsdClient::open().await?;
@@ -0,0 +1,124 @@
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use krata::idm::{
+    client::IdmClient,
+    protocol::{idm_event::Event, IdmEvent, IdmLogEvent, IdmLogStream},
+};
+use log::debug;
+use nix::{
+    fcntl::OFlag,
+    unistd::{dup2, pipe2},
+};
+use tokio::{
+    fs::File,
+    io::{stderr, stdout, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    task::JoinHandle,
+};
+
+const OUTPUT_BUFFER_LEN: usize = 8192;
+const OUTPUT_MAX_LINE_LEN: usize = 16384;
+
+/// Pipes that replace the task's stdout and stderr so the output can be
+/// shipped over IDM as log events while still being copied to the console.
+pub struct GuestOutputPipes {
+    stdout: (OwnedFd, OwnedFd),
+    stderr: (OwnedFd, OwnedFd),
+}
+
+impl GuestOutputPipes {
+    pub fn new() -> Result<GuestOutputPipes> {
+        Ok(GuestOutputPipes {
+            stdout: pipe2(OFlag::O_CLOEXEC)?,
+            stderr: pipe2(OFlag::O_CLOEXEC)?,
+        })
+    }
+
+    pub fn attach(self) -> Result<()> {
+        dup2(self.stdout.1.as_raw_fd(), 1)?;
+        dup2(self.stderr.1.as_raw_fd(), 2)?;
+        Ok(())
+    }
+
+    pub fn capture(self, idm: IdmClient) -> Vec<JoinHandle<()>> {
+        let GuestOutputPipes {
+            stdout: (stdout_read, stdout_write),
+            stderr: (stderr_read, stderr_write),
+        } = self;
+        drop(stdout_write);
+        drop(stderr_write);
+        vec![
+            tokio::task::spawn(GuestOutputPipes::process(
+                idm.clone(),
+                File::from_std(stdout_read.into()),
+                stdout(),
+                IdmLogStream::Stdout,
+            )),
+            tokio::task::spawn(GuestOutputPipes::process(
+                idm,
+                File::from_std(stderr_read.into()),
+                stderr(),
+                IdmLogStream::Stderr,
+            )),
+        ]
+    }
+
+    async fn process(
+        idm: IdmClient,
+        mut input: impl AsyncRead + Unpin,
+        mut console: impl AsyncWrite + Unpin,
+        stream: IdmLogStream,
+    ) {
+        let mut buffer = vec![0u8; OUTPUT_BUFFER_LEN];
+        let mut line = Vec::new();
+        let mut sequence = 0;
+        loop {
+            let size = match input.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(size) => size,
+            };
+            let data = &buffer[0..size];
+            if let Err(error) = console.write_all(data).await {
+                debug!("failed to copy task output to console: {}", error);
+            }
+            let _ = console.flush().await;
+
+            for byte in data {
+                if *byte == b'\n' {
+                    GuestOutputPipes::emit(&idm, stream, &mut sequence, &mut line).await;
+                } else {
+                    line.push(*byte);
+                    if line.len() >= OUTPUT_MAX_LINE_LEN {
+                        GuestOutputPipes::emit(&idm, stream, &mut sequence, &mut line).await;
+                    }
+                }
+            }
+        }
+
+        if !line.is_empty() {
+            GuestOutputPipes::emit(&idm, stream, &mut sequence, &mut line).await;
+        }
+    }
+
+    async fn emit(idm: &IdmClient, stream: IdmLogStream, sequence: &mut u64, line: &mut Vec<u8>) {
+        *sequence += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or(0);
+        let event = IdmEvent {
+            event: Some(Event::Log(IdmLogEvent {
+                stream: stream.into(),
+                timestamp,
+                data: std::mem::take(line),
+                sequence: *sequence,
+            })),
+        };
+        if let Err(error) = idm.emit(event).await {
+            debug!("failed to emit task log event: {}", error);
+        }
+    }
+}
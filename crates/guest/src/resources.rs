@@ -0,0 +1,150 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use cgroups_rs::{BlkIoDeviceThrottleResource, Cgroup, MaxValue, Resources};
+use krata::{
+    idm::{
+        client::IdmClient,
+        protocol::{idm_event::Event, IdmEvent, IdmOomEvent},
+    },
+    launchcfg::LaunchResources,
+};
+use log::{debug, warn};
+use tokio::{fs, task::JoinHandle, time::sleep};
+
+pub const TASK_CGROUP_NAME: &str = "krata-guest-task";
+const TASK_CGROUP_PATH: &str = "/sys/fs/cgroup/krata-guest-task";
+
+/// Memory kept out of reach of the task so init and the IDM channel survive a runaway workload.
+const TASK_MEMORY_HEADROOM: u64 = 32 * 1024 * 1024;
+const TASK_CPU_PERIOD_US: u64 = 100000;
+const OOM_POLL_INTERVAL_MS: u64 = 1000;
+
+pub struct GuestTaskResources {
+    limits: LaunchResources,
+}
+
+impl GuestTaskResources {
+    pub fn new(limits: LaunchResources) -> GuestTaskResources {
+        GuestTaskResources { limits }
+    }
+
+    pub async fn apply(&self, cgroup: &Cgroup) -> Result<()> {
+        let mut resources = Resources::default();
+
+        if let Some(pids_max) = self.limits.pids_max {
+            resources.pid.maximum_number_of_processes = Some(MaxValue::Value(pids_max as i64));
+        }
+
+        let available = GuestTaskResources::available_memory().await?;
+        let memory_max = match self.limits.memory_max {
+            Some(memory_max) => memory_max.min(available),
+            None => available,
+        };
+        resources.memory.memory_hard_limit = Some(memory_max as i64);
+
+        if let Some(cpu_millis) = self.limits.cpu_millis {
+            resources.cpu.period = Some(TASK_CPU_PERIOD_US);
+            resources.cpu.quota = Some((cpu_millis as u64 * TASK_CPU_PERIOD_US / 1000) as i64);
+        }
+
+        resources.blkio.weight = self.limits.io_weight;
+        if self.limits.io_read_bps.is_some() || self.limits.io_write_bps.is_some() {
+            for (major, minor) in GuestTaskResources::block_devices().await? {
+                if let Some(rate) = self.limits.io_read_bps {
+                    resources
+                        .blkio
+                        .throttle_read_bps_device
+                        .push(BlkIoDeviceThrottleResource { major, minor, rate });
+                }
+                if let Some(rate) = self.limits.io_write_bps {
+                    resources
+                        .blkio
+                        .throttle_write_bps_device
+                        .push(BlkIoDeviceThrottleResource { major, minor, rate });
+                }
+            }
+        }
+
+        cgroup.apply(&resources)?;
+        Ok(())
+    }
+
+    pub fn monitor_oom(idm: IdmClient) -> JoinHandle<()> {
+        tokio::task::spawn(async move {
+            let mut reported = 0;
+            loop {
+                sleep(Duration::from_millis(OOM_POLL_INTERVAL_MS)).await;
+                let oom_kills = match GuestTaskResources::read_oom_kills().await {
+                    Ok(oom_kills) => oom_kills,
+                    Err(error) => {
+                        warn!("failed to read task cgroup memory events: {}", error);
+                        break;
+                    }
+                };
+
+                if oom_kills <= reported {
+                    continue;
+                }
+                reported = oom_kills;
+                let event = IdmEvent {
+                    event: Some(Event::Oom(IdmOomEvent { oom_kills })),
+                };
+                if let Err(error) = idm.emit(event).await {
+                    debug!("failed to emit oom event: {}", error);
+                }
+            }
+        })
+    }
+
+    async fn read_oom_kills() -> Result<u64> {
+        let path = Path::new(TASK_CGROUP_PATH).join("memory.events");
+        let content = fs::read_to_string(path).await?;
+        for line in content.lines() {
+            if let Some(value) = line.strip_prefix("oom_kill ") {
+                return Ok(value.trim().parse()?);
+            }
+        }
+        Ok(0)
+    }
+
+    async fn available_memory() -> Result<u64> {
+        let content = fs::read_to_string("/proc/meminfo").await?;
+        let total = content
+            .lines()
+            .find_map(|line| line.strip_prefix("MemTotal:"))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .ok_or_else(|| anyhow!("unable to determine total memory"))?;
+        let total = total * 1024;
+        if total <= TASK_MEMORY_HEADROOM {
+            return Err(anyhow!(
+                "guest memory of {} MiB leaves nothing for the task after the {} MiB reserved for init",
+                total / 1024 / 1024,
+                TASK_MEMORY_HEADROOM / 1024 / 1024
+            ));
+        }
+        Ok(total - TASK_MEMORY_HEADROOM)
+    }
+
+    async fn block_devices() -> Result<Vec<(u64, u64)>> {
+        let mut devices = Vec::new();
+        let mut entries = fs::read_dir("/sys/block").await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_name().to_string_lossy().starts_with("xvd") {
+                continue;
+            }
+            let dev = fs::read_to_string(entry.path().join("dev")).await?;
+            if let Some((major, minor)) = dev.trim().split_once(':') {
+                devices.push((major.parse()?, minor.parse()?));
+            }
+        }
+        Ok(devices)
+    }
+}
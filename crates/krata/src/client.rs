@@ -1,19 +1,70 @@
+use std::path::PathBuf;
+
 use crate::{dial::ControlDialAddress, v1::control::control_service_client::ControlServiceClient};
-#[cfg(not(unix))]
-use anyhow::anyhow;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 #[cfg(unix)]
 use tokio::net::UnixStream;
 #[cfg(unix)]
 use tonic::transport::Uri;
-use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 #[cfg(unix)]
 use tower::service_fn;
 
+#[derive(Clone, Debug, Default)]
+pub struct ControlClientTls {
+    pub ca_certificate: Option<PathBuf>,
+    pub certificate: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl ControlClientTls {
+    async fn load(&self, host: &str) -> Result<ClientTlsConfig> {
+        let mut config = ClientTlsConfig::new().domain_name(host);
+        if let Some(ref path) = self.ca_certificate {
+            let ca = tokio::fs::read(path)
+                .await
+                .map_err(|error| anyhow!("failed to read ca certificate {:?}: {}", path, error))?;
+            config = config.ca_certificate(Certificate::from_pem(ca));
+        }
+
+        match (&self.certificate, &self.key) {
+            (Some(certificate_path), Some(key_path)) => {
+                let certificate = tokio::fs::read(certificate_path).await.map_err(|error| {
+                    anyhow!(
+                        "failed to read client certificate {:?}: {}",
+                        certificate_path,
+                        error
+                    )
+                })?;
+                let key = tokio::fs::read(key_path).await.map_err(|error| {
+                    anyhow!("failed to read client key {:?}: {}", key_path, error)
+                })?;
+                config = config.identity(Identity::from_pem(certificate, key));
+            }
+
+            (None, None) => {}
+
+            _ => {
+                return Err(anyhow!(
+                    "client certificate and key must be specified together"
+                ));
+            }
+        }
+        Ok(config)
+    }
+}
+
 pub struct ControlClientProvider {}
 
 impl ControlClientProvider {
     pub async fn dial(addr: ControlDialAddress) -> Result<ControlServiceClient<Channel>> {
+        ControlClientProvider::dial_with_tls(addr, &ControlClientTls::default()).await
+    }
+
+    pub async fn dial_with_tls(
+        addr: ControlDialAddress,
+        tls: &ControlClientTls,
+    ) -> Result<ControlServiceClient<Channel>> {
         let channel = match addr {
             ControlDialAddress::UnixSocket { path } => {
                 #[cfg(not(unix))]
@@ -36,7 +87,7 @@ impl ControlClientProvider {
                 port,
                 insecure: _,
             } => {
-                let tls_config = ClientTlsConfig::new().domain_name(&host);
+                let tls_config = tls.load(&host).await?;
                 let address = format!("https://{}:{}", host, port);
                 Channel::from_shared(address)?
                     .tls_config(tls_config)?
@@ -19,12 +19,62 @@ pub struct LaunchNetworkResolver {
     pub nameservers: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LaunchNetworkHost {
+    pub name: String,
+    pub ipv4: String,
+    pub ipv6: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LaunchNetwork {
     pub link: String,
     pub ipv4: LaunchNetworkIpv4,
     pub ipv6: LaunchNetworkIpv6,
     pub resolver: LaunchNetworkResolver,
+    #[serde(default)]
+    pub hosts: Vec<LaunchNetworkHost>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LaunchVolume {
+    pub device: String,
+    pub path: String,
+    pub read_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LaunchMount {
+    pub tag: String,
+    pub path: String,
+    pub read_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchHealthCheckKind {
+    Command { command: Vec<String> },
+    Tcp { port: u16 },
+    Http { port: u16, path: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LaunchHealthCheck {
+    pub kind: LaunchHealthCheckKind,
+    pub interval_seconds: u64,
+    pub timeout_seconds: u64,
+    pub retries: u32,
+    pub start_period_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LaunchResources {
+    pub pids_max: Option<u64>,
+    pub memory_max: Option<u64>,
+    pub cpu_millis: Option<u32>,
+    pub io_weight: Option<u16>,
+    pub io_read_bps: Option<u64>,
+    pub io_write_bps: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -33,4 +83,12 @@ pub struct LaunchInfo {
     pub network: Option<LaunchNetwork>,
     pub env: HashMap<String, String>,
     pub run: Option<Vec<String>>,
+    pub volumes: Vec<LaunchVolume>,
+    pub mounts: Vec<LaunchMount>,
+    #[serde(default)]
+    pub tty: bool,
+    #[serde(default)]
+    pub healthcheck: Option<LaunchHealthCheck>,
+    #[serde(default)]
+    pub resources: LaunchResources,
 }
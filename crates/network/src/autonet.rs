@@ -2,7 +2,7 @@ use anyhow::Result;
 use krata::{
     events::EventStream,
     v1::{
-        common::Guest,
+        common::{Guest, GuestNetworkPolicy, GuestPortProtocol},
         control::{
             control_service_client::ControlServiceClient, watch_events_reply::Event,
             ListGuestsRequest,
@@ -16,6 +16,8 @@ use tokio::{select, sync::broadcast::Receiver, time::sleep};
 use tonic::transport::Channel;
 use uuid::Uuid;
 
+use crate::portfwd::PortForward;
+
 pub struct AutoNetworkWatcher {
     control: ControlServiceClient<Channel>,
     pub events: EventStream,
@@ -35,6 +37,11 @@ pub struct NetworkMetadata {
     pub uuid: Uuid,
     pub guest: NetworkSide,
     pub gateway: NetworkSide,
+    pub ports: Vec<PortForward>,
+    pub group: Option<String>,
+    pub name: String,
+    pub annotations: HashMap<String, String>,
+    pub policy: Option<GuestNetworkPolicy>,
 }
 
 impl NetworkMetadata {
@@ -47,6 +54,7 @@ impl NetworkMetadata {
 pub struct AutoNetworkChangeset {
     pub added: Vec<NetworkMetadata>,
     pub removed: Vec<NetworkMetadata>,
+    pub current: Option<Vec<NetworkMetadata>>,
 }
 
 impl AutoNetworkWatcher {
@@ -112,6 +120,44 @@ impl AutoNetworkWatcher {
                 continue;
             };
 
+            let ports = guest
+                .spec
+                .as_ref()
+                .map(|spec| spec.ports.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|port| {
+                    Some(PortForward {
+                        host_port: u16::try_from(port.host_port).ok()?,
+                        guest_port: u16::try_from(port.guest_port).ok()?,
+                        protocol: GuestPortProtocol::try_from(port.protocol).ok()?,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let group = guest
+                .spec
+                .as_ref()
+                .map(|spec| spec.group.clone())
+                .filter(|group| !group.is_empty());
+            let name = guest
+                .spec
+                .as_ref()
+                .map(|spec| spec.name.clone())
+                .unwrap_or_default();
+            let annotations = guest
+                .spec
+                .as_ref()
+                .map(|spec| spec.annotations.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|x| (x.key.clone(), x.value.clone()))
+                .collect::<HashMap<_, _>>();
+            let policy = guest
+                .spec
+                .as_ref()
+                .and_then(|spec| spec.network_policy.clone());
+
             networks.push(NetworkMetadata {
                 domid: state.domid,
                 uuid: *uuid,
@@ -125,6 +171,11 @@ impl AutoNetworkWatcher {
                     ipv6: gateway_ipv6_cidr,
                     mac: gateway_mac,
                 },
+                ports,
+                group,
+                name,
+                annotations,
+                policy,
             });
         }
         Ok(networks)
@@ -139,17 +190,21 @@ impl AutoNetworkWatcher {
             Ok(networks) => networks,
             Err(error) => {
                 warn!("failed to read network changes: {}", error);
-                return Ok(AutoNetworkChangeset { added, removed });
+                return Ok(AutoNetworkChangeset {
+                    added,
+                    removed,
+                    current: None,
+                });
             }
         };
 
-        for network in networks {
+        for network in &networks {
             seen.push(network.uuid);
             if self.known.contains_key(&network.uuid) {
                 continue;
             }
             let _ = self.known.insert(network.uuid, network.clone());
-            added.push(network);
+            added.push(network.clone());
         }
 
         let mut gone: Vec<Uuid> = Vec::new();
@@ -168,7 +223,11 @@ impl AutoNetworkWatcher {
             removed.push(network);
         }
 
-        Ok(AutoNetworkChangeset { added, removed })
+        Ok(AutoNetworkChangeset {
+            added,
+            removed,
+            current: Some(networks),
+        })
     }
 
     pub async fn wait(&mut self, receiver: &mut Receiver<Event>) -> Result<()> {
@@ -1,6 +1,7 @@
 use crate::autonet::NetworkMetadata;
 use crate::chandev::ChannelDevice;
 use crate::nat::Nat;
+use crate::policy::NetworkPolicyTable;
 use crate::proxynat::ProxyNatHandlerFactory;
 use crate::raw_socket::{AsyncRawSocketChannel, RawSocketHandle, RawSocketProtocol};
 use crate::vbridge::{BridgeJoinHandle, VirtualBridge};
@@ -23,6 +24,7 @@ const TX_CHANNEL_BUFFER_LEN: usize = 3000;
 pub struct NetworkBackend {
     metadata: NetworkMetadata,
     bridge: VirtualBridge,
+    policy: NetworkPolicyTable,
 }
 
 #[derive(Debug)]
@@ -82,8 +84,16 @@ impl NetworkStack<'_> {
 }
 
 impl NetworkBackend {
-    pub fn new(metadata: NetworkMetadata, bridge: VirtualBridge) -> Result<Self> {
-        Ok(Self { metadata, bridge })
+    pub fn new(
+        metadata: NetworkMetadata,
+        bridge: VirtualBridge,
+        policy: NetworkPolicyTable,
+    ) -> Result<Self> {
+        Ok(Self {
+            metadata,
+            bridge,
+            policy,
+        })
     }
 
     pub async fn init(&mut self) -> Result<()> {
@@ -127,7 +137,15 @@ impl NetworkBackend {
         let (tx_sender, tx_receiver) = channel::<BytesMut>(TX_CHANNEL_BUFFER_LEN);
         let mut udev = ChannelDevice::new(mtu, Medium::Ethernet, tx_sender.clone());
         let mac = self.metadata.gateway.mac;
-        let nat = Nat::new(mtu, proxy, mac, addresses.clone(), tx_sender.clone())?;
+        let nat = Nat::new(
+            mtu,
+            proxy,
+            mac,
+            self.metadata.guest.mac,
+            addresses.clone(),
+            tx_sender.clone(),
+            self.policy.clone(),
+        )?;
         let hardware_addr = HardwareAddress::Ethernet(mac);
         let config = Config::new(hardware_addr);
         let mut iface = Interface::new(config, &mut udev, Instant::now());
@@ -137,7 +155,7 @@ impl NetworkBackend {
                 .expect("failed to set ip addresses");
         });
         let sockets = SocketSet::new(vec![]);
-        let handle = self.bridge.join(self.metadata.guest.mac).await?;
+        let handle = self.bridge.join_guest(self.metadata.guest.mac).await?;
         let kdev = AsyncRawSocketChannel::new(mtu, kdev)?;
         Ok(NetworkStack {
             tx: tx_receiver,
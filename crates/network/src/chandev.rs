@@ -29,8 +29,14 @@ impl ChannelDevice {
 pub struct RxToken(pub BytesMut);
 
 impl Device for ChannelDevice {
-    type RxToken<'a> = RxToken where Self: 'a;
-    type TxToken<'a> = &'a mut ChannelDevice where Self: 'a;
+    type RxToken<'a>
+        = RxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = &'a mut ChannelDevice
+    where
+        Self: 'a;
 
     fn receive(
         &mut self,
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     io::ErrorKind,
     net::{IpAddr, Ipv4Addr},
 };
@@ -7,27 +8,100 @@ use advmac::MacAddr6;
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use futures::TryStreamExt;
-use log::error;
-use smoltcp::wire::EthernetAddress;
-use tokio::{select, task::JoinHandle};
+use log::{debug, error};
+use smoltcp::wire::{EthernetAddress, Ipv4Cidr};
+use tokio::{
+    select,
+    sync::{
+        broadcast::error::RecvError,
+        mpsc::{channel, Receiver, Sender},
+    },
+    task::JoinHandle,
+};
 use tokio_tun::Tun;
 
-use crate::vbridge::{BridgeJoinHandle, VirtualBridge};
+use crate::vbridge::{BridgeJoinHandle, BridgeSender, VirtualBridge};
 
-const HOST_IPV4_ADDR: Ipv4Addr = Ipv4Addr::new(10, 75, 0, 1);
+const FROM_BRIDGES_QUEUE_LEN: usize = 3000;
+const ATTACHMENT_QUEUE_LEN: usize = 30;
 
-#[derive(Debug)]
 enum HostBridgeProcessSelect {
-    Send(Option<BytesMut>),
+    Send(Option<(Option<String>, BytesMut)>),
     Receive(std::io::Result<usize>),
+    Attachment(Option<HostBridgeAttachment>),
+}
+
+enum HostBridgeAttachment {
+    Attach(String, BridgeJoinHandle),
+    Detach(String),
+}
+
+/// A virtual bridge the host is attached to, keyed by group name or `None`
+/// for the shared bridge.
+struct HostBridgeMember {
+    to_bridge_sender: BridgeSender,
+    task: JoinHandle<()>,
+}
+
+impl HostBridgeMember {
+    fn new(
+        key: Option<String>,
+        mut handle: BridgeJoinHandle,
+        from_bridges_sender: Sender<(Option<String>, BytesMut)>,
+    ) -> HostBridgeMember {
+        let to_bridge_sender = handle.to_bridge_sender.clone();
+        let task = tokio::task::spawn(async move {
+            loop {
+                let packet = select! {
+                    biased;
+                    x = handle.from_bridge_receiver.recv() => x,
+                    x = handle.from_broadcast_receiver.recv() => match x {
+                        Ok(packet) => Some(packet),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => None,
+                    },
+                };
+                let Some(packet) = packet else {
+                    break;
+                };
+                if from_bridges_sender
+                    .send((key.clone(), packet))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        HostBridgeMember {
+            to_bridge_sender,
+            task,
+        }
+    }
+}
+
+impl Drop for HostBridgeMember {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
 }
 
+/// Attaches the host to the shared virtual bridge and to every group bridge.
+/// Frames from the host are switched to the bridge a destination was learned
+/// on, frames are never switched between bridges.
 pub struct HostBridge {
+    mac: EthernetAddress,
+    attachment_sender: Sender<HostBridgeAttachment>,
     task: JoinHandle<()>,
 }
 
 impl HostBridge {
-    pub async fn new(mtu: usize, interface: String, bridge: &VirtualBridge) -> Result<HostBridge> {
+    pub async fn new(
+        mtu: usize,
+        interface: String,
+        host_ipv4: Ipv4Cidr,
+        bridge: &VirtualBridge,
+    ) -> Result<HostBridge> {
         let tun = Tun::builder()
             .name(&interface)
             .tap(true)
@@ -54,7 +128,11 @@ impl HostBridge {
 
         handle
             .address()
-            .add(link.header.index, IpAddr::V4(HOST_IPV4_ADDR), 16)
+            .add(
+                link.header.index,
+                IpAddr::V4(Ipv4Addr::from(host_ipv4.address())),
+                host_ipv4.prefix_len(),
+            )
             .execute()
             .await?;
 
@@ -74,17 +152,57 @@ impl HostBridge {
 
         let mac = EthernetAddress(mac.to_array());
         let bridge_handle = bridge.join(mac).await?;
+        let (attachment_sender, attachment_receiver) = channel(ATTACHMENT_QUEUE_LEN);
 
         let task = tokio::task::spawn(async move {
-            if let Err(error) = HostBridge::process(mtu, tun, bridge_handle).await {
+            if let Err(error) =
+                HostBridge::process(mtu, tun, mac, bridge_handle, attachment_receiver).await
+            {
                 error!("failed to process host bridge: {}", error);
             }
         });
 
-        Ok(HostBridge { task })
+        Ok(HostBridge {
+            mac,
+            attachment_sender,
+            task,
+        })
     }
 
-    async fn process(mtu: usize, tun: Tun, mut bridge_handle: BridgeJoinHandle) -> Result<()> {
+    /// Attaches the host to the private bridge of a guest group.
+    pub async fn attach(&self, group: &str, bridge: &VirtualBridge) -> Result<()> {
+        let handle = bridge.join(self.mac).await?;
+        self.attachment_sender
+            .send(HostBridgeAttachment::Attach(group.to_string(), handle))
+            .await
+            .map_err(|_| anyhow!("host bridge is no longer running"))?;
+        Ok(())
+    }
+
+    pub async fn detach(&self, group: &str) -> Result<()> {
+        self.attachment_sender
+            .send(HostBridgeAttachment::Detach(group.to_string()))
+            .await
+            .map_err(|_| anyhow!("host bridge is no longer running"))?;
+        Ok(())
+    }
+
+    async fn process(
+        mtu: usize,
+        tun: Tun,
+        mac: EthernetAddress,
+        bridge_handle: BridgeJoinHandle,
+        mut attachment_receiver: Receiver<HostBridgeAttachment>,
+    ) -> Result<()> {
+        let (from_bridges_sender, mut from_bridges_receiver) =
+            channel::<(Option<String>, BytesMut)>(FROM_BRIDGES_QUEUE_LEN);
+        let mut members: HashMap<Option<String>, HostBridgeMember> = HashMap::new();
+        let mut learned: HashMap<EthernetAddress, Option<String>> = HashMap::new();
+        members.insert(
+            None,
+            HostBridgeMember::new(None, bridge_handle, from_bridges_sender.clone()),
+        );
+
         let tear_off_size = 100 * mtu;
         let mut buffer: BytesMut = BytesMut::with_capacity(tear_off_size);
         loop {
@@ -96,32 +214,77 @@ impl HostBridge {
             let selection = select! {
                 biased;
                 x = tun.recv(&mut buffer) => HostBridgeProcessSelect::Receive(x),
-                x = bridge_handle.from_bridge_receiver.recv() => HostBridgeProcessSelect::Send(x),
-                x = bridge_handle.from_broadcast_receiver.recv() => HostBridgeProcessSelect::Send(x.ok()),
+                x = from_bridges_receiver.recv() => HostBridgeProcessSelect::Send(x),
+                x = attachment_receiver.recv() => HostBridgeProcessSelect::Attachment(x),
             };
 
             match selection {
-                HostBridgeProcessSelect::Send(Some(bytes)) => match tun.try_send(&bytes) {
-                    Ok(_) => {}
-                    Err(error) => {
-                        if error.kind() == ErrorKind::WouldBlock {
-                            continue;
+                HostBridgeProcessSelect::Send(Some((key, bytes))) => {
+                    if bytes.len() >= 12 {
+                        let source = EthernetAddress::from_bytes(&bytes[6..12]);
+                        if source != mac && source.is_unicast() {
+                            learned.insert(source, key);
                         }
-                        return Err(error.into());
                     }
-                },
+
+                    match tun.try_send(&bytes) {
+                        Ok(_) => {}
+                        Err(error) => {
+                            if error.kind() == ErrorKind::WouldBlock {
+                                continue;
+                            }
+                            return Err(error.into());
+                        }
+                    }
+                }
 
                 HostBridgeProcessSelect::Send(None) => {
                     break;
                 }
 
+                HostBridgeProcessSelect::Attachment(Some(HostBridgeAttachment::Attach(
+                    group,
+                    handle,
+                ))) => {
+                    debug!("host bridge attached to group {}", group);
+                    let key = Some(group);
+                    members.insert(
+                        key.clone(),
+                        HostBridgeMember::new(key, handle, from_bridges_sender.clone()),
+                    );
+                }
+
+                HostBridgeProcessSelect::Attachment(Some(HostBridgeAttachment::Detach(group))) => {
+                    debug!("host bridge detached from group {}", group);
+                    let key = Some(group);
+                    members.remove(&key);
+                    learned.retain(|_, x| *x != key);
+                }
+
+                HostBridgeProcessSelect::Attachment(None) => {}
+
                 HostBridgeProcessSelect::Receive(result) => match result {
                     Ok(len) => {
-                        if len == 0 {
+                        if len < 6 {
                             continue;
                         }
                         let packet = buffer.split_to(len);
-                        let _ = bridge_handle.to_bridge_sender.try_send(packet);
+                        let destination = EthernetAddress::from_bytes(&packet[0..6]);
+                        let member = if destination.is_unicast() {
+                            learned.get(&destination).and_then(|key| members.get(key))
+                        } else {
+                            None
+                        };
+                        match member {
+                            Some(member) => {
+                                let _ = member.to_bridge_sender.try_send(packet);
+                            }
+                            None => {
+                                for member in members.values() {
+                                    let _ = member.to_bridge_sender.try_send(packet.clone());
+                                }
+                            }
+                        }
                     }
 
                     Err(error) => {
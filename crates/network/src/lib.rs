@@ -1,21 +1,25 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use autonet::{AutoNetworkChangeset, AutoNetworkWatcher, NetworkMetadata};
 use futures::{future::join_all, TryFutureExt};
 use hbridge::HostBridge;
 use krata::{
     client::ControlClientProvider,
     dial::ControlDialAddress,
-    v1::{common::Guest, control::control_service_client::ControlServiceClient},
+    v1::{
+        common::Guest,
+        control::{control_service_client::ControlServiceClient, GetHostNetworkRequest},
+    },
 };
 use log::warn;
+use smoltcp::wire::Ipv4Cidr;
 use tokio::{task::JoinHandle, time::sleep};
 use tonic::transport::Channel;
 use uuid::Uuid;
 use vbridge::VirtualBridge;
 
-use crate::backend::NetworkBackend;
+use crate::{backend::NetworkBackend, policy::NetworkPolicyTable, portfwd::PortForwarder};
 
 pub mod autonet;
 pub mod backend;
@@ -24,6 +28,8 @@ pub mod hbridge;
 pub mod icmp;
 pub mod nat;
 pub mod pkt;
+pub mod policy;
+pub mod portfwd;
 pub mod proxynat;
 pub mod raw_socket;
 pub mod vbridge;
@@ -35,22 +41,43 @@ pub struct NetworkService {
     pub control: ControlServiceClient<Channel>,
     pub guests: HashMap<Uuid, Guest>,
     pub backends: HashMap<Uuid, JoinHandle<()>>,
+    pub forwarders: HashMap<Uuid, PortForwarder>,
     pub bridge: VirtualBridge,
     pub hbridge: HostBridge,
+    pub group_bridges: HashMap<String, VirtualBridge>,
+    pub group_members: HashMap<Uuid, String>,
+    pub policy: NetworkPolicyTable,
 }
 
 impl NetworkService {
     pub async fn new(control_address: ControlDialAddress) -> Result<NetworkService> {
-        let control = ControlClientProvider::dial(control_address).await?;
-        let bridge = VirtualBridge::new()?;
-        let hbridge =
-            HostBridge::new(HOST_BRIDGE_MTU + EXTRA_MTU, "krata0".to_string(), &bridge).await?;
+        let mut control = ControlClientProvider::dial(control_address).await?;
+        let host_ipv4 = control
+            .get_host_network(GetHostNetworkRequest {})
+            .await?
+            .into_inner()
+            .host_ipv4;
+        let host_ipv4 = Ipv4Cidr::from_str(&host_ipv4)
+            .map_err(|_| anyhow!("daemon returned invalid host address {}", host_ipv4))?;
+        let policy = NetworkPolicyTable::new();
+        let bridge = VirtualBridge::new(policy.clone())?;
+        let hbridge = HostBridge::new(
+            HOST_BRIDGE_MTU + EXTRA_MTU,
+            "krata0".to_string(),
+            host_ipv4,
+            &bridge,
+        )
+        .await?;
         Ok(NetworkService {
             control,
             guests: HashMap::new(),
             backends: HashMap::new(),
+            forwarders: HashMap::new(),
             bridge,
             hbridge,
+            group_bridges: HashMap::new(),
+            group_members: HashMap::new(),
+            policy,
         })
     }
 }
@@ -72,10 +99,28 @@ impl NetworkService {
         collector: &mut AutoNetworkWatcher,
         changeset: AutoNetworkChangeset,
     ) -> Result<()> {
+        if let Some(ref current) = changeset.current {
+            self.policy.update(current);
+        }
+
         for removal in &changeset.removed {
             if let Some(handle) = self.backends.remove(&removal.uuid) {
                 handle.abort();
             }
+            self.forwarders.remove(&removal.uuid);
+            self.leave_group(removal.uuid).await;
+        }
+
+        for metadata in &changeset.added {
+            let Some(ref group) = metadata.group else {
+                continue;
+            };
+            if !self.group_bridges.contains_key(group) {
+                let bridge = VirtualBridge::new(self.policy.clone())?;
+                self.hbridge.attach(group, &bridge).await?;
+                self.group_bridges.insert(group.clone(), bridge);
+            }
+            self.group_members.insert(metadata.uuid, group.clone());
         }
 
         let futures = changeset
@@ -113,6 +158,32 @@ impl NetworkService {
 
         for uuid in failed {
             collector.mark_unknown(uuid)?;
+            self.leave_group(uuid).await;
+        }
+
+        for metadata in &changeset.added {
+            if metadata.ports.is_empty() || !self.backends.contains_key(&metadata.uuid) {
+                continue;
+            }
+
+            match PortForwarder::launch(
+                metadata.uuid,
+                metadata.guest.ipv4.address().into(),
+                &metadata.ports,
+            )
+            .await
+            {
+                Ok(forwarder) => {
+                    self.forwarders.insert(metadata.uuid, forwarder);
+                }
+
+                Err(error) => {
+                    warn!(
+                        "failed to launch port forwarding for krata guest {}: {}",
+                        metadata.uuid, error
+                    );
+                }
+            }
         }
 
         Ok(())
@@ -122,8 +193,32 @@ impl NetworkService {
         &self,
         metadata: &NetworkMetadata,
     ) -> Result<(Uuid, JoinHandle<()>)> {
-        let mut network = NetworkBackend::new(metadata.clone(), self.bridge.clone())?;
+        // guests in a group get a private bridge, only the host bridge is shared with it
+        let bridge = match metadata.group {
+            Some(ref group) => self
+                .group_bridges
+                .get(group)
+                .cloned()
+                .ok_or_else(|| anyhow!("virtual bridge for group {} not found", group))?,
+            None => self.bridge.clone(),
+        };
+        let mut network = NetworkBackend::new(metadata.clone(), bridge, self.policy.clone())?;
         network.init().await?;
         Ok((metadata.uuid, network.launch().await?))
     }
+
+    async fn leave_group(&mut self, uuid: Uuid) {
+        let Some(group) = self.group_members.remove(&uuid) else {
+            return;
+        };
+        if !self.group_members.values().any(|x| *x == group) {
+            self.group_bridges.remove(&group);
+            if let Err(error) = self.hbridge.detach(&group).await {
+                warn!(
+                    "failed to detach host bridge from group {}: {}",
+                    group, error
+                );
+            }
+        }
+    }
 }
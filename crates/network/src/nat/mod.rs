@@ -3,6 +3,7 @@ use tokio::sync::mpsc::Sender;
 
 use self::handler::NatHandlerFactory;
 use self::processor::NatProcessor;
+use crate::policy::NetworkPolicyTable;
 use bytes::BytesMut;
 use smoltcp::wire::EthernetAddress;
 use smoltcp::wire::IpCidr;
@@ -23,11 +24,20 @@ impl Nat {
         mtu: usize,
         factory: Box<dyn NatHandlerFactory>,
         local_mac: EthernetAddress,
+        client_mac: EthernetAddress,
         local_cidrs: Vec<IpCidr>,
         transmit_sender: Sender<BytesMut>,
+        policy: NetworkPolicyTable,
     ) -> Result<Self> {
-        let (receive_sender, task) =
-            NatProcessor::launch(mtu, factory, local_mac, local_cidrs, transmit_sender)?;
+        let (receive_sender, task) = NatProcessor::launch(
+            mtu,
+            factory,
+            local_mac,
+            client_mac,
+            local_cidrs,
+            transmit_sender,
+            policy,
+        )?;
         Ok(Self {
             receive_sender,
             task,
@@ -20,6 +20,7 @@ use smoltcp::wire::IpAddress;
 use smoltcp::wire::IpCidr;
 use smoltcp::wire::IpEndpoint;
 use std::collections::hash_map::Entry;
+use std::time::Duration;
 use tokio::select;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Receiver;
@@ -32,13 +33,16 @@ use super::handler::NatHandlerFactory;
 use super::key::NatKey;
 use super::key::NatKeyProtocol;
 use super::table::NatTable;
+use crate::policy::{NetworkPolicyTable, PolicyFlow, PolicyProtocol};
 
 const RECEIVE_CHANNEL_QUEUE_LEN: usize = 3000;
 const RECLAIM_CHANNEL_QUEUE_LEN: usize = 30;
+const POLICY_REVALIDATE_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct NatProcessor {
     mtu: usize,
     local_mac: EthernetAddress,
+    client_mac: EthernetAddress,
     local_cidrs: Vec<IpCidr>,
     table: NatTable,
     factory: Box<dyn NatHandlerFactory>,
@@ -46,11 +50,14 @@ pub struct NatProcessor {
     reclaim_sender: Sender<NatKey>,
     reclaim_receiver: Receiver<NatKey>,
     receive_receiver: Receiver<BytesMut>,
+    policy: NetworkPolicyTable,
+    policy_generation: u64,
 }
 
 enum NatProcessorSelect {
     Reclaim(Option<NatKey>),
     ReceivedPacket(Option<BytesMut>),
+    PolicyCheck,
 }
 
 impl NatProcessor {
@@ -58,14 +65,18 @@ impl NatProcessor {
         mtu: usize,
         factory: Box<dyn NatHandlerFactory>,
         local_mac: EthernetAddress,
+        client_mac: EthernetAddress,
         local_cidrs: Vec<IpCidr>,
         transmit_sender: Sender<BytesMut>,
+        policy: NetworkPolicyTable,
     ) -> Result<(Sender<BytesMut>, JoinHandle<()>)> {
         let (reclaim_sender, reclaim_receiver) = channel(RECLAIM_CHANNEL_QUEUE_LEN);
         let (receive_sender, receive_receiver) = channel(RECEIVE_CHANNEL_QUEUE_LEN);
+        let policy_generation = policy.generation();
         let mut processor = Self {
             mtu,
             local_mac,
+            client_mac,
             local_cidrs,
             factory,
             table: NatTable::new(),
@@ -73,6 +84,8 @@ impl NatProcessor {
             reclaim_sender,
             receive_receiver,
             reclaim_receiver,
+            policy,
+            policy_generation,
         };
 
         let handle = tokio::task::spawn(async move {
@@ -85,12 +98,19 @@ impl NatProcessor {
     }
 
     pub async fn process(&mut self) -> Result<()> {
+        let mut policy_check = tokio::time::interval(POLICY_REVALIDATE_INTERVAL);
         loop {
             let selection = select! {
                 x = self.reclaim_receiver.recv() => NatProcessorSelect::Reclaim(x),
                 x = self.receive_receiver.recv() => NatProcessorSelect::ReceivedPacket(x),
+                _ = policy_check.tick() => NatProcessorSelect::PolicyCheck,
             };
 
+            if self.policy.generation() != self.policy_generation {
+                self.policy_generation = self.policy.generation();
+                self.revalidate();
+            }
+
             match selection {
                 NatProcessorSelect::Reclaim(Some(key)) => {
                     if self.table.inner.remove(&key).is_some() {
@@ -111,6 +131,8 @@ impl NatProcessor {
                 NatProcessorSelect::ReceivedPacket(None) | NatProcessorSelect::Reclaim(None) => {
                     break
                 }
+
+                NatProcessorSelect::PolicyCheck => {}
             }
         }
         Ok(())
@@ -144,6 +166,11 @@ impl NatProcessor {
             return Ok(());
         }
 
+        if !self.policy.verify_source(self.client_mac, packet.raw) {
+            trace!("dropped spoofed packet from guest {}", self.client_mac);
+            return Ok(());
+        }
+
         let key = match packet.ip {
             Some(RecvPacketIp::Ipv4(ipv4)) => self.extract_key_ipv4(packet, ipv4)?,
             Some(RecvPacketIp::Ipv6(ipv6)) => self.extract_key_ipv6(packet, ipv6)?,
@@ -169,6 +196,11 @@ impl NatProcessor {
         let handler: Option<&mut Box<dyn NatHandler>> = match self.table.inner.entry(key) {
             Entry::Occupied(entry) => Some(entry.into_mut()),
             Entry::Vacant(entry) => {
+                if !NatProcessor::allows(&self.policy, self.client_mac, &key) {
+                    trace!("network policy denied nat entry for key: {}", key);
+                    return Ok(());
+                }
+
                 if let Some(handler) = self.factory.nat(context).await {
                     debug!("creating nat entry for key: {}", key);
                     Some(entry.insert(handler))
@@ -186,6 +218,36 @@ impl NatProcessor {
         Ok(())
     }
 
+    /// Reclaims the entries of flows that updated policies no longer permit.
+    fn revalidate(&mut self) {
+        let policy = &self.policy;
+        let client_mac = self.client_mac;
+        self.table.inner.retain(|key, _| {
+            let allowed = NatProcessor::allows(policy, client_mac, key);
+            if !allowed {
+                debug!("network policy reclaimed nat entry for key: {}", key);
+            }
+            allowed
+        });
+    }
+
+    fn allows(policy: &NetworkPolicyTable, client_mac: EthernetAddress, key: &NatKey) -> bool {
+        let flow = PolicyFlow {
+            protocol: match key.protocol {
+                NatKeyProtocol::Tcp => PolicyProtocol::Tcp,
+                NatKeyProtocol::Udp => PolicyProtocol::Udp,
+                NatKeyProtocol::Icmp => PolicyProtocol::Icmp,
+            },
+            source: key.client_ip,
+            destination: key.external_ip,
+        };
+        policy.allows(
+            Some(client_mac),
+            policy.guest_at(&key.external_ip.addr),
+            &flow,
+        )
+    }
+
     pub fn extract_key_ipv4<'a>(
         &mut self,
         packet: &RecvPacket<'a>,
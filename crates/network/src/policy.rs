@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use etherparse::{
+    EtherType, Ethernet2Header, Icmpv4Type, Icmpv6Type, IpNumber, NetSlice, SlicedPacket,
+    TransportSlice,
+};
+use krata::v1::common::{
+    GuestNetworkPolicyAction, GuestNetworkPolicyProtocol, GuestNetworkPolicyRule,
+};
+use log::warn;
+use smoltcp::wire::{
+    ArpPacket, ArpRepr, EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv6Address,
+};
+
+use crate::autonet::NetworkMetadata;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PolicyProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Other,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PolicyFlow {
+    pub protocol: PolicyProtocol,
+    pub source: IpEndpoint,
+    pub destination: IpEndpoint,
+}
+
+impl PolicyFlow {
+    /// Extracts the flow of an ethernet frame. Frames that carry no policy relevant
+    /// traffic (ARP, ICMP errors, neighbor discovery) have no flow and are always allowed.
+    pub fn from_ethernet(packet: &[u8]) -> Option<PolicyFlow> {
+        let slice = SlicedPacket::from_ethernet(packet).ok()?;
+        let (source, destination, protocol) = match slice.net? {
+            NetSlice::Ipv4(ipv4) => (
+                IpAddress::Ipv4(ipv4.header().source_addr().into()),
+                IpAddress::Ipv4(ipv4.header().destination_addr().into()),
+                ipv4.header().protocol(),
+            ),
+            NetSlice::Ipv6(ipv6) => (
+                IpAddress::Ipv6(ipv6.header().source_addr().into()),
+                IpAddress::Ipv6(ipv6.header().destination_addr().into()),
+                ipv6.header().next_header(),
+            ),
+        };
+
+        let (protocol, source_port, destination_port) = match slice.transport {
+            Some(TransportSlice::Tcp(tcp)) => (
+                PolicyProtocol::Tcp,
+                tcp.source_port(),
+                tcp.destination_port(),
+            ),
+            Some(TransportSlice::Udp(udp)) => (
+                PolicyProtocol::Udp,
+                udp.source_port(),
+                udp.destination_port(),
+            ),
+            Some(TransportSlice::Icmpv4(icmp)) => match icmp.icmp_type() {
+                Icmpv4Type::EchoRequest(_) | Icmpv4Type::EchoReply(_) => {
+                    (PolicyProtocol::Icmp, 0, 0)
+                }
+                _ => return None,
+            },
+            Some(TransportSlice::Icmpv6(icmp)) => match icmp.icmp_type() {
+                Icmpv6Type::EchoRequest(_) | Icmpv6Type::EchoReply(_) => {
+                    (PolicyProtocol::Icmp, 0, 0)
+                }
+                _ => return None,
+            },
+            None if protocol == IpNumber::IPV6_ICMP || protocol == IpNumber::ICMP => {
+                return None;
+            }
+            None => (PolicyProtocol::Other, 0, 0),
+        };
+
+        Some(PolicyFlow {
+            protocol,
+            source: IpEndpoint::new(source, source_port),
+            destination: IpEndpoint::new(destination, destination_port),
+        })
+    }
+
+    pub fn reverse(&self) -> PolicyFlow {
+        PolicyFlow {
+            protocol: self.protocol,
+            source: self.destination,
+            destination: self.source,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PolicyDirection {
+    Ingress,
+    Egress,
+}
+
+struct PolicyRule {
+    allow: bool,
+    guest: Option<String>,
+    selector: Vec<(String, String)>,
+    cidr: Option<IpCidr>,
+    port: Option<u16>,
+    protocol: GuestNetworkPolicyProtocol,
+}
+
+impl PolicyRule {
+    fn new(owner: &str, rule: &GuestNetworkPolicyRule) -> Option<PolicyRule> {
+        let cidr = if rule.cidr.is_empty() {
+            None
+        } else {
+            match IpCidr::from_str(&rule.cidr) {
+                Ok(cidr) => Some(cidr),
+                Err(_) => {
+                    warn!(
+                        "ignoring network policy rule of guest {} with invalid cidr {}",
+                        owner, rule.cidr
+                    );
+                    return None;
+                }
+            }
+        };
+
+        Some(PolicyRule {
+            allow: rule.action() == GuestNetworkPolicyAction::Allow,
+            guest: Some(rule.guest.clone()).filter(|x| !x.is_empty()),
+            selector: rule
+                .selector
+                .iter()
+                .map(|x| (x.key.clone(), x.value.clone()))
+                .collect(),
+            cidr,
+            port: u16::try_from(rule.port).ok().filter(|x| *x != 0),
+            protocol: rule.protocol(),
+        })
+    }
+
+    fn matches(&self, peer: Option<&GuestPolicy>, peer_addr: IpAddress, flow: &PolicyFlow) -> bool {
+        if let Some(ref guest) = self.guest {
+            if peer.map(|x| &x.name != guest).unwrap_or(true) {
+                return false;
+            }
+        }
+
+        if !self.selector.is_empty() {
+            let Some(peer) = peer else {
+                return false;
+            };
+            if !self
+                .selector
+                .iter()
+                .all(|(key, value)| peer.annotations.get(key) == Some(value))
+            {
+                return false;
+            }
+        }
+
+        if let Some(cidr) = self.cidr {
+            if !cidr.contains_addr(&peer_addr) {
+                return false;
+            }
+        }
+
+        if let Some(port) = self.port {
+            if flow.destination.port != port {
+                return false;
+            }
+        }
+
+        match self.protocol {
+            GuestNetworkPolicyProtocol::Any => true,
+            GuestNetworkPolicyProtocol::Tcp => flow.protocol == PolicyProtocol::Tcp,
+            GuestNetworkPolicyProtocol::Udp => flow.protocol == PolicyProtocol::Udp,
+            GuestNetworkPolicyProtocol::Icmp => flow.protocol == PolicyProtocol::Icmp,
+        }
+    }
+}
+
+struct GuestPolicy {
+    mac: EthernetAddress,
+    ipv4: Ipv4Address,
+    ipv6: Ipv6Address,
+    name: String,
+    annotations: HashMap<String, String>,
+    ingress_allow: bool,
+    egress_allow: bool,
+    ingress: Vec<PolicyRule>,
+    egress: Vec<PolicyRule>,
+}
+
+impl GuestPolicy {
+    fn new(metadata: &NetworkMetadata) -> GuestPolicy {
+        let policy = metadata.policy.clone().unwrap_or_default();
+        let rules = |rules: &[GuestNetworkPolicyRule]| {
+            rules
+                .iter()
+                .filter_map(|rule| PolicyRule::new(&metadata.name, rule))
+                .collect::<Vec<_>>()
+        };
+        GuestPolicy {
+            mac: metadata.guest.mac,
+            ipv4: metadata.guest.ipv4.address(),
+            ipv6: metadata.guest.ipv6.address(),
+            name: metadata.name.clone(),
+            annotations: metadata.annotations.clone(),
+            ingress_allow: policy.ingress_default() == GuestNetworkPolicyAction::Allow,
+            egress_allow: policy.egress_default() == GuestNetworkPolicyAction::Allow,
+            ingress: rules(&policy.ingress),
+            egress: rules(&policy.egress),
+        }
+    }
+
+    fn permits(
+        &self,
+        direction: PolicyDirection,
+        peer: Option<&GuestPolicy>,
+        peer_addr: IpAddress,
+        flow: &PolicyFlow,
+    ) -> bool {
+        let (rules, default) = match direction {
+            PolicyDirection::Ingress => (&self.ingress, self.ingress_allow),
+            PolicyDirection::Egress => (&self.egress, self.egress_allow),
+        };
+        rules
+            .iter()
+            .find(|rule| rule.matches(peer, peer_addr, flow))
+            .map(|rule| rule.allow)
+            .unwrap_or(default)
+    }
+
+    /// Frames of a guest must carry its own mac and addresses. IPv6 link-local and
+    /// unspecified sources are only accepted for neighbor discovery, which carries no flow.
+    fn sends(&self, packet: &[u8]) -> bool {
+        let Ok((header, payload)) = Ethernet2Header::from_slice(packet) else {
+            return false;
+        };
+        if EthernetAddress(header.source) != self.mac {
+            return false;
+        }
+
+        if header.ether_type == EtherType::ARP {
+            return match ArpPacket::new_checked(payload).and_then(|x| ArpRepr::parse(&x)) {
+                Ok(ArpRepr::EthernetIpv4 {
+                    source_hardware_addr,
+                    source_protocol_addr,
+                    ..
+                }) => {
+                    source_hardware_addr == self.mac
+                        && (source_protocol_addr == self.ipv4
+                            || source_protocol_addr.is_unspecified())
+                }
+                _ => false,
+            };
+        }
+
+        let Ok(slice) = SlicedPacket::from_ethernet(packet) else {
+            return false;
+        };
+        match slice.net {
+            Some(NetSlice::Ipv4(ipv4)) => {
+                Ipv4Address::from(ipv4.header().source_addr()) == self.ipv4
+            }
+            Some(NetSlice::Ipv6(ipv6)) => {
+                let source = Ipv6Address::from(ipv6.header().source_addr());
+                if source == self.ipv6 {
+                    return true;
+                }
+                (source.is_link_local() || source.is_unspecified())
+                    && matches!(slice.transport, Some(TransportSlice::Icmpv6(_)))
+                    && PolicyFlow::from_ethernet(packet).is_none()
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PolicyGuests {
+    guests: HashMap<EthernetAddress, Arc<GuestPolicy>>,
+    addresses: HashMap<IpAddress, EthernetAddress>,
+}
+
+/// Network policies of all guests keyed by guest mac, shared by the bridges and nat.
+/// Policies apply to the guest a frame was received from or is delivered to, never
+/// to whoever the addresses inside the frame claim to be.
+#[derive(Clone, Default)]
+pub struct NetworkPolicyTable {
+    guests: Arc<RwLock<PolicyGuests>>,
+    generation: Arc<AtomicU64>,
+}
+
+impl NetworkPolicyTable {
+    pub fn new() -> NetworkPolicyTable {
+        NetworkPolicyTable::default()
+    }
+
+    pub fn update(&self, networks: &[NetworkMetadata]) {
+        let mut guests = PolicyGuests::default();
+        for metadata in networks {
+            let mac = metadata.guest.mac;
+            guests
+                .guests
+                .insert(mac, Arc::new(GuestPolicy::new(metadata)));
+            guests
+                .addresses
+                .insert(metadata.guest.ipv4.address().into(), mac);
+            guests
+                .addresses
+                .insert(metadata.guest.ipv6.address().into(), mac);
+        }
+        *self.guests.write().unwrap_or_else(|x| x.into_inner()) = guests;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Changes whenever the policies are updated, so cached decisions can be revalidated.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// The mac of the guest that was assigned an address.
+    pub fn guest_at(&self, addr: &IpAddress) -> Option<EthernetAddress> {
+        let guests = self.guests.read().unwrap_or_else(|x| x.into_inner());
+        guests.addresses.get(addr).copied()
+    }
+
+    /// Checks that a frame received from a guest is not spoofed. Frames of unknown
+    /// guests are never accepted.
+    pub fn verify_source(&self, guest: EthernetAddress, packet: &[u8]) -> bool {
+        let guests = self.guests.read().unwrap_or_else(|x| x.into_inner());
+        guests
+            .guests
+            .get(&guest)
+            .map(|x| x.sends(packet))
+            .unwrap_or(false)
+    }
+
+    /// A flow is allowed when the egress policy of the guest it was received from and
+    /// the ingress policy of the guest it is delivered to both permit it. Non-guest
+    /// endpoints have no policy.
+    pub fn allows(
+        &self,
+        source: Option<EthernetAddress>,
+        destination: Option<EthernetAddress>,
+        flow: &PolicyFlow,
+    ) -> bool {
+        let guests = self.guests.read().unwrap_or_else(|x| x.into_inner());
+        let source = source
+            .and_then(|x| guests.guests.get(&x))
+            .map(|x| x.as_ref());
+        let destination = destination
+            .and_then(|x| guests.guests.get(&x))
+            .map(|x| x.as_ref());
+
+        if let Some(source) = source {
+            if !source.permits(
+                PolicyDirection::Egress,
+                destination,
+                flow.destination.addr,
+                flow,
+            ) {
+                return false;
+            }
+        }
+
+        if let Some(destination) = destination {
+            if !destination.permits(PolicyDirection::Ingress, source, flow.source.addr, flow) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autonet::NetworkSide;
+    use etherparse::PacketBuilder;
+    use krata::v1::common::{GuestNetworkPolicy, GuestSpecAnnotation};
+    use smoltcp::wire::{Ipv4Cidr, Ipv6Cidr};
+    use uuid::Uuid;
+
+    fn guest(index: u8, name: &str, policy: Option<GuestNetworkPolicy>) -> NetworkMetadata {
+        let gateway = NetworkSide {
+            ipv4: Ipv4Cidr::new(Ipv4Address::new(10, 75, 0, 1), 16),
+            ipv6: Ipv6Cidr::new(Ipv6Address::new(0xfdd4, 0, 0, 0, 0, 0, 0, 1), 64),
+            mac: EthernetAddress([0x02, 0, 0, 0, 0, 1]),
+        };
+        NetworkMetadata {
+            domid: index as u32,
+            uuid: Uuid::new_v4(),
+            guest: NetworkSide {
+                ipv4: Ipv4Cidr::new(Ipv4Address::new(10, 75, 0, index), 16),
+                ipv6: Ipv6Cidr::new(Ipv6Address::new(0xfdd4, 0, 0, 0, 0, 0, 0, index as u16), 64),
+                mac: EthernetAddress([0x02, 0, 0, 0, 1, index]),
+            },
+            gateway,
+            ports: vec![],
+            group: None,
+            name: name.to_string(),
+            annotations: HashMap::from([("role".to_string(), name.to_string())]),
+            policy,
+        }
+    }
+
+    fn rule(action: GuestNetworkPolicyAction, port: u32) -> GuestNetworkPolicyRule {
+        GuestNetworkPolicyRule {
+            action: action.into(),
+            port,
+            ..Default::default()
+        }
+    }
+
+    fn tcp(source: &NetworkMetadata, destination: &NetworkMetadata, port: u16) -> PolicyFlow {
+        PolicyFlow {
+            protocol: PolicyProtocol::Tcp,
+            source: IpEndpoint::new(source.guest.ipv4.address().into(), 40000),
+            destination: IpEndpoint::new(destination.guest.ipv4.address().into(), port),
+        }
+    }
+
+    fn allows(
+        table: &NetworkPolicyTable,
+        source: &NetworkMetadata,
+        destination: &NetworkMetadata,
+        port: u16,
+    ) -> bool {
+        table.allows(
+            Some(source.guest.mac),
+            Some(destination.guest.mac),
+            &tcp(source, destination, port),
+        )
+    }
+
+    #[test]
+    fn default_actions_apply_without_matching_rules() {
+        let open = guest(2, "open", None);
+        let closed = guest(
+            3,
+            "closed",
+            Some(GuestNetworkPolicy {
+                ingress_default: GuestNetworkPolicyAction::Deny.into(),
+                ..Default::default()
+            }),
+        );
+        let silent = guest(
+            4,
+            "silent",
+            Some(GuestNetworkPolicy {
+                egress_default: GuestNetworkPolicyAction::Deny.into(),
+                ..Default::default()
+            }),
+        );
+        let table = NetworkPolicyTable::new();
+        table.update(&[open.clone(), closed.clone(), silent.clone()]);
+
+        assert!(allows(&table, &open, &silent, 80));
+        assert!(allows(&table, &closed, &open, 80));
+        assert!(!allows(&table, &open, &closed, 80));
+        assert!(!allows(&table, &silent, &open, 80));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let client = guest(2, "client", None);
+        let deny_first = guest(
+            3,
+            "deny-first",
+            Some(GuestNetworkPolicy {
+                ingress: vec![
+                    rule(GuestNetworkPolicyAction::Deny, 22),
+                    rule(GuestNetworkPolicyAction::Allow, 0),
+                ],
+                ..Default::default()
+            }),
+        );
+        let allow_first = guest(
+            4,
+            "allow-first",
+            Some(GuestNetworkPolicy {
+                ingress: vec![
+                    rule(GuestNetworkPolicyAction::Allow, 0),
+                    rule(GuestNetworkPolicyAction::Deny, 22),
+                ],
+                ..Default::default()
+            }),
+        );
+        let table = NetworkPolicyTable::new();
+        table.update(&[client.clone(), deny_first.clone(), allow_first.clone()]);
+
+        assert!(!allows(&table, &client, &deny_first, 22));
+        assert!(allows(&table, &client, &deny_first, 80));
+        assert!(allows(&table, &client, &allow_first, 22));
+    }
+
+    #[test]
+    fn rules_match_peer_name_selector_and_cidr() {
+        let web = guest(2, "web", None);
+        let other = guest(3, "other", None);
+        let db = guest(
+            4,
+            "db",
+            Some(GuestNetworkPolicy {
+                ingress_default: GuestNetworkPolicyAction::Deny.into(),
+                egress_default: GuestNetworkPolicyAction::Deny.into(),
+                ingress: vec![
+                    GuestNetworkPolicyRule {
+                        guest: "web".to_string(),
+                        ..Default::default()
+                    },
+                    GuestNetworkPolicyRule {
+                        selector: vec![GuestSpecAnnotation {
+                            key: "role".to_string(),
+                            value: "other".to_string(),
+                        }],
+                        port: 5432,
+                        ..Default::default()
+                    },
+                ],
+                egress: vec![GuestNetworkPolicyRule {
+                    cidr: "1.1.1.0/24".to_string(),
+                    ..Default::default()
+                }],
+            }),
+        );
+        let table = NetworkPolicyTable::new();
+        table.update(&[web.clone(), other.clone(), db.clone()]);
+
+        assert!(allows(&table, &web, &db, 80));
+        assert!(allows(&table, &other, &db, 5432));
+        assert!(!allows(&table, &other, &db, 80));
+
+        let external = |addr: Ipv4Address| PolicyFlow {
+            protocol: PolicyProtocol::Udp,
+            source: IpEndpoint::new(db.guest.ipv4.address().into(), 40000),
+            destination: IpEndpoint::new(addr.into(), 53),
+        };
+        assert!(table.allows(
+            Some(db.guest.mac),
+            None,
+            &external(Ipv4Address::new(1, 1, 1, 1))
+        ));
+        assert!(!table.allows(
+            Some(db.guest.mac),
+            None,
+            &external(Ipv4Address::new(8, 8, 8, 8))
+        ));
+    }
+
+    #[test]
+    fn policy_follows_the_member_not_the_claimed_address() {
+        let open = guest(2, "open", None);
+        let silent = guest(
+            3,
+            "silent",
+            Some(GuestNetworkPolicy {
+                egress_default: GuestNetworkPolicyAction::Deny.into(),
+                ..Default::default()
+            }),
+        );
+        let table = NetworkPolicyTable::new();
+        table.update(&[open.clone(), silent.clone()]);
+
+        // a flow claiming the address of the open guest is still subject to the
+        // policy of the guest that sent it.
+        let flow = tcp(&open, &open, 80);
+        assert!(!table.allows(Some(silent.guest.mac), None, &flow));
+        assert!(table.allows(Some(open.guest.mac), None, &flow));
+    }
+
+    fn frame(
+        mac: EthernetAddress,
+        builder: impl FnOnce([u8; 6]) -> etherparse::PacketBuilderStep<etherparse::IpHeaders>,
+    ) -> Vec<u8> {
+        let builder = builder(mac.0).tcp(40000, 80, 1, 1024);
+        let mut packet = Vec::with_capacity(builder.size(0));
+        builder.write(&mut packet, &[]).unwrap();
+        packet
+    }
+
+    #[test]
+    fn verify_source_rejects_spoofed_frames() {
+        let sender = guest(2, "sender", None);
+        let other = guest(3, "other", None);
+        let table = NetworkPolicyTable::new();
+        table.update(&[sender.clone(), other.clone()]);
+
+        let ipv4 = |source: Ipv4Address| {
+            move |mac: [u8; 6]| {
+                PacketBuilder::ethernet2(mac, [0x02, 0, 0, 0, 1, 3]).ipv4(
+                    source.0,
+                    [10, 75, 0, 3],
+                    64,
+                )
+            }
+        };
+        let ipv6 = |source: Ipv6Address| {
+            move |mac: [u8; 6]| {
+                PacketBuilder::ethernet2(mac, [0x02, 0, 0, 0, 1, 3]).ipv6(
+                    source.0,
+                    other.guest.ipv6.address().0,
+                    64,
+                )
+            }
+        };
+
+        let mac = sender.guest.mac;
+        let address = sender.guest.ipv4.address();
+        assert!(table.verify_source(mac, &frame(mac, ipv4(address))));
+        assert!(table.verify_source(mac, &frame(mac, ipv6(sender.guest.ipv6.address()))));
+        assert!(!table.verify_source(mac, &frame(other.guest.mac, ipv4(address))));
+        assert!(!table.verify_source(mac, &frame(mac, ipv4(other.guest.ipv4.address()))));
+        assert!(!table.verify_source(
+            mac,
+            &frame(mac, ipv6(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2)))
+        ));
+        assert!(!table.verify_source(
+            EthernetAddress([0x02, 0, 0, 0, 1, 9]),
+            &frame(mac, ipv4(address))
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use krata::v1::common::GuestPortProtocol;
+use log::{debug, info, warn};
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Mutex,
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
+use uuid::Uuid;
+
+const UDP_TIMEOUT_SECS: u64 = 60;
+const UDP_BUFFER_LEN: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    pub host_port: u16,
+    pub guest_port: u16,
+    pub protocol: GuestPortProtocol,
+}
+
+pub struct PortForwarder {
+    uuid: Uuid,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl PortForwarder {
+    pub async fn launch(
+        uuid: Uuid,
+        guest: Ipv4Addr,
+        forwards: &[PortForward],
+    ) -> Result<PortForwarder> {
+        let mut forwarder = PortForwarder {
+            uuid,
+            tasks: Vec::new(),
+        };
+        for forward in forwards {
+            let host = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), forward.host_port);
+            let target = SocketAddr::new(IpAddr::V4(guest), forward.guest_port);
+            let task = match forward.protocol {
+                GuestPortProtocol::Tcp => {
+                    let listener = TcpListener::bind(host).await?;
+                    tokio::task::spawn(async move {
+                        if let Err(error) = PortForwarder::process_tcp(listener, target).await {
+                            warn!("tcp port forward to {} failed: {}", target, error);
+                        }
+                    })
+                }
+
+                GuestPortProtocol::Udp => {
+                    let socket = UdpSocket::bind(host).await?;
+                    tokio::task::spawn(async move {
+                        if let Err(error) = PortForwarder::process_udp(socket, target).await {
+                            warn!("udp port forward to {} failed: {}", target, error);
+                        }
+                    })
+                }
+            };
+            info!(
+                "forwarding host port {} to krata guest {} port {} ({:?})",
+                forward.host_port, uuid, forward.guest_port, forward.protocol
+            );
+            forwarder.tasks.push(task);
+        }
+        Ok(forwarder)
+    }
+
+    // connection tasks live in a JoinSet owned by the listener task, so aborting
+    // the listener when the forwarder is dropped also aborts every connection.
+    async fn process_tcp(listener: TcpListener, target: SocketAddr) -> Result<()> {
+        let mut connections = JoinSet::new();
+        loop {
+            let (mut inbound, peer) = listener.accept().await?;
+            while connections.try_join_next().is_some() {}
+            connections.spawn(async move {
+                let mut outbound = match TcpStream::connect(target).await {
+                    Ok(outbound) => outbound,
+                    Err(error) => {
+                        debug!("failed to connect to {} for {}: {}", target, peer, error);
+                        return;
+                    }
+                };
+                if let Err(error) = copy_bidirectional(&mut inbound, &mut outbound).await {
+                    debug!("tcp forward from {} to {} closed: {}", peer, target, error);
+                }
+            });
+        }
+    }
+
+    async fn process_udp(socket: UdpSocket, target: SocketAddr) -> Result<()> {
+        let socket = Arc::new(socket);
+        let peers: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let mut replies = JoinSet::new();
+        let mut buffer = vec![0u8; UDP_BUFFER_LEN];
+        loop {
+            let (size, peer) = socket.recv_from(&mut buffer).await?;
+            while replies.try_join_next().is_some() {}
+            let upstream = {
+                let mut map = peers.lock().await;
+                if let Some(upstream) = map.get(&peer) {
+                    upstream.clone()
+                } else {
+                    let upstream = match PortForwarder::connect_udp(target).await {
+                        Ok(upstream) => Arc::new(upstream),
+                        Err(error) => {
+                            warn!(
+                                "failed to open udp forward from {} to {}: {}",
+                                peer, target, error
+                            );
+                            continue;
+                        }
+                    };
+                    map.insert(peer, upstream.clone());
+
+                    let socket = socket.clone();
+                    let peers = peers.clone();
+                    let reader = upstream.clone();
+                    replies.spawn(async move {
+                        let mut buffer = vec![0u8; UDP_BUFFER_LEN];
+                        loop {
+                            let result = timeout(
+                                Duration::from_secs(UDP_TIMEOUT_SECS),
+                                reader.recv(&mut buffer),
+                            )
+                            .await;
+                            let Ok(Ok(size)) = result else {
+                                break;
+                            };
+                            if let Err(error) = socket.send_to(&buffer[0..size], peer).await {
+                                debug!("failed to send udp reply to {}: {}", peer, error);
+                                break;
+                            }
+                        }
+                        peers.lock().await.remove(&peer);
+                    });
+                    upstream
+                }
+            };
+
+            if let Err(error) = upstream.send(&buffer[0..size]).await {
+                debug!("failed to forward udp packet to {}: {}", target, error);
+            }
+        }
+    }
+
+    async fn connect_udp(target: SocketAddr) -> Result<UdpSocket> {
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).await?;
+        socket.connect(target).await?;
+        Ok(socket)
+    }
+}
+
+impl Drop for PortForwarder {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+
+        if !self.tasks.is_empty() {
+            info!("stopped port forwarding for krata guest {}", self.uuid);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::policy::{NetworkPolicyTable, PolicyFlow};
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use etherparse::{EtherType, Ethernet2Header, IpNumber, Ipv4Header, Ipv6Header, TcpHeader};
@@ -6,14 +7,16 @@ use smoltcp::wire::EthernetAddress;
 use std::{
     collections::{hash_map::Entry, HashMap},
     sync::Arc,
+    time::{Duration, Instant},
 };
+
 use tokio::sync::broadcast::{
     channel as broadcast_channel, Receiver as BroadcastReceiver, Sender as BroadcastSender,
 };
 use tokio::{
     select,
     sync::{
-        mpsc::{channel, Receiver, Sender},
+        mpsc::{channel, error::TrySendError, Receiver, Sender},
         Mutex,
     },
     task::JoinHandle,
@@ -23,15 +26,36 @@ const TO_BRIDGE_QUEUE_LEN: usize = 3000;
 const FROM_BRIDGE_QUEUE_LEN: usize = 3000;
 const BROADCAST_QUEUE_LEN: usize = 3000;
 const MEMBER_LEAVE_QUEUE_LEN: usize = 30;
+const POLICY_FLOW_TTL: Duration = Duration::from_secs(120);
+const POLICY_FLOW_GENERATION_LEN: usize = 8192;
 
 #[derive(Debug)]
 struct BridgeMember {
     pub from_bridge_sender: Sender<BytesMut>,
+    pub guest: bool,
+}
+
+/// Sends packets to the bridge on behalf of a single member.
+#[derive(Clone)]
+pub struct BridgeSender {
+    mac: EthernetAddress,
+    sender: Sender<(EthernetAddress, BytesMut)>,
+}
+
+impl BridgeSender {
+    pub fn try_send(&self, packet: BytesMut) -> Result<(), TrySendError<BytesMut>> {
+        self.sender
+            .try_send((self.mac, packet))
+            .map_err(|error| match error {
+                TrySendError::Full((_, packet)) => TrySendError::Full(packet),
+                TrySendError::Closed((_, packet)) => TrySendError::Closed(packet),
+            })
+    }
 }
 
 pub struct BridgeJoinHandle {
     mac: EthernetAddress,
-    pub to_bridge_sender: Sender<BytesMut>,
+    pub to_bridge_sender: BridgeSender,
     pub from_bridge_receiver: Receiver<BytesMut>,
     pub from_broadcast_receiver: BroadcastReceiver<BytesMut>,
     member_leave_sender: Sender<EthernetAddress>,
@@ -50,9 +74,67 @@ impl Drop for BridgeJoinHandle {
 
 type VirtualBridgeMemberMap = Arc<Mutex<HashMap<EthernetAddress, BridgeMember>>>;
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct PolicyFlowKey {
+    source: EthernetAddress,
+    destination: EthernetAddress,
+    flow: PolicyFlow,
+}
+
+impl PolicyFlowKey {
+    fn reverse(&self) -> PolicyFlowKey {
+        PolicyFlowKey {
+            source: self.destination,
+            destination: self.source,
+            flow: self.flow.reverse(),
+        }
+    }
+}
+
+/// Permitted flows, bounded without ever scanning on insert: once the current
+/// generation is full it replaces the previous one, and flows of the previous
+/// generation are only kept when they are seen again.
+#[derive(Default)]
+struct PolicyFlowCache {
+    current: HashMap<PolicyFlowKey, Instant>,
+    previous: HashMap<PolicyFlowKey, Instant>,
+}
+
+impl PolicyFlowCache {
+    fn touch(&mut self, key: &PolicyFlowKey, now: Instant) -> bool {
+        if let Some(seen) = self.current.get_mut(key) {
+            if now.duration_since(*seen) < POLICY_FLOW_TTL {
+                *seen = now;
+                return true;
+            }
+            return false;
+        }
+
+        match self.previous.remove(key) {
+            Some(seen) if now.duration_since(seen) < POLICY_FLOW_TTL => {
+                self.insert(*key, now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, key: PolicyFlowKey, now: Instant) {
+        if self.current.len() >= POLICY_FLOW_GENERATION_LEN {
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.insert(key, now);
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&PolicyFlowKey) -> bool) {
+        self.current.retain(|key, _| keep(key));
+        self.previous.retain(|key, _| keep(key));
+    }
+}
+
 #[derive(Clone)]
 pub struct VirtualBridge {
-    to_bridge_sender: Sender<BytesMut>,
+    to_bridge_sender: Sender<(EthernetAddress, BytesMut)>,
     from_broadcast_sender: BroadcastSender<BytesMut>,
     member_leave_sender: Sender<EthernetAddress>,
     members: VirtualBridgeMemberMap,
@@ -61,13 +143,14 @@ pub struct VirtualBridge {
 
 enum VirtualBridgeSelect {
     BroadcastSent,
-    PacketReceived(Option<BytesMut>),
+    PacketReceived(Option<(EthernetAddress, BytesMut)>),
     MemberLeave(Option<EthernetAddress>),
 }
 
 impl VirtualBridge {
-    pub fn new() -> Result<VirtualBridge> {
-        let (to_bridge_sender, to_bridge_receiver) = channel::<BytesMut>(TO_BRIDGE_QUEUE_LEN);
+    pub fn new(policy: NetworkPolicyTable) -> Result<VirtualBridge> {
+        let (to_bridge_sender, to_bridge_receiver) =
+            channel::<(EthernetAddress, BytesMut)>(TO_BRIDGE_QUEUE_LEN);
         let (member_leave_sender, member_leave_reciever) =
             channel::<EthernetAddress>(MEMBER_LEAVE_QUEUE_LEN);
         let (from_broadcast_sender, from_broadcast_receiver) =
@@ -80,6 +163,7 @@ impl VirtualBridge {
             tokio::task::spawn(async move {
                 if let Err(error) = VirtualBridge::process(
                     members,
+                    policy,
                     member_leave_reciever,
                     to_bridge_receiver,
                     broadcast_rx_sender,
@@ -101,9 +185,23 @@ impl VirtualBridge {
         })
     }
 
+    /// Joins a trusted member, such as the host, whose frames are not verified.
     pub async fn join(&self, mac: EthernetAddress) -> Result<BridgeJoinHandle> {
+        self.join_member(mac, false).await
+    }
+
+    /// Joins a guest, frames that do not carry the mac and addresses assigned
+    /// to the guest are dropped.
+    pub async fn join_guest(&self, mac: EthernetAddress) -> Result<BridgeJoinHandle> {
+        self.join_member(mac, true).await
+    }
+
+    async fn join_member(&self, mac: EthernetAddress, guest: bool) -> Result<BridgeJoinHandle> {
         let (from_bridge_sender, from_bridge_receiver) = channel::<BytesMut>(FROM_BRIDGE_QUEUE_LEN);
-        let member = BridgeMember { from_bridge_sender };
+        let member = BridgeMember {
+            from_bridge_sender,
+            guest,
+        };
 
         match self.members.lock().await.entry(mac) {
             Entry::Occupied(_) => {
@@ -119,17 +217,23 @@ impl VirtualBridge {
             member_leave_sender: self.member_leave_sender.clone(),
             from_bridge_receiver,
             from_broadcast_receiver: self.from_broadcast_sender.subscribe(),
-            to_bridge_sender: self.to_bridge_sender.clone(),
+            to_bridge_sender: BridgeSender {
+                mac,
+                sender: self.to_bridge_sender.clone(),
+            },
         })
     }
 
     async fn process(
         members: VirtualBridgeMemberMap,
+        policy: NetworkPolicyTable,
         mut member_leave_reciever: Receiver<EthernetAddress>,
-        mut to_bridge_receiver: Receiver<BytesMut>,
+        mut to_bridge_receiver: Receiver<(EthernetAddress, BytesMut)>,
         broadcast_rx_sender: BroadcastSender<BytesMut>,
         mut from_broadcast_receiver: BroadcastReceiver<BytesMut>,
     ) -> Result<()> {
+        let mut flows = PolicyFlowCache::default();
+        let mut policy_generation = policy.generation();
         loop {
             let selection = select! {
                 biased;
@@ -139,7 +243,7 @@ impl VirtualBridge {
             };
 
             match selection {
-                VirtualBridgeSelect::PacketReceived(Some(mut packet)) => {
+                VirtualBridgeSelect::PacketReceived(Some((sender, mut packet))) => {
                     let (header, payload) = match Ethernet2Header::from_slice(&packet) {
                         Ok(data) => data,
                         Err(error) => {
@@ -172,24 +276,74 @@ impl VirtualBridge {
                         }
                     }
 
+                    let members = members.lock().await;
+                    let Some(member) = members.get(&sender) else {
+                        continue;
+                    };
+                    if member.guest && !policy.verify_source(sender, &packet) {
+                        trace!("virtual bridge dropped spoofed packet from {}", sender);
+                        continue;
+                    }
+
+                    if policy.generation() != policy_generation {
+                        policy_generation = policy.generation();
+                        flows.retain(|key| {
+                            policy.allows(Some(key.source), Some(key.destination), &key.flow)
+                        });
+                    }
+
+                    let flow = PolicyFlow::from_ethernet(&packet);
                     let destination = EthernetAddress(header.destination);
                     if destination.is_multicast() {
-                        broadcast_rx_sender.send(packet)?;
+                        let Some(flow) = flow else {
+                            broadcast_rx_sender.send(packet)?;
+                            continue;
+                        };
+
+                        // every receiver of multicast traffic is subject to its own policy.
+                        for (mac, member) in members.iter() {
+                            if *mac == sender {
+                                continue;
+                            }
+                            let key = PolicyFlowKey {
+                                source: sender,
+                                destination: *mac,
+                                flow,
+                            };
+                            if VirtualBridge::permit(&policy, &mut flows, key) {
+                                let _ = member.from_bridge_sender.try_send(packet.clone());
+                            }
+                        }
                         continue;
                     }
-                    match members.lock().await.get(&destination) {
-                        Some(member) => {
-                            member.from_bridge_sender.try_send(packet)?;
+
+                    let Some(member) = members.get(&destination) else {
+                        trace!("no bridge member with address: {}", destination);
+                        continue;
+                    };
+
+                    if let Some(flow) = flow {
+                        let key = PolicyFlowKey {
+                            source: sender,
+                            destination,
+                            flow,
+                        };
+                        if !VirtualBridge::permit(&policy, &mut flows, key) {
                             trace!(
-                                "sending bridged packet from {} to {}",
-                                EthernetAddress(header.source),
-                                EthernetAddress(header.destination)
+                                "network policy denied packet from {} to {}",
+                                flow.source,
+                                flow.destination
                             );
-                        }
-                        None => {
-                            trace!("no bridge member with address: {}", destination);
+                            continue;
                         }
                     }
+
+                    member.from_bridge_sender.try_send(packet)?;
+                    trace!(
+                        "sending bridged packet from {} to {}",
+                        EthernetAddress(header.source),
+                        EthernetAddress(header.destination)
+                    );
                 }
 
                 VirtualBridgeSelect::MemberLeave(Some(mac)) => {
@@ -205,4 +359,22 @@ impl VirtualBridge {
         }
         Ok(())
     }
+
+    /// Packets of a flow, or replies to it, that were already permitted skip policy evaluation.
+    fn permit(
+        policy: &NetworkPolicyTable,
+        flows: &mut PolicyFlowCache,
+        key: PolicyFlowKey,
+    ) -> bool {
+        let now = Instant::now();
+        if flows.touch(&key, now) || flows.touch(&key.reverse(), now) {
+            return true;
+        }
+
+        if !policy.allows(Some(key.source), Some(key.destination), &key.flow) {
+            return false;
+        }
+        flows.insert(key, now);
+        true
+    }
 }
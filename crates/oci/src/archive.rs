@@ -0,0 +1,397 @@
+use super::{
+    blobs::verify_digest, fetch::OciResolvedImage, name::ImageName, registry::OciRegistryPlatform,
+};
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use oci_spec::image::{
+    Descriptor, ImageConfiguration, ImageIndex, ImageManifest, ImageManifestBuilder, MediaType,
+    PlatformBuilder, ToDockerV2S2, SCHEMA_VERSION,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, BufWriter},
+};
+use tokio_stream::StreamExt;
+use tokio_tar::Archive;
+
+const OCI_LAYOUT_INDEX: &str = "index.json";
+const DOCKER_SAVE_MANIFEST: &str = "manifest.json";
+const ANNOTATION_CONTAINERD_IMAGE_NAME: &str = "io.containerd.image.name";
+const ANNOTATION_OCI_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// An image named by an archive, the reference is absent when it was saved untagged.
+#[derive(Clone, Debug)]
+pub struct OciArchiveImage {
+    pub reference: Option<String>,
+    pub descriptor: Descriptor,
+}
+
+/// A local image archive, either an OCI image layout or a `docker save` tarball.
+/// Images in the legacy `docker save` format carry no manifest, so one is synthesized
+/// from their config and uncompressed layers.
+#[derive(Clone, Debug)]
+pub struct OciImageArchive {
+    path: PathBuf,
+    images: Vec<OciArchiveImage>,
+    blobs: HashMap<String, String>,
+    manifests: HashMap<String, ImageManifest>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerSaveManifest {
+    config: String,
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+impl OciImageArchive {
+    pub async fn open(path: &Path) -> Result<OciImageArchive> {
+        let mut files: HashMap<String, u64> = HashMap::new();
+        let mut links: HashMap<String, String> = HashMap::new();
+        let mut index: Option<ImageIndex> = None;
+        let mut docker: Option<Vec<DockerSaveManifest>> = None;
+
+        let file = File::open(path).await?;
+        let mut archive = Archive::new(file);
+        let mut entries = archive.entries()?;
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let name = archive_path(&String::from_utf8(entry.path_bytes().to_vec())?);
+            let kind = entry.header().entry_type();
+            if kind.is_symlink() || kind.is_hard_link() {
+                if let Some(target) = entry.link_name_bytes() {
+                    let target = String::from_utf8(target.to_vec())?;
+                    let target = if kind.is_symlink() {
+                        let parent = Path::new(&name).parent().unwrap_or(Path::new(""));
+                        path_clean::clean(parent.join(target))
+                            .to_string_lossy()
+                            .to_string()
+                    } else {
+                        archive_path(&target)
+                    };
+                    links.insert(name, target);
+                }
+                continue;
+            }
+
+            if !kind.is_file() {
+                continue;
+            }
+
+            if name == OCI_LAYOUT_INDEX {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).await?;
+                index = Some(serde_json::from_str(&content)?);
+            } else if name == DOCKER_SAVE_MANIFEST {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).await?;
+                docker = Some(serde_json::from_str(&content)?);
+            }
+            files.insert(name, entry.header().size()?);
+        }
+
+        let resolve_link = |name: &str| -> String {
+            let mut name = name.to_string();
+            // bounded to guard against link cycles
+            for _ in 0..16 {
+                match links.get(&name) {
+                    Some(target) => name = target.clone(),
+                    None => break,
+                }
+            }
+            name
+        };
+
+        let mut archive = OciImageArchive {
+            path: path.to_path_buf(),
+            images: Vec::new(),
+            blobs: HashMap::new(),
+            manifests: HashMap::new(),
+        };
+
+        for name in files.keys().chain(links.keys()) {
+            let Some((digest_type, digest_content)) =
+                name.strip_prefix("blobs/").and_then(|x| x.split_once('/'))
+            else {
+                continue;
+            };
+            archive.blobs.insert(
+                format!("{}:{}", digest_type, digest_content),
+                resolve_link(name),
+            );
+        }
+
+        if let Some(index) = index {
+            for descriptor in index.manifests() {
+                archive.images.push(OciArchiveImage {
+                    reference: descriptor_reference(descriptor),
+                    descriptor: descriptor.clone(),
+                });
+            }
+        } else if let Some(docker) = docker {
+            for image in docker {
+                archive
+                    .add_docker_image(image, &files, &resolve_link)
+                    .await?;
+            }
+        } else {
+            return Err(anyhow!(
+                "{:?} is neither an OCI image layout nor a docker save archive",
+                path
+            ));
+        }
+        debug!(
+            "opened image archive {:?} images={}",
+            path,
+            archive.images.len()
+        );
+        Ok(archive)
+    }
+
+    async fn add_docker_image(
+        &mut self,
+        image: DockerSaveManifest,
+        files: &HashMap<String, u64>,
+        resolve_link: &impl Fn(&str) -> String,
+    ) -> Result<()> {
+        let config_path = resolve_link(&archive_path(&image.config));
+        let config_bytes = read_archive_entry(&self.path, &config_path)
+            .await?
+            .ok_or_else(|| anyhow!("docker save archive is missing config {}", image.config))?;
+        let config: ImageConfiguration = serde_json::from_slice(&config_bytes)?;
+        let config_digest = format!("sha256:{}", sha256::digest(config_bytes.as_slice()));
+        self.blobs.insert(config_digest.clone(), config_path);
+
+        let diff_ids = config.rootfs().diff_ids();
+        if diff_ids.len() != image.layers.len() {
+            return Err(anyhow!(
+                "docker save image {} has {} layers but its config lists {}",
+                image.config,
+                image.layers.len(),
+                diff_ids.len()
+            ));
+        }
+
+        // layers are saved uncompressed, so each one is addressed by its diff id
+        let mut layers = Vec::new();
+        for (layer, diff_id) in image.layers.iter().zip(diff_ids) {
+            let layer_path = resolve_link(&archive_path(layer));
+            let size = files
+                .get(&layer_path)
+                .ok_or_else(|| anyhow!("docker save archive is missing layer {}", layer))?;
+            layers.push(Descriptor::new(
+                MediaType::ImageLayer,
+                *size as i64,
+                diff_id.clone(),
+            ));
+            self.blobs.insert(diff_id.clone(), layer_path);
+        }
+
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageManifest)
+            .config(Descriptor::new(
+                MediaType::ImageConfig,
+                config_bytes.len() as i64,
+                config_digest,
+            ))
+            .layers(layers)
+            .build()?;
+        let manifest_bytes = serde_json::to_vec(&manifest)?;
+        let manifest_digest = format!("sha256:{}", sha256::digest(manifest_bytes.as_slice()));
+        let mut descriptor = Descriptor::new(
+            MediaType::ImageManifest,
+            manifest_bytes.len() as i64,
+            manifest_digest.clone(),
+        );
+        descriptor.set_platform(Some(
+            PlatformBuilder::default()
+                .architecture(config.architecture().clone())
+                .os(config.os().clone())
+                .build()?,
+        ));
+        self.manifests.insert(manifest_digest, manifest);
+
+        let mut references = image
+            .repo_tags
+            .unwrap_or_default()
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        if references.is_empty() {
+            references.push(None);
+        }
+        for reference in references {
+            self.images.push(OciArchiveImage {
+                reference,
+                descriptor: descriptor.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn images(&self) -> &[OciArchiveImage] {
+        &self.images
+    }
+
+    /// Finds the platform manifest for an image, pinned images match by digest since the
+    /// name they were saved under may differ.
+    pub async fn resolve(
+        &self,
+        image: &ImageName,
+        platform: &OciRegistryPlatform,
+    ) -> Result<Option<OciResolvedImage>> {
+        let reference = image.to_string();
+        for candidate in &self.images {
+            if let Some(ref digest) = image.digest {
+                if candidate.descriptor.digest() != digest {
+                    continue;
+                }
+            } else {
+                let Some(ref name) = candidate.reference else {
+                    continue;
+                };
+                let name = ImageName::parse(name)
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|_| name.clone());
+                if name != reference {
+                    continue;
+                }
+            }
+
+            if !platform_matches(&candidate.descriptor, platform) {
+                continue;
+            }
+
+            let mut descriptor = candidate.descriptor.clone();
+            if is_index(&descriptor) {
+                let Some(index) = self.load_json_blob::<ImageIndex>(&descriptor).await? else {
+                    continue;
+                };
+                let Some(found) = index
+                    .manifests()
+                    .iter()
+                    .find(|x| platform_matches(x, platform))
+                else {
+                    return Err(anyhow!(
+                        "image {} in archive has no manifest for {}/{}",
+                        image,
+                        platform.os,
+                        platform.arch
+                    ));
+                };
+                descriptor = found.clone();
+            }
+
+            if let Some(manifest) = self.load_json_blob::<ImageManifest>(&descriptor).await? {
+                debug!(
+                    "found seeded manifest image={} manifest={}",
+                    image,
+                    descriptor.digest()
+                );
+                return Ok(Some(OciResolvedImage {
+                    name: image.clone(),
+                    digest: descriptor.digest().clone(),
+                    manifest,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn load_json_blob<T: DeserializeOwned>(
+        &self,
+        descriptor: &Descriptor,
+    ) -> Result<Option<T>> {
+        if let Some(manifest) = self.manifests.get(descriptor.digest()) {
+            return Ok(Some(serde_json::from_value(serde_json::to_value(
+                manifest,
+            )?)?));
+        }
+        let Some(path) = self.blobs.get(descriptor.digest()) else {
+            return Ok(None);
+        };
+        let Some(content) = read_archive_entry(&self.path, path).await? else {
+            return Ok(None);
+        };
+        verify_digest(descriptor.digest(), &content)?;
+        Ok(Some(serde_json::from_slice(&content)?))
+    }
+
+    pub async fn extract_blob(&self, descriptor: &Descriptor, to: &Path) -> Result<bool> {
+        let Some(want) = self.blobs.get(descriptor.digest()) else {
+            return Ok(false);
+        };
+        let file = File::open(&self.path).await?;
+        let mut archive = Archive::new(file);
+        let mut entries = archive.entries()?;
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let path = archive_path(&String::from_utf8(entry.path_bytes().to_vec())?);
+            if path == *want && entry.header().entry_type().is_file() {
+                let file = File::create(to).await?;
+                let mut bufwrite = BufWriter::new(file);
+                tokio::io::copy(&mut entry, &mut bufwrite).await?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+async fn read_archive_entry(archive: &Path, want: &str) -> Result<Option<Vec<u8>>> {
+    let file = File::open(archive).await?;
+    let mut archive = Archive::new(file);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let path = archive_path(&String::from_utf8(entry.path_bytes().to_vec())?);
+        if path == want && entry.header().entry_type().is_file() {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).await?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+fn archive_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_string()
+}
+
+fn descriptor_reference(descriptor: &Descriptor) -> Option<String> {
+    let annotations = descriptor.annotations().as_ref()?;
+    if let Some(name) = annotations.get(ANNOTATION_CONTAINERD_IMAGE_NAME) {
+        return Some(name.clone());
+    }
+    // the ref name is frequently only a tag, which does not name an image on its own
+    annotations
+        .get(ANNOTATION_OCI_REF_NAME)
+        .filter(|name| name.contains('/') || name.contains(':'))
+        .cloned()
+}
+
+fn is_index(descriptor: &Descriptor) -> bool {
+    *descriptor.media_type() == MediaType::ImageIndex
+        || MediaType::ImageIndex
+            .to_docker_v2s2()
+            .map(|x| descriptor.media_type().to_string() == x)
+            .unwrap_or(false)
+}
+
+fn platform_matches(descriptor: &Descriptor, platform: &OciRegistryPlatform) -> bool {
+    match descriptor.platform() {
+        Some(found) => *found.architecture() == platform.arch && *found.os() == platform.os,
+        None => true,
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use serde::Deserialize;
+use tokio::{fs, io::AsyncWriteExt, process::Command};
+
+use crate::name::DOCKER_HUB_REGISTRY;
+
+const DOCKER_HELPER_TOKEN_USERNAME: &str = "<token>";
+const DOCKER_HELPER_NOT_FOUND: &str = "credentials not found";
+const DOCKER_HUB_HELPER_SERVER: &str = "https://index.docker.io/v1/";
+
+/// Credentials presented to a registry.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub enum OciRegistryAuth {
+    #[default]
+    Anonymous,
+    /// Username and password, used directly for basic auth or for the token exchange.
+    Basic { username: String, password: String },
+    /// OAuth2 refresh token exchanged for a bearer token.
+    IdentityToken(String),
+    /// Bearer token sent to the registry as-is.
+    RegistryToken(String),
+}
+
+impl fmt::Debug for OciRegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OciRegistryAuth::Anonymous => write!(f, "Anonymous"),
+            OciRegistryAuth::Basic { username, .. } => write!(f, "Basic({})", username),
+            OciRegistryAuth::IdentityToken(_) => write!(f, "IdentityToken"),
+            OciRegistryAuth::RegistryToken(_) => write!(f, "RegistryToken"),
+        }
+    }
+}
+
+/// Resolves credentials for a registry host. Explicit credentials take precedence
+/// over the docker config file, which is re-read on every lookup.
+#[derive(Clone, Debug, Default)]
+pub struct OciCredentialStore {
+    docker_config: Option<PathBuf>,
+    registries: HashMap<String, OciRegistryAuth>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerConfigAuth>,
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+struct DockerConfigAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    identitytoken: Option<String>,
+    registrytoken: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerHelperCredentials {
+    username: String,
+    secret: String,
+}
+
+impl OciCredentialStore {
+    pub fn new() -> OciCredentialStore {
+        OciCredentialStore::default()
+    }
+
+    pub fn with_docker_config(path: Option<PathBuf>) -> OciCredentialStore {
+        OciCredentialStore {
+            docker_config: path,
+            registries: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, registry: &str, auth: OciRegistryAuth) {
+        self.registries.insert(normalize_registry(registry), auth);
+    }
+
+    pub async fn lookup(&self, registry: &str) -> Result<OciRegistryAuth> {
+        let registry = normalize_registry(registry);
+        if let Some(auth) = self.registries.get(&registry) {
+            return Ok(auth.clone());
+        }
+
+        let Some(ref path) = self.docker_config else {
+            return Ok(OciRegistryAuth::Anonymous);
+        };
+        let auth = lookup_docker_config(path, &registry).await?;
+        debug!(
+            "registry {} credentials from {:?}: {:?}",
+            registry, path, auth
+        );
+        Ok(auth)
+    }
+}
+
+async fn lookup_docker_config(path: &Path, registry: &str) -> Result<OciRegistryAuth> {
+    if !path.exists() {
+        return Ok(OciRegistryAuth::Anonymous);
+    }
+    let content = fs::read_to_string(path).await?;
+    let config: DockerConfig = serde_json::from_str(&content)
+        .map_err(|error| anyhow!("failed to parse docker config {:?}: {}", path, error))?;
+
+    let helper = config
+        .cred_helpers
+        .iter()
+        .find(|(key, _)| normalize_registry(key) == registry)
+        .map(|(_, helper)| helper)
+        .or(config.creds_store.as_ref());
+    if let Some(helper) = helper {
+        if let Some(auth) = lookup_docker_helper(helper, registry).await? {
+            return Ok(auth);
+        }
+    }
+
+    let Some(entry) = config
+        .auths
+        .iter()
+        .find(|(key, _)| normalize_registry(key) == registry)
+        .map(|(_, entry)| entry)
+    else {
+        return Ok(OciRegistryAuth::Anonymous);
+    };
+
+    if let Some(ref token) = entry.registrytoken {
+        return Ok(OciRegistryAuth::RegistryToken(token.clone()));
+    }
+    if let Some(ref token) = entry.identitytoken {
+        return Ok(OciRegistryAuth::IdentityToken(token.clone()));
+    }
+    if let (Some(username), Some(password)) = (&entry.username, &entry.password) {
+        return Ok(OciRegistryAuth::Basic {
+            username: username.clone(),
+            password: password.clone(),
+        });
+    }
+    if let Some(ref auth) = entry.auth {
+        let decoded = String::from_utf8(STANDARD.decode(auth.trim())?)?;
+        let (username, password) = decoded
+            .split_once(':')
+            .ok_or_else(|| anyhow!("docker config auth for {} is malformed", registry))?;
+        return Ok(OciRegistryAuth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+    }
+    Ok(OciRegistryAuth::Anonymous)
+}
+
+async fn lookup_docker_helper(helper: &str, registry: &str) -> Result<Option<OciRegistryAuth>> {
+    let program = format!("docker-credential-{}", helper);
+    let mut child = Command::new(&program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| anyhow!("failed to run credential helper {}: {}", program, error))?;
+    // docker stores hub credentials under the legacy index address
+    let server = if registry == DOCKER_HUB_REGISTRY {
+        DOCKER_HUB_HELPER_SERVER
+    } else {
+        registry
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.contains(DOCKER_HELPER_NOT_FOUND) {
+            return Ok(None);
+        }
+        return Err(anyhow!(
+            "credential helper {} failed for {}: {}",
+            program,
+            registry,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let credentials: DockerHelperCredentials = serde_json::from_slice(&output.stdout)?;
+    if credentials.username == DOCKER_HELPER_TOKEN_USERNAME {
+        return Ok(Some(OciRegistryAuth::IdentityToken(credentials.secret)));
+    }
+    Ok(Some(OciRegistryAuth::Basic {
+        username: credentials.username,
+        password: credentials.secret,
+    }))
+}
+
+/// Reduces a registry key such as `https://registry.example.com/v1/` to its host.
+/// The Docker Hub aliases all reduce to the Docker Hub registry.
+fn normalize_registry(registry: &str) -> String {
+    let registry = registry
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let host = registry
+        .split_once('/')
+        .map(|x| x.0)
+        .unwrap_or(registry)
+        .to_lowercase();
+    match host.as_str() {
+        "docker.io" | "index.docker.io" => DOCKER_HUB_REGISTRY.to_string(),
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::{ImageName, DOCKER_HUB_MIRROR};
+
+    struct DockerConfigFile(PathBuf);
+
+    impl DockerConfigFile {
+        fn new(content: &str) -> DockerConfigFile {
+            let path =
+                std::env::temp_dir().join(format!("krata-docker-{}.json", uuid::Uuid::new_v4()));
+            std::fs::write(&path, content).unwrap();
+            DockerConfigFile(path)
+        }
+
+        fn store(&self) -> OciCredentialStore {
+            OciCredentialStore::with_docker_config(Some(self.0.clone()))
+        }
+    }
+
+    impl Drop for DockerConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn basic(username: &str, password: &str) -> OciRegistryAuth {
+        OciRegistryAuth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn lookup_reads_each_kind_of_docker_config_entry() {
+        let config = DockerConfigFile::new(&format!(
+            r#"{{"auths": {{
+                "https://registry.example.com/v1/": {{"auth": "{}"}},
+                "plain.example.com": {{"username": "user", "password": "pass"}},
+                "identity.example.com": {{"identitytoken": "refresh"}},
+                "token.example.com": {{"registrytoken": "bearer"}}
+            }}}}"#,
+            STANDARD.encode("alice:secret:with:colons")
+        ));
+        let store = config.store();
+
+        assert_eq!(
+            store.lookup("registry.example.com").await.unwrap(),
+            basic("alice", "secret:with:colons")
+        );
+        assert_eq!(
+            store.lookup("plain.example.com").await.unwrap(),
+            basic("user", "pass")
+        );
+        assert_eq!(
+            store.lookup("identity.example.com").await.unwrap(),
+            OciRegistryAuth::IdentityToken("refresh".to_string())
+        );
+        assert_eq!(
+            store.lookup("token.example.com").await.unwrap(),
+            OciRegistryAuth::RegistryToken("bearer".to_string())
+        );
+        assert_eq!(
+            store.lookup("other.example.com").await.unwrap(),
+            OciRegistryAuth::Anonymous
+        );
+    }
+
+    #[tokio::test]
+    async fn docker_hub_credentials_are_never_used_for_the_mirror() {
+        let config = DockerConfigFile::new(&format!(
+            r#"{{"auths": {{"https://index.docker.io/v1/": {{"auth": "{}"}}}}}}"#,
+            STANDARD.encode("hub:secret")
+        ));
+        let store = config.store();
+        let image = ImageName::parse("alpine").unwrap();
+
+        assert_eq!(image.hostname, DOCKER_HUB_MIRROR);
+        assert_eq!(image.credential_host(), DOCKER_HUB_REGISTRY);
+        assert_eq!(
+            store.lookup(&image.credential_host()).await.unwrap(),
+            basic("hub", "secret")
+        );
+        assert_eq!(
+            store.lookup("docker.io").await.unwrap(),
+            basic("hub", "secret")
+        );
+        assert_eq!(
+            store.lookup(&image.registry_host()).await.unwrap(),
+            OciRegistryAuth::Anonymous
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_credentials_take_precedence() {
+        let config = DockerConfigFile::new(
+            r#"{"auths": {"registry.example.com": {"username": "file", "password": "pass"}}}"#,
+        );
+        let mut store = config.store();
+        store.insert(
+            "https://Registry.Example.com/v2/",
+            OciRegistryAuth::RegistryToken("explicit".to_string()),
+        );
+
+        assert_eq!(
+            store.lookup("registry.example.com").await.unwrap(),
+            OciRegistryAuth::RegistryToken("explicit".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_without_a_docker_config_is_anonymous() {
+        let missing =
+            std::env::temp_dir().join(format!("krata-docker-{}.json", uuid::Uuid::new_v4()));
+        let store = OciCredentialStore::with_docker_config(Some(missing));
+        assert_eq!(
+            store.lookup("registry.example.com").await.unwrap(),
+            OciRegistryAuth::Anonymous
+        );
+        assert_eq!(
+            OciCredentialStore::new()
+                .lookup("registry.example.com")
+                .await
+                .unwrap(),
+            OciRegistryAuth::Anonymous
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_rejects_malformed_auth() {
+        let config = DockerConfigFile::new(&format!(
+            r#"{{"auths": {{"registry.example.com": {{"auth": "{}"}}}}}}"#,
+            STANDARD.encode("no-separator")
+        ));
+        assert!(config.store().lookup("registry.example.com").await.is_err());
+    }
+}
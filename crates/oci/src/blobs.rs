@@ -0,0 +1,232 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncSeekExt,
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard, RwLock, RwLockReadGuard},
+};
+
+const BLOB_PARTIAL_SUFFIX: &str = ".partial";
+
+// serializes writers of the same blob across every store handle in the process
+static BLOB_LOCKS: OnceLock<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>> = OnceLock::new();
+
+// compiles hold this shared while they use blobs, collection holds it exclusively
+static BLOB_COLLECT_LOCK: RwLock<()> = RwLock::const_new(());
+
+/// Content-addressed store of image layer blobs. Layers shared between images are
+/// fetched once, interrupted downloads resume from a partial file and every blob is
+/// verified against its digest before it enters the store.
+#[derive(Clone, Debug)]
+pub struct OciBlobStore {
+    dir: PathBuf,
+}
+
+impl OciBlobStore {
+    pub fn new(dir: &Path) -> OciBlobStore {
+        OciBlobStore {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, digest: &str) -> Result<PathBuf> {
+        let (algorithm, hex) = split_digest(digest)?;
+        Ok(self.dir.join(algorithm).join(hex))
+    }
+
+    pub fn partial_path(&self, digest: &str) -> Result<PathBuf> {
+        let (algorithm, hex) = split_digest(digest)?;
+        Ok(self
+            .dir
+            .join(algorithm)
+            .join(format!("{}{}", hex, BLOB_PARTIAL_SUFFIX)))
+    }
+
+    pub fn contains(&self, digest: &str) -> Result<bool> {
+        Ok(self.path(digest)?.is_file())
+    }
+
+    /// Prevents blobs from being collected while they are in use.
+    pub async fn pin(&self) -> RwLockReadGuard<'static, ()> {
+        BLOB_COLLECT_LOCK.read().await
+    }
+
+    /// Acquires exclusive access to write the blob with the given digest.
+    pub async fn lock(&self, digest: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = BLOB_LOCKS
+                .get_or_init(|| Mutex::new(HashMap::new()))
+                .lock()
+                .unwrap_or_else(|x| x.into_inner());
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(digest.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Returns the partial download path of a blob, creating its parent directory.
+    pub async fn prepare(&self, digest: &str) -> Result<PathBuf> {
+        let path = self.partial_path(digest)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(path)
+    }
+
+    /// Opens the partial download of a blob positioned at its end, along with the
+    /// number of bytes already present.
+    pub async fn resume(&self, digest: &str) -> Result<(File, u64)> {
+        let path = self.prepare(digest).await?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .await?;
+        let offset = file.seek(SeekFrom::End(0)).await?;
+        if offset > 0 {
+            debug!("resuming blob digest={} offset={}", digest, offset);
+        }
+        Ok((file, offset))
+    }
+
+    /// Verifies the partial download of a blob and moves it into the store. A partial
+    /// download that fails verification is discarded so the next attempt starts over.
+    pub async fn commit(&self, digest: &str) -> Result<PathBuf> {
+        let partial = self.partial_path(digest)?;
+        let path = self.path(digest)?;
+        if let Err(error) = verify_file(&partial, digest).await {
+            fs::remove_file(&partial).await?;
+            return Err(error);
+        }
+        fs::rename(&partial, &path).await?;
+        debug!("stored blob digest={}", digest);
+        Ok(path)
+    }
+
+    /// Removes every blob not in `referenced`, returning the number of bytes reclaimed.
+    /// The referenced set is gathered once no blobs are pinned. Partial downloads are
+    /// kept so they can still be resumed.
+    pub async fn collect<F>(&self, referenced: F) -> Result<u64>
+    where
+        F: Future<Output = Result<HashSet<String>>>,
+    {
+        let _lock = BLOB_COLLECT_LOCK.write().await;
+        let referenced = referenced.await?;
+        if !self.dir.is_dir() {
+            return Ok(0);
+        }
+        let mut reclaimed = 0;
+        let mut algorithms = fs::read_dir(&self.dir).await?;
+        while let Some(algorithm) = algorithms.next_entry().await? {
+            if !algorithm.file_type().await?.is_dir() {
+                continue;
+            }
+            let algorithm_name = algorithm.file_name().to_string_lossy().to_string();
+            let mut blobs = fs::read_dir(algorithm.path()).await?;
+            while let Some(blob) = blobs.next_entry().await? {
+                let name = blob.file_name().to_string_lossy().to_string();
+                if name.ends_with(BLOB_PARTIAL_SUFFIX) {
+                    continue;
+                }
+                if referenced.contains(&format!("{}:{}", algorithm_name, name)) {
+                    continue;
+                }
+                let size = blob.metadata().await?.len();
+                if let Err(error) = fs::remove_file(blob.path()).await {
+                    warn!("failed to remove blob {:?}: {}", blob.path(), error);
+                    continue;
+                }
+                reclaimed += size;
+            }
+        }
+        debug!("collected blobs reclaimed={}", reclaimed);
+        Ok(reclaimed)
+    }
+}
+
+/// Checks content against a digest, failing when it does not match.
+pub fn verify_digest(digest: &str, content: &[u8]) -> Result<()> {
+    split_digest(digest)?;
+    let actual = format!("sha256:{}", sha256::digest(content));
+    if actual != digest {
+        return Err(anyhow!(
+            "content digest {} does not match expected digest {}",
+            actual,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+async fn verify_file(path: &Path, digest: &str) -> Result<()> {
+    split_digest(digest)?;
+    let actual = format!("sha256:{}", sha256::try_async_digest(path).await?);
+    if actual != digest {
+        return Err(anyhow!(
+            "blob digest {} does not match expected digest {}",
+            actual,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+fn split_digest(digest: &str) -> Result<(&str, &str)> {
+    match digest.split_once(':') {
+        Some(("sha256", hex))
+            if hex.len() == 64 && hex.chars().all(|x| matches!(x, '0'..='9' | 'a'..='f')) =>
+        {
+            Ok(("sha256", hex))
+        }
+        _ => Err(anyhow!(
+            "unsupported blob digest {}, expected sha256",
+            digest
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sha256 of "hello"
+    const HELLO: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn verify_digest_accepts_matching_content() {
+        verify_digest(HELLO, b"hello").unwrap();
+    }
+
+    #[test]
+    fn verify_digest_rejects_mismatched_content() {
+        assert!(verify_digest(HELLO, b"hello!").is_err());
+        assert!(verify_digest(&HELLO.to_uppercase(), b"hello").is_err());
+    }
+
+    #[test]
+    fn split_digest_only_accepts_sha256() {
+        let hex = &HELLO["sha256:".len()..];
+        assert_eq!(split_digest(HELLO).unwrap(), ("sha256", hex));
+        for digest in [
+            format!("sha512:{}", hex),
+            hex.to_string(),
+            format!("sha256:{}", &hex[1..]),
+            format!("sha256:{}0", hex),
+            format!("sha256:{}", hex.to_uppercase()),
+            format!("sha256:../{}", &hex[3..]),
+            "sha256:".to_string(),
+        ] {
+            assert!(split_digest(&digest).is_err(), "{}", digest);
+            assert!(verify_digest(&digest, b"hello").is_err(), "{}", digest);
+        }
+    }
+}
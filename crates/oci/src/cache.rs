@@ -1,23 +1,76 @@
-use super::compiler::ImageInfo;
-use anyhow::Result;
-use log::debug;
+use super::{blobs::OciBlobStore, compiler::ImageInfo, name::ImageName};
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
 use oci_spec::image::{ImageConfiguration, ImageManifest};
-use std::path::{Path, PathBuf};
-use tokio::fs;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, sync::Mutex};
+
+const IMAGE_CACHE_INDEX: &str = "index.json";
+const IMAGE_CACHE_BLOBS: &str = "blobs";
+const IMAGE_CACHE_ID_PREFIX_MIN: usize = 12;
+
+// guards the index across every cache handle in the process
+static IMAGE_CACHE_INDEX_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// An image in the cache. Entries created before the index existed have no
+/// digest or references and are dated by their file modification time.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageCacheEntry {
+    pub id: String,
+    pub digest: String,
+    pub references: Vec<String>,
+    pub size: u64,
+    pub created: u64,
+    pub last_used: u64,
+    /// Set when the image was imported from an archive rather than pulled.
+    #[serde(default)]
+    pub imported: bool,
+}
+
+impl ImageCacheEntry {
+    /// Matches an id, a manifest digest or an image reference.
+    pub fn matches(&self, query: &str) -> bool {
+        if self.id == query || self.digest == query {
+            return true;
+        }
+        let reference = ImageName::parse(query)
+            .map(|x| x.to_string())
+            .unwrap_or_else(|_| query.to_string());
+        self.references
+            .iter()
+            .any(|x| *x == query || *x == reference)
+    }
+
+    /// Matches an id prefix long enough to be meaningful.
+    pub fn matches_prefix(&self, query: &str) -> bool {
+        query.len() >= IMAGE_CACHE_ID_PREFIX_MIN && self.id.starts_with(query)
+    }
+}
 
 #[derive(Clone)]
 pub struct ImageCache {
     cache_dir: PathBuf,
+    blobs: OciBlobStore,
 }
 
 impl ImageCache {
     pub fn new(cache_dir: &Path) -> Result<ImageCache> {
         Ok(ImageCache {
             cache_dir: cache_dir.to_path_buf(),
+            blobs: OciBlobStore::new(&cache_dir.join(IMAGE_CACHE_BLOBS)),
         })
     }
 
-    pub async fn recall(&self, digest: &str) -> Result<Option<ImageInfo>> {
+    pub fn blobs(&self) -> &OciBlobStore {
+        &self.blobs
+    }
+
+    pub async fn recall(&self, digest: &str, manifest_digest: &str) -> Result<Option<ImageInfo>> {
         let mut squashfs_path = self.cache_dir.clone();
         let mut config_path = self.cache_dir.clone();
         let mut manifest_path = self.cache_dir.clone();
@@ -38,7 +91,12 @@ impl ImageCache {
                     let config_text = fs::read_to_string(&config_path).await?;
                     let config: ImageConfiguration = serde_json::from_str(&config_text)?;
                     debug!("cache hit digest={}", digest);
-                    Some(ImageInfo::new(squashfs_path.clone(), manifest, config)?)
+                    Some(ImageInfo::new(
+                        squashfs_path.clone(),
+                        manifest_digest.to_string(),
+                        manifest,
+                        config,
+                    )?)
                 } else {
                     None
                 }
@@ -49,6 +107,178 @@ impl ImageCache {
         )
     }
 
+    pub async fn list(&self) -> Result<Vec<ImageCacheEntry>> {
+        let _lock = IMAGE_CACHE_INDEX_LOCK.lock().await;
+        let entries = self.load_index().await?;
+        self.save_index(&entries).await?;
+        Ok(entries)
+    }
+
+    /// Finds the images named by an id, manifest digest or image reference, falling
+    /// back to an id prefix. A prefix that matches more than one image is an error.
+    pub async fn find(&self, query: &str) -> Result<Vec<ImageCacheEntry>> {
+        let entries = self.list().await?;
+        let exact = entries
+            .iter()
+            .filter(|x| x.matches(query))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !exact.is_empty() {
+            return Ok(exact);
+        }
+        let prefixed = entries
+            .into_iter()
+            .filter(|x| x.matches_prefix(query))
+            .collect::<Vec<_>>();
+        if prefixed.len() > 1 {
+            return Err(anyhow!(
+                "image id prefix {} is ambiguous, it matches {}",
+                query,
+                prefixed
+                    .iter()
+                    .map(|x| x.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        Ok(prefixed)
+    }
+
+    /// Records that the image was used under `reference`. A reference names a single
+    /// image, so it moves off any other entry holding it.
+    pub async fn touch(&self, id: &str, reference: &str) -> Result<()> {
+        let _lock = IMAGE_CACHE_INDEX_LOCK.lock().await;
+        let mut entries = self.load_index().await?;
+        if !entries.iter().any(|x| x.id == id) {
+            return Ok(());
+        }
+        for entry in entries.iter_mut() {
+            if entry.id == id {
+                entry.last_used = unix_now();
+                if !entry.references.iter().any(|x| x == reference) {
+                    entry.references.push(reference.to_string());
+                }
+            } else {
+                entry.references.retain(|x| x != reference);
+            }
+        }
+        self.save_index(&entries).await
+    }
+
+    /// Records `reference` on the image with the given manifest digest and marks it as
+    /// imported, which allows it to be used when its registry cannot be reached.
+    pub async fn tag_imported(&self, manifest_digest: &str, reference: &str) -> Result<()> {
+        let id = {
+            let _lock = IMAGE_CACHE_INDEX_LOCK.lock().await;
+            let mut entries = self.load_index().await?;
+            let Some(entry) = entries.iter_mut().find(|x| x.digest == manifest_digest) else {
+                return Ok(());
+            };
+            entry.imported = true;
+            let id = entry.id.clone();
+            self.save_index(&entries).await?;
+            id
+        };
+        self.touch(&id, reference).await
+    }
+
+    /// Removes an image and every layer blob no other cached image uses.
+    pub async fn remove(&self, id: &str) -> Result<Option<ImageCacheEntry>> {
+        let entry = {
+            let _lock = IMAGE_CACHE_INDEX_LOCK.lock().await;
+            let mut entries = self.load_index().await?;
+            let Some(position) = entries.iter().position(|x| x.id == id) else {
+                return Ok(None);
+            };
+            let entry = entries.remove(position);
+            for suffix in ["squashfs", "manifest.json", "config.json"] {
+                let path = self.cache_dir.join(format!("{}.{}", entry.id, suffix));
+                if path.exists() {
+                    fs::remove_file(&path).await?;
+                }
+            }
+            self.save_index(&entries).await?;
+            entry
+        };
+        debug!("cache remove digest={}", entry.id);
+        self.blobs.collect(self.referenced_blobs()).await?;
+        Ok(Some(entry))
+    }
+
+    async fn referenced_blobs(&self) -> Result<HashSet<String>> {
+        let mut referenced = HashSet::new();
+        let mut dir = fs::read_dir(&self.cache_dir).await?;
+        while let Some(item) = dir.next_entry().await? {
+            let name = item.file_name();
+            if !name
+                .to_str()
+                .map(|x| x.ends_with(".manifest.json"))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            let content = fs::read_to_string(item.path()).await?;
+            let manifest: ImageManifest = serde_json::from_str(&content)?;
+            referenced.extend(manifest.layers().iter().map(|x| x.digest().clone()));
+        }
+        Ok(referenced)
+    }
+
+    async fn load_index(&self) -> Result<Vec<ImageCacheEntry>> {
+        let index_path = self.cache_dir.join(IMAGE_CACHE_INDEX);
+        let mut entries: Vec<ImageCacheEntry> = if index_path.exists() {
+            let content = fs::read_to_string(&index_path).await?;
+            serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("image cache index is corrupt, rebuilding it: {}", error);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        entries.retain(|entry| {
+            self.cache_dir
+                .join(format!("{}.squashfs", entry.id))
+                .is_file()
+        });
+
+        let mut dir = fs::read_dir(&self.cache_dir).await?;
+        while let Some(item) = dir.next_entry().await? {
+            let name = item.file_name();
+            let Some(id) = name.to_str().and_then(|x| x.strip_suffix(".squashfs")) else {
+                continue;
+            };
+            if entries.iter().any(|x| x.id == id) {
+                continue;
+            }
+            let metadata = item.metadata().await?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map(|x| x.as_secs())
+                .unwrap_or_default();
+            entries.push(ImageCacheEntry {
+                id: id.to_string(),
+                digest: String::new(),
+                references: Vec::new(),
+                size: metadata.len(),
+                created: modified,
+                last_used: modified,
+                imported: false,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn save_index(&self, entries: &[ImageCacheEntry]) -> Result<()> {
+        let index_path = self.cache_dir.join(IMAGE_CACHE_INDEX);
+        let temporary_path = self.cache_dir.join(format!("{}.tmp", IMAGE_CACHE_INDEX));
+        fs::write(&temporary_path, serde_json::to_vec_pretty(entries)?).await?;
+        fs::rename(&temporary_path, &index_path).await?;
+        Ok(())
+    }
+
     pub async fn store(&self, digest: &str, info: &ImageInfo) -> Result<ImageInfo> {
         debug!("cache store digest={}", digest);
         let mut squashfs_path = self.cache_dir.clone();
@@ -62,10 +292,34 @@ impl ImageCache {
         fs::write(&manifest_path, manifest_text).await?;
         let config_text = serde_json::to_string_pretty(&info.config)?;
         fs::write(&config_path, config_text).await?;
+
+        let _lock = IMAGE_CACHE_INDEX_LOCK.lock().await;
+        let mut entries = self.load_index().await?;
+        let size = fs::metadata(&squashfs_path).await?.len();
+        let now = unix_now();
+        entries.retain(|x| x.id != digest);
+        entries.push(ImageCacheEntry {
+            id: digest.to_string(),
+            digest: info.digest.clone(),
+            references: Vec::new(),
+            size,
+            created: now,
+            last_used: now,
+            imported: false,
+        });
+        self.save_index(&entries).await?;
         ImageInfo::new(
             squashfs_path.clone(),
+            info.digest.clone(),
             info.manifest.clone(),
             info.config.clone(),
         )
     }
 }
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
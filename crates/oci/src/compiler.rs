@@ -1,6 +1,9 @@
+use crate::archive::OciImageArchive;
+use crate::auth::OciCredentialStore;
 use crate::cache::ImageCache;
 use crate::fetch::{OciImageDownloader, OciImageLayer};
 use crate::name::ImageName;
+use crate::progress::{OciProgressContext, OciProgressLayerPhase, OciProgressPhase};
 use crate::registry::OciRegistryPlatform;
 use anyhow::{anyhow, Result};
 use backhand::compression::Compressor;
@@ -24,6 +27,7 @@ pub const IMAGE_SQUASHFS_VERSION: u64 = 2;
 
 pub struct ImageInfo {
     pub image_squashfs: PathBuf,
+    pub digest: String,
     pub manifest: ImageManifest,
     pub config: ImageConfiguration,
 }
@@ -31,11 +35,13 @@ pub struct ImageInfo {
 impl ImageInfo {
     pub fn new(
         squashfs: PathBuf,
+        digest: String,
         manifest: ImageManifest,
         config: ImageConfiguration,
     ) -> Result<ImageInfo> {
         Ok(ImageInfo {
             image_squashfs: squashfs,
+            digest,
             manifest,
             config,
         })
@@ -45,14 +51,105 @@ impl ImageInfo {
 pub struct ImageCompiler<'a> {
     cache: &'a ImageCache,
     seed: Option<PathBuf>,
+    credentials: OciCredentialStore,
+    progress: OciProgressContext,
 }
 
 impl ImageCompiler<'_> {
-    pub fn new(cache: &ImageCache, seed: Option<PathBuf>) -> Result<ImageCompiler> {
-        Ok(ImageCompiler { cache, seed })
+    pub fn new(
+        cache: &ImageCache,
+        seed: Option<PathBuf>,
+        credentials: OciCredentialStore,
+        progress: OciProgressContext,
+    ) -> Result<ImageCompiler> {
+        Ok(ImageCompiler {
+            cache,
+            seed,
+            credentials,
+            progress,
+        })
     }
 
     pub async fn compile(&self, image: &ImageName) -> Result<ImageInfo> {
+        let seed = match self.seed {
+            Some(ref seed) => Some(OciImageArchive::open(seed).await?),
+            None => None,
+        };
+        self.compile_with_seed(image, seed).await
+    }
+
+    /// Compiles every image in the seed archive for the current platform, tagging each
+    /// under the references it was saved with. `tag` names an archive holding one image.
+    pub async fn import(&self, tag: Option<&ImageName>) -> Result<Vec<ImageInfo>> {
+        let Some(ref seed) = self.seed else {
+            return Err(anyhow!("no image archive was provided to import"));
+        };
+        let archive = OciImageArchive::open(seed).await?;
+        let platform = OciRegistryPlatform::current();
+        let mut images: Vec<(String, Vec<ImageName>)> = Vec::new();
+        for image in archive.images() {
+            if let Some(found) = image.descriptor.platform() {
+                if *found.architecture() != platform.arch || *found.os() != platform.os {
+                    continue;
+                }
+            }
+            let reference = match image.reference {
+                Some(ref reference) => Some(ImageName::parse(reference)?),
+                None => None,
+            };
+            let digest = image.descriptor.digest();
+            match images.iter_mut().find(|(x, _)| x == digest) {
+                Some((_, references)) => references.extend(reference),
+                None => images.push((digest.clone(), reference.into_iter().collect())),
+            }
+        }
+
+        if images.is_empty() {
+            return Err(anyhow!(
+                "image archive has no images for {}/{}",
+                platform.os,
+                platform.arch
+            ));
+        }
+
+        if let Some(tag) = tag {
+            if images.len() != 1 {
+                return Err(anyhow!(
+                    "image archive has {} images, a tag can only name a single image",
+                    images.len()
+                ));
+            }
+            images[0].1.push(tag.clone());
+        }
+
+        let mut infos = Vec::new();
+        for (digest, references) in images {
+            let Some(reference) = references.first() else {
+                return Err(anyhow!(
+                    "image {} in archive has no reference, a tag is required",
+                    digest
+                ));
+            };
+            let mut pinned = reference.clone();
+            pinned.digest = Some(digest);
+            let info = self
+                .compile_with_seed(&pinned, Some(archive.clone()))
+                .await?;
+            for reference in &references {
+                self.cache
+                    .tag_imported(&info.digest, &reference.to_string())
+                    .await?;
+            }
+            infos.push(info);
+        }
+        Ok(infos)
+    }
+
+    async fn compile_with_seed(
+        &self,
+        image: &ImageName,
+        seed: Option<OciImageArchive>,
+    ) -> Result<ImageInfo> {
         debug!("compile image={image}");
         let mut tmp_dir = std::env::temp_dir().clone();
         tmp_dir.push(format!("krata-compile-{}", Uuid::new_v4()));
@@ -61,44 +158,101 @@ impl ImageCompiler<'_> {
         image_dir.push("image");
         fs::create_dir_all(&image_dir).await?;
 
-        let mut layer_dir = tmp_dir.clone();
-        layer_dir.push("layer");
-        fs::create_dir_all(&layer_dir).await?;
-
         let mut squash_file = tmp_dir.clone();
         squash_file.push("image.squashfs");
         let info = self
-            .download_and_compile(image, &layer_dir, &image_dir, &squash_file)
+            .download_and_compile(image, seed, &image_dir, &squash_file)
             .await?;
         fs::remove_dir_all(&tmp_dir).await?;
         Ok(info)
     }
 
+    /// Finds an imported image by reference, so imported images launch without a
+    /// registry. Pulled images are never used this way, as that would bypass the
+    /// registry authorization of the caller.
+    async fn recall_imported(&self, image: &ImageName) -> Result<Option<ImageInfo>> {
+        let reference = image.to_string();
+        let Some(entry) = self.cache.list().await?.into_iter().find(|entry| {
+            entry.imported
+                && !entry.digest.is_empty()
+                && match image.digest {
+                    Some(ref digest) => entry.digest == *digest,
+                    None => entry.references.contains(&reference),
+                }
+        }) else {
+            return Ok(None);
+        };
+        let cached = self.cache.recall(&entry.id, &entry.digest).await?;
+        if cached.is_some() {
+            self.cache.touch(&entry.id, &reference).await?;
+        }
+        Ok(cached)
+    }
+
     async fn download_and_compile(
         &self,
         image: &ImageName,
-        layer_dir: &Path,
+        seed: Option<OciImageArchive>,
         image_dir: &Path,
         squash_file: &Path,
     ) -> Result<ImageInfo> {
+        let blobs = self.cache.blobs();
+        let _pin = blobs.pin().await;
         let downloader = OciImageDownloader::new(
-            self.seed.clone(),
-            layer_dir.to_path_buf(),
+            seed,
+            blobs.clone(),
             OciRegistryPlatform::current(),
+            self.credentials.clone(),
+            self.progress.clone(),
         );
-        let resolved = downloader.resolve(image.clone()).await?;
+        let resolved = match downloader.resolve(image.clone()).await {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                if !is_registry_unreachable(&error) {
+                    return Err(error);
+                }
+                let Some(cached) = self.recall_imported(image).await? else {
+                    return Err(error);
+                };
+                warn!(
+                    "unable to resolve image {}, using cached image {}: {}",
+                    image, cached.digest, error
+                );
+                self.progress.update(|progress| {
+                    progress.phase = OciProgressPhase::Complete;
+                });
+                return Ok(cached);
+            }
+        };
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::Resolved;
+        });
         let cache_key = format!(
             "manifest={}:squashfs-version={}\n",
             resolved.digest, IMAGE_SQUASHFS_VERSION
         );
         let cache_digest = sha256::digest(cache_key);
 
-        if let Some(cached) = self.cache.recall(&cache_digest).await? {
+        if let Some(cached) = self.cache.recall(&cache_digest, &resolved.digest).await? {
+            self.cache.touch(&cache_digest, &image.to_string()).await?;
+            self.progress.update(|progress| {
+                progress.phase = OciProgressPhase::Complete;
+            });
             return Ok(cached);
         }
 
         let local = downloader.download(resolved).await?;
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::Packing;
+            progress.value = 0;
+            progress.total = local.layers.len() as u64;
+        });
         for layer in &local.layers {
+            self.progress.update(|progress| {
+                if let Some(entry) = progress.layer(&layer.digest) {
+                    entry.phase = OciProgressLayerPhase::Extracting;
+                }
+            });
             debug!(
                 "process layer digest={} compression={:?}",
                 &layer.digest, layer.compression,
@@ -136,21 +290,27 @@ impl ImageCompiler<'_> {
                         .await?;
                 }
             }
-        }
-
-        for layer in &local.layers {
-            if layer.path.exists() {
-                fs::remove_file(&layer.path).await?;
-            }
+            self.progress.update(|progress| {
+                progress.value += 1;
+                if let Some(entry) = progress.layer(&layer.digest) {
+                    entry.phase = OciProgressLayerPhase::Extracted;
+                }
+            });
         }
 
         self.squash(image_dir, squash_file)?;
         let info = ImageInfo::new(
             squash_file.to_path_buf(),
+            local.image.digest,
             local.image.manifest,
             local.config,
         )?;
-        self.cache.store(&cache_digest, &info).await
+        let info = self.cache.store(&cache_digest, &info).await?;
+        self.cache.touch(&cache_digest, &image.to_string()).await?;
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::Complete;
+        });
+        Ok(info)
     }
 
     async fn process_layer_whiteout(
@@ -409,3 +569,14 @@ impl Drop for ConsumingFileReader {
         }
     }
 }
+
+/// Whether resolving failed because the registry could not be reached at all, as
+/// opposed to refusing the request or serving content that failed verification.
+fn is_registry_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .map(|x| x.is_connect() || x.is_timeout())
+            .unwrap_or(false)
+    })
+}
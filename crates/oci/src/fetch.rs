@@ -1,31 +1,34 @@
 use super::{
-    name::ImageName,
+    archive::OciImageArchive,
+    auth::{OciCredentialStore, OciRegistryAuth},
+    blobs::OciBlobStore,
+    name::{ImageName, DOCKER_HUB_MIRROR, DOCKER_HUB_REGISTRY},
+    progress::{OciProgressContext, OciProgressLayerPhase, OciProgressPhase},
     registry::{OciRegistryClient, OciRegistryPlatform},
 };
 
-use std::{
-    path::{Path, PathBuf},
-    pin::Pin,
-};
+use std::{path::PathBuf, pin::Pin};
 
 use anyhow::{anyhow, Result};
 use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use futures::{StreamExt, TryStreamExt};
 use log::debug;
-use oci_spec::image::{
-    Descriptor, ImageConfiguration, ImageIndex, ImageManifest, MediaType, ToDockerV2S2,
-};
-use serde::de::DeserializeOwned;
+use oci_spec::image::{Descriptor, ImageConfiguration, ImageManifest, MediaType, ToDockerV2S2};
 use tokio::{
     fs::File,
-    io::{AsyncRead, AsyncReadExt, BufReader, BufWriter},
+    io::{AsyncRead, BufReader},
 };
-use tokio_stream::StreamExt;
 use tokio_tar::Archive;
+use url::Url;
+
+const OCI_LAYER_DOWNLOAD_PARALLELISM: usize = 3;
 
 pub struct OciImageDownloader {
-    seed: Option<PathBuf>,
-    storage: PathBuf,
+    seed: Option<OciImageArchive>,
+    blobs: OciBlobStore,
     platform: OciRegistryPlatform,
+    credentials: OciCredentialStore,
+    progress: OciProgressContext,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -76,130 +79,48 @@ pub struct OciLocalImage {
 
 impl OciImageDownloader {
     pub fn new(
-        seed: Option<PathBuf>,
-        storage: PathBuf,
+        seed: Option<OciImageArchive>,
+        blobs: OciBlobStore,
         platform: OciRegistryPlatform,
+        credentials: OciCredentialStore,
+        progress: OciProgressContext,
     ) -> OciImageDownloader {
         OciImageDownloader {
             seed,
-            storage,
+            blobs,
             platform,
+            credentials,
+            progress,
         }
     }
 
-    async fn load_seed_json_blob<T: DeserializeOwned>(
-        &self,
-        descriptor: &Descriptor,
-    ) -> Result<Option<T>> {
-        let digest = descriptor.digest();
-        let Some((digest_type, digest_content)) = digest.split_once(':') else {
-            return Err(anyhow!("digest content was not properly formatted"));
-        };
-        let want = format!("blobs/{}/{}", digest_type, digest_content);
-        self.load_seed_json(&want).await
-    }
-
-    async fn load_seed_json<T: DeserializeOwned>(&self, want: &str) -> Result<Option<T>> {
-        let Some(ref seed) = self.seed else {
-            return Ok(None);
-        };
-
-        let file = File::open(seed).await?;
-        let mut archive = Archive::new(file);
-        let mut entries = archive.entries()?;
-        while let Some(entry) = entries.next().await {
-            let mut entry = entry?;
-            let path = String::from_utf8(entry.path_bytes().to_vec())?;
-            if path == want {
-                let mut content = String::new();
-                entry.read_to_string(&mut content).await?;
-                let data = serde_json::from_str::<T>(&content)?;
-                return Ok(Some(data));
-            }
-        }
-        Ok(None)
-    }
-
-    async fn extract_seed_blob(&self, descriptor: &Descriptor, to: &Path) -> Result<bool> {
-        let Some(ref seed) = self.seed else {
-            return Ok(false);
-        };
-
-        let digest = descriptor.digest();
-        let Some((digest_type, digest_content)) = digest.split_once(':') else {
-            return Err(anyhow!("digest content was not properly formatted"));
-        };
-        let want = format!("blobs/{}/{}", digest_type, digest_content);
-
-        let seed = File::open(seed).await?;
-        let mut archive = Archive::new(seed);
-        let mut entries = archive.entries()?;
-        while let Some(entry) = entries.next().await {
-            let mut entry = entry?;
-            let path = String::from_utf8(entry.path_bytes().to_vec())?;
-            if path == want {
-                let file = File::create(to).await?;
-                let mut bufwrite = BufWriter::new(file);
-                tokio::io::copy(&mut entry, &mut bufwrite).await?;
-                return Ok(true);
+    async fn client(&self, image: &ImageName) -> Result<OciRegistryClient> {
+        if image.is_docker_hub() {
+            let auth = self.credentials.lookup(DOCKER_HUB_REGISTRY).await?;
+            if auth != OciRegistryAuth::Anonymous || image.hostname != DOCKER_HUB_MIRROR {
+                let url = Url::parse(&format!("https://{}", DOCKER_HUB_REGISTRY))?;
+                return OciRegistryClient::new(url, self.platform.clone(), auth);
             }
         }
-        Ok(false)
+        let auth = self.credentials.lookup(&image.registry_host()).await?;
+        OciRegistryClient::new(image.registry_url()?, self.platform.clone(), auth)
     }
 
     pub async fn resolve(&self, image: ImageName) -> Result<OciResolvedImage> {
         debug!("resolve manifest image={}", image);
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::Resolving;
+        });
 
-        if let Some(index) = self.load_seed_json::<ImageIndex>("index.json").await? {
-            let mut found: Option<&Descriptor> = None;
-            for manifest in index.manifests() {
-                let Some(annotations) = manifest.annotations() else {
-                    continue;
-                };
-
-                let mut image_name = annotations.get("io.containerd.image.name");
-                if image_name.is_none() {
-                    image_name = annotations.get("org.opencontainers.image.ref.name");
-                }
-
-                let Some(image_name) = image_name else {
-                    continue;
-                };
-
-                if *image_name != image.to_string() {
-                    continue;
-                }
-
-                if let Some(platform) = manifest.platform() {
-                    if *platform.architecture() != self.platform.arch
-                        || *platform.os() != self.platform.os
-                    {
-                        continue;
-                    }
-                }
-                found = Some(manifest);
-                break;
-            }
-
-            if let Some(found) = found {
-                if let Some(manifest) = self.load_seed_json_blob(found).await? {
-                    debug!(
-                        "found seeded manifest image={} manifest={}",
-                        image,
-                        found.digest()
-                    );
-                    return Ok(OciResolvedImage {
-                        name: image,
-                        digest: found.digest().clone(),
-                        manifest,
-                    });
-                }
+        if let Some(ref seed) = self.seed {
+            if let Some(resolved) = seed.resolve(&image, &self.platform).await? {
+                return Ok(resolved);
             }
         }
 
-        let mut client = OciRegistryClient::new(image.registry_url()?, self.platform.clone())?;
+        let mut client = self.client(&image).await?;
         let (manifest, digest) = client
-            .get_manifest_with_digest(&image.name, &image.reference)
+            .get_manifest_with_digest(&image.name, image.manifest_reference())
             .await?;
         Ok(OciResolvedImage {
             name: image,
@@ -211,11 +132,28 @@ impl OciImageDownloader {
     pub async fn download(&self, image: OciResolvedImage) -> Result<OciLocalImage> {
         let config: ImageConfiguration;
 
-        let mut client = OciRegistryClient::new(image.name.registry_url()?, self.platform.clone())?;
-        if let Some(seeded) = self
-            .load_seed_json_blob::<ImageConfiguration>(image.manifest.config())
-            .await?
-        {
+        let mut client = self.client(&image.name).await?;
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::ConfigAcquire;
+            for layer in image.manifest.layers() {
+                progress.add_layer(layer.digest(), layer.size() as u64);
+            }
+            progress.value = 0;
+            progress.total = image
+                .manifest
+                .layers()
+                .iter()
+                .map(|x| x.size() as u64)
+                .sum();
+        });
+        let seeded = match self.seed {
+            Some(ref seed) => {
+                seed.load_json_blob::<ImageConfiguration>(image.manifest.config())
+                    .await?
+            }
+            None => None,
+        };
+        if let Some(seeded) = seeded {
             config = seeded;
         } else {
             let config_bytes = client
@@ -223,10 +161,19 @@ impl OciImageDownloader {
                 .await?;
             config = serde_json::from_slice(&config_bytes)?;
         }
-        let mut layers = Vec::new();
+        self.progress.update(|progress| {
+            progress.phase = OciProgressPhase::LayerAcquire;
+        });
+        let name = &image.name;
+        let mut acquires = Vec::new();
         for layer in image.manifest.layers() {
-            layers.push(self.acquire_layer(&image.name, layer, &mut client).await?);
+            let mut client = client.clone();
+            acquires.push(async move { self.acquire_layer(name, layer, &mut client).await });
         }
+        let layers = futures::stream::iter(acquires)
+            .buffered(OCI_LAYER_DOWNLOAD_PARALLELISM)
+            .try_collect::<Vec<_>>()
+            .await?;
         Ok(OciLocalImage {
             image,
             config,
@@ -245,19 +192,25 @@ impl OciImageDownloader {
             layer.digest(),
             layer.size()
         );
-        let mut layer_path = self.storage.clone();
-        layer_path.push(format!("{}.layer", layer.digest()));
-
-        let seeded = self.extract_seed_blob(layer, &layer_path).await?;
-        if !seeded {
-            let file = File::create(&layer_path).await?;
-            let size = client.write_blob_to_file(&image.name, layer, file).await?;
-            if layer.size() as u64 != size {
-                return Err(anyhow!(
-                    "downloaded layer size differs from size in manifest",
-                ));
+        let digest = layer.digest();
+        let size = layer.size() as u64;
+        let _lock = self.blobs.lock(digest).await;
+        let cached = self.blobs.contains(digest)?;
+        let layer_path = if cached {
+            debug!("layer digest={} found in blob store", digest);
+            self.blobs.path(digest)?
+        } else {
+            self.fetch_layer(image, layer, client).await?
+        };
+        self.progress.update(|progress| {
+            if cached {
+                progress.value += size;
             }
-        }
+            if let Some(entry) = progress.layer(digest) {
+                entry.phase = OciProgressLayerPhase::Downloaded;
+                entry.value = entry.total;
+            }
+        });
 
         let mut media_type = layer.media_type().clone();
 
@@ -278,4 +231,59 @@ impl OciImageDownloader {
             compression,
         })
     }
+
+    async fn fetch_layer(
+        &self,
+        image: &ImageName,
+        layer: &Descriptor,
+        client: &mut OciRegistryClient,
+    ) -> Result<PathBuf> {
+        let digest = layer.digest();
+        let size = layer.size() as u64;
+        if let Some(ref seed) = self.seed {
+            let partial = self.blobs.prepare(digest).await?;
+            if seed.extract_blob(layer, &partial).await? {
+                self.progress.update(|progress| {
+                    progress.value += size;
+                });
+                return self.blobs.commit(digest).await;
+            }
+        }
+
+        let (mut file, mut offset) = self.blobs.resume(digest).await?;
+        if offset >= size {
+            // a complete partial download was left behind, keep it if it verifies
+            drop(file);
+            match self.blobs.commit(digest).await {
+                Ok(path) => {
+                    self.progress.update(|progress| {
+                        progress.value += size;
+                    });
+                    return Ok(path);
+                }
+                Err(error) => debug!("discarded partial layer digest={}: {}", digest, error),
+            }
+            (file, offset) = self.blobs.resume(digest).await?;
+        }
+
+        let mut reported = 0u64;
+        let written = client
+            .write_blob_to_file(&image.name, layer, file, offset, |written| {
+                self.progress.update(|progress| {
+                    progress.value = progress.value.saturating_sub(reported) + written;
+                    if let Some(entry) = progress.layer(digest) {
+                        entry.phase = OciProgressLayerPhase::Downloading;
+                        entry.value = written;
+                    }
+                });
+                reported = written;
+            })
+            .await?;
+        if written != size {
+            return Err(anyhow!(
+                "downloaded layer size differs from size in manifest",
+            ));
+        }
+        self.blobs.commit(digest).await
+    }
 }
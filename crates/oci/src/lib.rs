@@ -1,5 +1,9 @@
+pub mod archive;
+pub mod auth;
+pub mod blobs;
 pub mod cache;
 pub mod compiler;
 pub mod fetch;
 pub mod name;
+pub mod progress;
 pub mod registry;
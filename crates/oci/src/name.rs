@@ -1,8 +1,15 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::fmt;
 use url::Url;
 
-const DOCKER_HUB_MIRROR: &str = "mirror.gcr.io";
+pub const DOCKER_HUB_MIRROR: &str = "mirror.gcr.io";
+pub const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+const DOCKER_HUB_HOSTNAMES: &[&str] = &[
+    DOCKER_HUB_MIRROR,
+    DOCKER_HUB_REGISTRY,
+    "docker.io",
+    "index.docker.io",
+];
 const DEFAULT_IMAGE_TAG: &str = "latest";
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -11,6 +18,7 @@ pub struct ImageName {
     pub port: Option<u16>,
     pub name: String,
     pub reference: String,
+    pub digest: Option<String>,
 }
 
 impl fmt::Display for ImageName {
@@ -20,10 +28,14 @@ impl fmt::Display for ImageName {
                 f,
                 "{}:{}/{}:{}",
                 self.hostname, port, self.name, self.reference
-            )
+            )?;
         } else {
-            write!(f, "{}/{}:{}", self.hostname, self.name, self.reference)
+            write!(f, "{}/{}:{}", self.hostname, self.name, self.reference)?;
         }
+        if let Some(ref digest) = self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
     }
 }
 
@@ -36,6 +48,10 @@ impl Default for ImageName {
 
 impl ImageName {
     pub fn parse(name: &str) -> Result<Self> {
+        let (name, digest) = match name.split_once('@') {
+            Some((name, digest)) => (name, Some(ImageName::parse_digest(digest)?)),
+            None => (name, None),
+        };
         let full_name = name.to_string();
         let name = full_name.clone();
         let (mut hostname, mut name) = name
@@ -69,15 +85,56 @@ impl ImageName {
             port,
             name,
             reference,
+            digest,
         })
     }
 
-    pub fn registry_url(&self) -> Result<Url> {
-        let hostname = if let Some(port) = self.port {
+    fn parse_digest(digest: &str) -> Result<String> {
+        let Some(("sha256", hex)) = digest.split_once(':') else {
+            return Err(anyhow!(
+                "unsupported image digest {}, expected sha256",
+                digest
+            ));
+        };
+        if hex.len() != 64 || !hex.chars().all(|x| matches!(x, '0'..='9' | 'a'..='f')) {
+            return Err(anyhow!(
+                "image digest {} is not a valid sha256 digest",
+                digest
+            ));
+        }
+        Ok(digest.to_string())
+    }
+
+    /// The reference to fetch the manifest by, the digest when the image is pinned.
+    pub fn manifest_reference(&self) -> &str {
+        self.digest.as_deref().unwrap_or(&self.reference)
+    }
+
+    /// Docker Hub images are pulled from a mirror unless there are credentials for Docker Hub.
+    pub fn is_docker_hub(&self) -> bool {
+        self.port.is_none() && DOCKER_HUB_HOSTNAMES.contains(&self.hostname.as_str())
+    }
+
+    /// The registry that credentials for this image belong to. Docker Hub credentials
+    /// are kept under Docker Hub itself so they are never presented to the mirror.
+    pub fn credential_host(&self) -> String {
+        if self.is_docker_hub() {
+            DOCKER_HUB_REGISTRY.to_string()
+        } else {
+            self.registry_host()
+        }
+    }
+
+    pub fn registry_host(&self) -> String {
+        if let Some(port) = self.port {
             format!("{}:{}", self.hostname, port)
         } else {
             self.hostname.clone()
-        };
+        }
+    }
+
+    pub fn registry_url(&self) -> Result<Url> {
+        let hostname = self.registry_host();
         let url = if self.hostname.starts_with("localhost") {
             format!("http://{}", hostname)
         } else {
@@ -86,3 +143,61 @@ impl ImageName {
         Ok(Url::parse(&url)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:2b8a3b8b4a1e3a0c7b5f8f1a4c2d6e9b0a1c3e5f7d9b2a4c6e8f0a1b3c5d7e9f";
+
+    #[test]
+    fn parse_pins_the_image_to_a_digest() {
+        let name = ImageName::parse(&format!("alpine@{}", DIGEST)).unwrap();
+        assert_eq!(name.hostname, DOCKER_HUB_MIRROR);
+        assert_eq!(name.name, "library/alpine");
+        assert_eq!(name.reference, DEFAULT_IMAGE_TAG);
+        assert_eq!(name.digest.as_deref(), Some(DIGEST));
+        assert_eq!(name.manifest_reference(), DIGEST);
+    }
+
+    #[test]
+    fn parse_keeps_the_tag_alongside_a_digest() {
+        let name = ImageName::parse(&format!("ghcr.io:443/edera/app:1.2@{}", DIGEST)).unwrap();
+        assert_eq!(name.hostname, "ghcr.io");
+        assert_eq!(name.port, Some(443));
+        assert_eq!(name.name, "edera/app");
+        assert_eq!(name.reference, "1.2");
+        assert_eq!(name.manifest_reference(), DIGEST);
+        assert_eq!(
+            name.to_string(),
+            format!("ghcr.io:443/edera/app:1.2@{}", DIGEST)
+        );
+    }
+
+    #[test]
+    fn parse_without_a_digest_uses_the_tag() {
+        let name = ImageName::parse("edera/app:1.2").unwrap();
+        assert_eq!(name.hostname, DOCKER_HUB_MIRROR);
+        assert_eq!(name.name, "edera/app");
+        assert_eq!(name.digest, None);
+        assert_eq!(name.manifest_reference(), "1.2");
+    }
+
+    #[test]
+    fn parse_digest_rejects_invalid_digests() {
+        let hex = &DIGEST["sha256:".len()..];
+        for digest in [
+            format!("sha512:{}", hex),
+            hex.to_string(),
+            format!("sha256:{}", &hex[1..]),
+            format!("sha256:{}0", hex),
+            format!("sha256:{}", hex.to_uppercase()),
+            format!("sha256:{}g", &hex[1..]),
+            "sha256:".to_string(),
+        ] {
+            assert!(ImageName::parse_digest(&digest).is_err(), "{}", digest);
+            assert!(ImageName::parse(&format!("alpine@{}", digest)).is_err());
+        }
+        assert_eq!(ImageName::parse_digest(DIGEST).unwrap(), DIGEST);
+    }
+}
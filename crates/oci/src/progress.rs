@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OciProgressPhase {
+    #[default]
+    Resolving,
+    Resolved,
+    ConfigAcquire,
+    LayerAcquire,
+    Packing,
+    Complete,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OciProgressLayerPhase {
+    #[default]
+    Waiting,
+    Downloading,
+    Downloaded,
+    Extracting,
+    Extracted,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OciProgressLayer {
+    pub id: String,
+    pub phase: OciProgressLayerPhase,
+    pub value: u64,
+    pub total: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OciProgress {
+    pub phase: OciProgressPhase,
+    pub layers: Vec<OciProgressLayer>,
+    pub value: u64,
+    pub total: u64,
+}
+
+impl OciProgress {
+    pub fn add_layer(&mut self, id: &str, total: u64) {
+        self.layers.push(OciProgressLayer {
+            id: id.to_string(),
+            phase: OciProgressLayerPhase::Waiting,
+            value: 0,
+            total,
+        });
+    }
+
+    pub fn layer(&mut self, id: &str) -> Option<&mut OciProgressLayer> {
+        self.layers.iter_mut().find(|layer| layer.id == id)
+    }
+}
+
+/// Publishes the progress of an image pull. Watchers only observe the latest state,
+/// so frequent updates never block the pull.
+#[derive(Clone)]
+pub struct OciProgressContext {
+    state: Arc<Mutex<OciProgress>>,
+    sender: Arc<watch::Sender<OciProgress>>,
+}
+
+impl OciProgressContext {
+    pub fn create() -> (OciProgressContext, watch::Receiver<OciProgress>) {
+        let (sender, receiver) = watch::channel(OciProgress::default());
+        let context = OciProgressContext {
+            state: Arc::new(Mutex::new(OciProgress::default())),
+            sender: Arc::new(sender),
+        };
+        (context, receiver)
+    }
+
+    pub fn update<F: FnOnce(&mut OciProgress)>(&self, f: F) {
+        let mut state = self.state.lock().unwrap_or_else(|x| x.into_inner());
+        f(&mut state);
+        self.sender.send_replace(state.clone());
+    }
+}
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, io::SeekFrom};
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use oci_spec::image::{Arch, Descriptor, ImageIndex, ImageManifest, MediaType, Os, ToDockerV2S2};
 use reqwest::{Client, RequestBuilder, Response, StatusCode};
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
 use url::Url;
 
+use crate::{auth::OciRegistryAuth, blobs::verify_digest};
+
 #[derive(Clone, Debug)]
 pub struct OciRegistryPlatform {
     pub os: Os,
@@ -31,94 +36,168 @@ impl OciRegistryPlatform {
     }
 }
 
+#[derive(Clone)]
 pub struct OciRegistryClient {
     agent: Client,
     url: Url,
     platform: OciRegistryPlatform,
+    auth: OciRegistryAuth,
     token: Option<String>,
+    basic: bool,
 }
 
 impl OciRegistryClient {
-    pub fn new(url: Url, platform: OciRegistryPlatform) -> Result<OciRegistryClient> {
+    pub fn new(
+        url: Url,
+        platform: OciRegistryPlatform,
+        auth: OciRegistryAuth,
+    ) -> Result<OciRegistryClient> {
+        let token = match auth {
+            OciRegistryAuth::RegistryToken(ref token) => Some(token.clone()),
+            _ => None,
+        };
         Ok(OciRegistryClient {
             agent: Client::new(),
             url,
             platform,
-            token: None,
+            auth,
+            token,
+            basic: false,
         })
     }
 
-    async fn call(&mut self, mut req: RequestBuilder) -> Result<Response> {
+    fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
         if let Some(ref token) = self.token {
-            req = req.bearer_auth(token);
+            return req.bearer_auth(token);
+        }
+        match self.auth {
+            OciRegistryAuth::Basic {
+                ref username,
+                ref password,
+            } if self.basic => req.basic_auth(username, Some(password)),
+            _ => req,
         }
-        let req_first_try = req.try_clone().ok_or(anyhow!("request is not clonable"))?;
-        let response = self.agent.execute(req_first_try.build()?).await?;
-        if response.status() == StatusCode::UNAUTHORIZED && self.token.is_none() {
+    }
+
+    async fn call(&mut self, req: RequestBuilder) -> Result<Response> {
+        let req_first_try = self
+            .authorize(req.try_clone().ok_or(anyhow!("request is not clonable"))?)
+            .build()?;
+        let response = self.agent.execute(req_first_try).await?;
+        if response.status() == StatusCode::UNAUTHORIZED && self.token.is_none() && !self.basic {
             let Some(www_authenticate) = response.headers().get("www-authenticate") else {
                 return Err(anyhow!("not authorized to perform this action"));
             };
-
             let www_authenticate = www_authenticate.to_str()?;
-            if !www_authenticate.starts_with("Bearer ") {
+            let Some((scheme, details)) = www_authenticate.split_once(' ') else {
                 return Err(anyhow!("unknown authentication scheme"));
-            }
+            };
 
-            let details = &www_authenticate[7..];
-            let details = details
-                .split(',')
-                .map(|x| x.split('='))
-                .map(|mut x| (x.next(), x.next()))
-                .filter(|(key, value)| key.is_some() && value.is_some())
-                .map(|(key, value)| {
-                    (
-                        key.unwrap().trim().to_lowercase(),
-                        value.unwrap().trim().to_string(),
-                    )
-                })
-                .map(|(key, value)| (key, value.trim_matches('\"').to_string()))
-                .collect::<HashMap<_, _>>();
-            let realm = details.get("realm");
-            let service = details.get("service");
-            let scope = details.get("scope");
-            if realm.is_none() || service.is_none() || scope.is_none() {
-                return Err(anyhow!(
-                    "unknown authentication scheme: realm, service, and scope are required"
-                ));
-            }
-            let mut url = Url::parse(realm.unwrap())?;
-            url.query_pairs_mut()
-                .append_pair("service", service.unwrap())
-                .append_pair("scope", scope.unwrap());
-            let token_response = self.agent.get(url.clone()).send().await?;
-            if token_response.status() != StatusCode::OK {
-                return Err(anyhow!(
-                    "failed to acquire token via {}: status {}",
-                    url,
-                    token_response.status()
-                ));
+            if scheme.eq_ignore_ascii_case("basic") {
+                if !matches!(self.auth, OciRegistryAuth::Basic { .. }) {
+                    return Err(anyhow!(
+                        "registry requires basic authentication but no username and password were provided"
+                    ));
+                }
+                self.basic = true;
+            } else if scheme.eq_ignore_ascii_case("bearer") {
+                let token = self.acquire_token(details).await?;
+                self.token = Some(token);
+            } else {
+                return Err(anyhow!("unknown authentication scheme: {}", scheme));
             }
-            let token_bytes = token_response.bytes().await?;
-            let token = serde_json::from_slice::<serde_json::Value>(&token_bytes)?;
-            let token = token
-                .get("token")
-                .and_then(|x| x.as_str())
-                .ok_or(anyhow!("token key missing from response"))?;
-            self.token = Some(token.to_string());
-            return Ok(self.agent.execute(req.bearer_auth(token).build()?).await?);
+            return self.check(self.agent.execute(self.authorize(req).build()?).await?);
         }
+        self.check(response)
+    }
 
+    fn check(&self, response: Response) -> Result<Response> {
         if !response.status().is_success() {
             return Err(anyhow!(
                 "request to {} failed: status {}",
-                req.build()?.url(),
+                response.url(),
                 response.status()
             ));
         }
-
         Ok(response)
     }
 
+    /// Performs the token exchange described by a bearer challenge, authenticating
+    /// with the configured credentials when there are any.
+    async fn acquire_token(&self, challenge: &str) -> Result<String> {
+        let details = challenge
+            .split(',')
+            .filter_map(|x| x.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.trim().to_lowercase(),
+                    value.trim().trim_matches('\"').to_string(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let Some(realm) = details.get("realm") else {
+            return Err(anyhow!("unknown authentication scheme: realm is required"));
+        };
+        let service = details.get("service");
+        let scope = details.get("scope");
+
+        let url = Url::parse(realm)?;
+        let token_request = match self.auth {
+            OciRegistryAuth::IdentityToken(ref refresh_token) => {
+                let mut form = vec![
+                    ("grant_type", "refresh_token"),
+                    ("client_id", "krata"),
+                    ("refresh_token", refresh_token.as_str()),
+                ];
+                if let Some(service) = service {
+                    form.push(("service", service));
+                }
+                if let Some(scope) = scope {
+                    form.push(("scope", scope));
+                }
+                self.agent.post(url.clone()).form(&form)
+            }
+
+            _ => {
+                let mut url = url.clone();
+                {
+                    let mut query = url.query_pairs_mut();
+                    if let Some(service) = service {
+                        query.append_pair("service", service);
+                    }
+                    if let Some(scope) = scope {
+                        query.append_pair("scope", scope);
+                    }
+                }
+                let request = self.agent.get(url);
+                match self.auth {
+                    OciRegistryAuth::Basic {
+                        ref username,
+                        ref password,
+                    } => request.basic_auth(username, Some(password)),
+                    _ => request,
+                }
+            }
+        };
+
+        let token_response = token_request.send().await?;
+        if token_response.status() != StatusCode::OK {
+            return Err(anyhow!(
+                "failed to acquire token via {}: status {}",
+                url,
+                token_response.status()
+            ));
+        }
+        let token_bytes = token_response.bytes().await?;
+        let token = serde_json::from_slice::<serde_json::Value>(&token_bytes)?;
+        let token = token
+            .get("token")
+            .or_else(|| token.get("access_token"))
+            .and_then(|x| x.as_str())
+            .ok_or(anyhow!("token key missing from response"))?;
+        Ok(token.to_string())
+    }
+
     pub async fn get_blob<N: AsRef<str>>(
         &mut self,
         name: N,
@@ -130,26 +209,44 @@ impl OciRegistryClient {
             descriptor.digest()
         ))?;
         let response = self.call(self.agent.get(url.as_str())).await?;
-        Ok(response.bytes().await?)
+        let content = response.bytes().await?;
+        verify_digest(descriptor.digest(), &content)?;
+        Ok(content)
     }
 
-    pub async fn write_blob_to_file<N: AsRef<str>>(
+    /// Writes a blob to `dest`, resuming after the first `offset` bytes already in it
+    /// when the registry honors the range request. Returns the total size of the blob.
+    pub async fn write_blob_to_file<N: AsRef<str>, P: FnMut(u64)>(
         &mut self,
         name: N,
         descriptor: &Descriptor,
         mut dest: File,
+        offset: u64,
+        mut progress: P,
     ) -> Result<u64> {
         let url = self.url.join(&format!(
             "/v2/{}/blobs/{}",
             name.as_ref(),
             descriptor.digest()
         ))?;
-        let mut response = self.call(self.agent.get(url.as_str())).await?;
-        let mut size: u64 = 0;
+        let mut request = self.agent.get(url.as_str());
+        if offset > 0 {
+            request = request.header("Range", format!("bytes={}-", offset));
+        }
+        let mut response = self.call(request).await?;
+        let mut size: u64 = offset;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            dest.set_len(0).await?;
+            dest.seek(SeekFrom::Start(0)).await?;
+            size = 0;
+        }
+        progress(size);
         while let Some(chunk) = response.chunk().await? {
             dest.write_all(&chunk).await?;
             size += chunk.len() as u64;
+            progress(size);
         }
+        dest.flush().await?;
         Ok(size)
     }
 
@@ -158,28 +255,8 @@ impl OciRegistryClient {
         name: N,
         reference: R,
     ) -> Result<(ImageManifest, String)> {
-        let url = self.url.join(&format!(
-            "/v2/{}/manifests/{}",
-            name.as_ref(),
-            reference.as_ref()
-        ))?;
-        let accept = format!(
-            "{}, {}, {}, {}",
-            MediaType::ImageManifest.to_docker_v2s2()?,
-            MediaType::ImageManifest,
-            MediaType::ImageIndex,
-            MediaType::ImageIndex.to_docker_v2s2()?,
-        );
-        let response = self
-            .call(self.agent.get(url.as_str()).header("Accept", &accept))
-            .await?;
-        let digest = response
-            .headers()
-            .get("Docker-Content-Digest")
-            .ok_or_else(|| anyhow!("fetching manifest did not yield a content digest"))?
-            .to_str()?
-            .to_string();
-        let manifest = serde_json::from_str(&response.text().await?)?;
+        let (_, content, digest) = self.fetch_manifest(name, reference).await?;
+        let manifest = serde_json::from_slice(&content)?;
         Ok((manifest, digest))
     }
 
@@ -188,6 +265,29 @@ impl OciRegistryClient {
         name: N,
         reference: R,
     ) -> Result<(ImageManifest, String)> {
+        let (content_type, content, digest) = self.fetch_manifest(&name, reference).await?;
+        if content_type == MediaType::ImageIndex.to_string()
+            || content_type == MediaType::ImageIndex.to_docker_v2s2()?
+        {
+            let index = serde_json::from_slice(&content)?;
+            let descriptor = self
+                .pick_manifest(index)
+                .ok_or_else(|| anyhow!("unable to pick manifest from index"))?;
+            return self
+                .get_raw_manifest_with_digest(name, descriptor.digest())
+                .await;
+        }
+        let manifest = serde_json::from_slice(&content)?;
+        Ok((manifest, digest))
+    }
+
+    /// Fetches a manifest or index, returning its content type, content and digest.
+    /// When fetched by digest the content is verified to match it.
+    async fn fetch_manifest<N: AsRef<str>, R: AsRef<str>>(
+        &mut self,
+        name: N,
+        reference: R,
+    ) -> Result<(String, Bytes, String)> {
         let url = self.url.join(&format!(
             "/v2/{}/manifests/{}",
             name.as_ref(),
@@ -207,26 +307,19 @@ impl OciRegistryClient {
             .headers()
             .get("Content-Type")
             .ok_or_else(|| anyhow!("registry response did not have a Content-Type header"))?
-            .to_str()?;
-        if content_type == MediaType::ImageIndex.to_string()
-            || content_type == MediaType::ImageIndex.to_docker_v2s2()?
-        {
-            let index = serde_json::from_str(&response.text().await?)?;
-            let descriptor = self
-                .pick_manifest(index)
-                .ok_or_else(|| anyhow!("unable to pick manifest from index"))?;
-            return self
-                .get_raw_manifest_with_digest(name, descriptor.digest())
-                .await;
-        }
-        let digest = response
-            .headers()
-            .get("Docker-Content-Digest")
-            .ok_or_else(|| anyhow!("fetching manifest did not yield a content digest"))?
             .to_str()?
             .to_string();
-        let manifest = serde_json::from_str(&response.text().await?)?;
-        Ok((manifest, digest))
+        let content = response.bytes().await?;
+        let digest = format!("sha256:{}", sha256::digest(content.as_ref()));
+        if reference.as_ref().contains(':') && reference.as_ref() != digest {
+            return Err(anyhow!(
+                "manifest digest mismatch for {}: expected {}, received {}",
+                name.as_ref(),
+                reference.as_ref(),
+                digest
+            ));
+        }
+        Ok((content_type, content, digest))
     }
 
     fn pick_manifest(&mut self, index: ImageIndex) -> Option<Descriptor> {
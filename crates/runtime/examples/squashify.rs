@@ -2,7 +2,10 @@ use std::{env::args, path::PathBuf};
 
 use anyhow::Result;
 use env_logger::Env;
-use krataoci::{cache::ImageCache, compiler::ImageCompiler, name::ImageName};
+use krataoci::{
+    auth::OciCredentialStore, cache::ImageCache, compiler::ImageCompiler, name::ImageName,
+    progress::OciProgressContext,
+};
 use tokio::fs;
 
 #[tokio::main]
@@ -18,7 +21,12 @@ async fn main() -> Result<()> {
     }
 
     let cache = ImageCache::new(&cache_dir)?;
-    let compiler = ImageCompiler::new(&cache, seed)?;
+    let compiler = ImageCompiler::new(
+        &cache,
+        seed,
+        OciCredentialStore::new(),
+        OciProgressContext::create().0,
+    )?;
     let info = compiler.compile(&image).await?;
     println!(
         "generated squashfs of {} to {}",
@@ -19,9 +19,17 @@ impl AutoLoop {
     }
 
     pub fn loopify(&self, file: &str) -> Result<BlockDeviceRef> {
-        debug!("creating loop for file {}", file);
+        self.loopify_with_mode(file, true)
+    }
+
+    pub fn loopify_writable(&self, file: &str) -> Result<BlockDeviceRef> {
+        self.loopify_with_mode(file, false)
+    }
+
+    fn loopify_with_mode(&self, file: &str, read_only: bool) -> Result<BlockDeviceRef> {
+        debug!("creating loop for file {} read_only={}", file, read_only);
         let device = self.control.next_free()?;
-        device.with().read_only(true).attach(file)?;
+        device.with().read_only(read_only).attach(file)?;
         let path = device
             .path()
             .ok_or(anyhow!("unable to get loop device path"))?
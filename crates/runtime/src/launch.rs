@@ -1,38 +1,83 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv6Addr};
 use std::sync::Arc;
-use std::{fs, net::Ipv4Addr, str::FromStr};
+use std::{fs, net::Ipv4Addr};
 
 use advmac::MacAddr6;
 use anyhow::{anyhow, Result};
-use ipnetwork::{IpNetwork, Ipv4Network};
+use ipnetwork::IpNetwork;
 use krata::launchcfg::{
-    LaunchInfo, LaunchNetwork, LaunchNetworkIpv4, LaunchNetworkIpv6, LaunchNetworkResolver,
+    LaunchHealthCheck, LaunchInfo, LaunchMount, LaunchNetwork, LaunchNetworkHost,
+    LaunchNetworkIpv4, LaunchNetworkIpv6, LaunchNetworkResolver, LaunchResources, LaunchVolume,
 };
 use tokio::sync::Semaphore;
 use uuid::Uuid;
-use xenclient::{DomainChannel, DomainConfig, DomainDisk, DomainNetworkInterface};
-use xenstore::XsdInterface;
+use xenclient::{
+    BlockDeviceRef, DomainChannel, DomainConfig, DomainDisk, DomainFilesystem,
+    DomainNetworkInterface,
+};
 
 use crate::cfgblk::ConfigBlock;
 use crate::RuntimeContext;
 use krataoci::{
+    auth::OciCredentialStore,
     cache::ImageCache,
     compiler::{ImageCompiler, ImageInfo},
     name::ImageName,
+    progress::OciProgressContext,
 };
 
 use super::{GuestInfo, GuestState};
 
+const MAX_VOLUMES: usize = 24;
+
+pub struct GuestLaunchVolume {
+    pub image: String,
+    pub path: String,
+    pub read_only: bool,
+}
+
+pub struct GuestLaunchMount {
+    pub host_path: String,
+    pub guest_path: String,
+    pub read_only: bool,
+}
+
+pub struct GuestLaunchNetwork {
+    pub ipv4: Ipv4Addr,
+    pub ipv4_prefix: u8,
+    pub ipv6: Ipv6Addr,
+    pub ipv6_prefix: u8,
+    pub gateway_ipv4: Ipv4Addr,
+    pub gateway_ipv6: Ipv6Addr,
+    pub nameservers: Vec<String>,
+    pub hosts: Vec<GuestLaunchNetworkHost>,
+}
+
+pub struct GuestLaunchNetworkHost {
+    pub name: String,
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+}
+
 pub struct GuestLaunchRequest<'a> {
     pub uuid: Option<Uuid>,
     pub name: Option<&'a str>,
     pub image: &'a str,
     pub vcpus: u32,
     pub mem: u64,
+    pub max_vcpus: u32,
+    pub max_mem: u64,
     pub env: HashMap<String, String>,
     pub run: Option<Vec<String>>,
     pub debug: bool,
+    pub volumes: Vec<GuestLaunchVolume>,
+    pub mounts: Vec<GuestLaunchMount>,
+    pub network: GuestLaunchNetwork,
+    pub tty: bool,
+    pub healthcheck: Option<LaunchHealthCheck>,
+    pub resources: LaunchResources,
+    pub credentials: OciCredentialStore,
 }
 
 pub struct GuestLauncher {
@@ -49,9 +94,18 @@ impl GuestLauncher {
         context: &RuntimeContext,
         request: GuestLaunchRequest<'r>,
     ) -> Result<GuestInfo> {
+        if request.volumes.len() > MAX_VOLUMES {
+            return Err(anyhow!(
+                "guests can have at most {} volumes attached",
+                MAX_VOLUMES
+            ));
+        }
+
         let uuid = request.uuid.unwrap_or_else(Uuid::new_v4);
         let xen_name = format!("krata-{uuid}");
-        let image_info = self.compile(request.image, &context.image_cache).await?;
+        let image_info = self
+            .compile(request.image, &context.image_cache, &request.credentials)
+            .await?;
 
         let mut gateway_mac = MacAddr6::random();
         gateway_mac.set_local(true);
@@ -61,12 +115,12 @@ impl GuestLauncher {
         container_mac.set_multicast(false);
 
         let _launch_permit = self.launch_semaphore.acquire().await?;
-        let guest_ipv4 = self.allocate_ipv4(context).await?;
-        let guest_ipv6 = container_mac.to_link_local_ipv6();
-        let gateway_ipv4 = "10.75.70.1";
-        let gateway_ipv6 = "fe80::1";
-        let ipv4_network_mask: u32 = 16;
-        let ipv6_network_mask: u32 = 10;
+        let guest_ipv4 = request.network.ipv4;
+        let guest_ipv6 = request.network.ipv6;
+        let gateway_ipv4 = request.network.gateway_ipv4;
+        let gateway_ipv6 = request.network.gateway_ipv6;
+        let ipv4_network_mask = request.network.ipv4_prefix;
+        let ipv6_network_mask = request.network.ipv6_prefix;
 
         let launch_config = LaunchInfo {
             hostname: Some(
@@ -86,16 +140,44 @@ impl GuestLauncher {
                     gateway: gateway_ipv6.to_string(),
                 },
                 resolver: LaunchNetworkResolver {
-                    nameservers: vec![
-                        "1.1.1.1".to_string(),
-                        "1.0.0.1".to_string(),
-                        "2606:4700:4700::1111".to_string(),
-                        "2606:4700:4700::1001".to_string(),
-                    ],
+                    nameservers: request.network.nameservers.clone(),
                 },
+                hosts: request
+                    .network
+                    .hosts
+                    .iter()
+                    .map(|host| LaunchNetworkHost {
+                        name: host.name.clone(),
+                        ipv4: host.ipv4.to_string(),
+                        ipv6: host.ipv6.to_string(),
+                    })
+                    .collect(),
             }),
             env: request.env,
             run: request.run,
+            volumes: request
+                .volumes
+                .iter()
+                .enumerate()
+                .map(|(index, volume)| LaunchVolume {
+                    device: format!("/dev/{}", GuestLauncher::volume_vdev(index)),
+                    path: volume.path.clone(),
+                    read_only: volume.read_only,
+                })
+                .collect(),
+            mounts: request
+                .mounts
+                .iter()
+                .enumerate()
+                .map(|(index, mount)| LaunchMount {
+                    tag: GuestLauncher::mount_tag(index),
+                    path: mount.guest_path.clone(),
+                    read_only: mount.read_only,
+                })
+                .collect(),
+            tty: request.tty,
+            healthcheck: request.healthcheck.clone(),
+            resources: request.resources.clone(),
         };
 
         let cfgblk = ConfigBlock::new(&uuid, &image_info)?;
@@ -118,6 +200,38 @@ impl GuestLauncher {
         let image_squashfs_loop = context.autoloop.loopify(image_squashfs_path)?;
         let cfgblk_squashfs_loop = context.autoloop.loopify(cfgblk_squashfs_path)?;
 
+        let mut volume_loops: Vec<BlockDeviceRef> = Vec::new();
+        for volume in &request.volumes {
+            let result = if volume.read_only {
+                context.autoloop.loopify(&volume.image)
+            } else {
+                context.autoloop.loopify_writable(&volume.image)
+            };
+            match result {
+                Ok(device) => volume_loops.push(device),
+                Err(error) => {
+                    let _ = context.autoloop.unloop(&image_squashfs_loop.path).await;
+                    let _ = context.autoloop.unloop(&cfgblk_squashfs_loop.path).await;
+                    for device in &volume_loops {
+                        let _ = context.autoloop.unloop(&device.path).await;
+                    }
+                    let _ = fs::remove_dir(&cfgblk.dir);
+                    return Err(error);
+                }
+            }
+        }
+
+        let mut loops = vec![
+            format!("{}:{}:none", &image_squashfs_loop.path, image_squashfs_path),
+            format!(
+                "{}:{}:{}",
+                &cfgblk_squashfs_loop.path, cfgblk_squashfs_path, cfgblk_dir_path
+            ),
+        ];
+        for (volume, device) in request.volumes.iter().zip(volume_loops.iter()) {
+            loops.push(format!("{}:{}:none", &device.path, volume.image));
+        }
+
         let cmdline_options = [
             if request.debug { "debug" } else { "quiet" },
             "elevator=noop",
@@ -129,18 +243,9 @@ impl GuestLauncher {
 
         let mut extra_keys = vec![
             ("krata/uuid".to_string(), uuid.to_string()),
-            (
-                "krata/loops".to_string(),
-                format!(
-                    "{}:{}:none,{}:{}:{}",
-                    &image_squashfs_loop.path,
-                    image_squashfs_path,
-                    &cfgblk_squashfs_loop.path,
-                    cfgblk_squashfs_path,
-                    cfgblk_dir_path,
-                ),
-            ),
+            ("krata/loops".to_string(), loops.join(",")),
             ("krata/image".to_string(), request.image.to_string()),
+            ("krata/image-digest".to_string(), image_info.digest.clone()),
             (
                 "krata/network/guest/ipv4".to_string(),
                 format!("{}/{}", guest_ipv4, ipv4_network_mask),
@@ -171,27 +276,59 @@ impl GuestLauncher {
             extra_keys.push(("krata/name".to_string(), name.to_string()));
         }
 
+        let volume_vdevs = (0..volume_loops.len())
+            .map(GuestLauncher::volume_vdev)
+            .collect::<Vec<_>>();
+        let mut disks = vec![
+            DomainDisk {
+                vdev: "xvda",
+                block: &image_squashfs_loop,
+                writable: false,
+            },
+            DomainDisk {
+                vdev: "xvdb",
+                block: &cfgblk_squashfs_loop,
+                writable: false,
+            },
+        ];
+        for ((vdev, device), volume) in volume_vdevs
+            .iter()
+            .zip(volume_loops.iter())
+            .zip(request.volumes.iter())
+        {
+            disks.push(DomainDisk {
+                vdev,
+                block: device,
+                writable: !volume.read_only,
+            });
+        }
+
+        let mount_tags = (0..request.mounts.len())
+            .map(GuestLauncher::mount_tag)
+            .collect::<Vec<_>>();
+        let filesystems = request
+            .mounts
+            .iter()
+            .zip(mount_tags.iter())
+            .map(|(mount, tag)| DomainFilesystem {
+                path: &mount.host_path,
+                tag,
+                read_only: mount.read_only,
+            })
+            .collect::<Vec<_>>();
+
         let config = DomainConfig {
             backend_domid: 0,
             name: &xen_name,
-            max_vcpus: request.vcpus,
-            mem_mb: request.mem,
+            max_vcpus: request.max_vcpus.max(request.vcpus),
+            mem_mb: request.max_mem.max(request.mem),
+            target_vcpus: request.vcpus,
+            target_mem_mb: request.mem,
             kernel_path: &context.kernel,
             initrd_path: &context.initrd,
             cmdline: &cmdline,
             use_console_backend: Some("krata-console"),
-            disks: vec![
-                DomainDisk {
-                    vdev: "xvda",
-                    block: &image_squashfs_loop,
-                    writable: false,
-                },
-                DomainDisk {
-                    vdev: "xvdb",
-                    block: &cfgblk_squashfs_loop,
-                    writable: false,
-                },
-            ],
+            disks,
             channels: vec![DomainChannel {
                 typ: "krata-channel".to_string(),
                 initialized: false,
@@ -202,7 +339,7 @@ impl GuestLauncher {
                 bridge: None,
                 script: None,
             }],
-            filesystems: vec![],
+            filesystems,
             event_channels: vec![],
             extra_keys,
             extra_rw_paths: vec!["krata/guest".to_string()],
@@ -213,73 +350,49 @@ impl GuestLauncher {
                 uuid,
                 domid: created.domid,
                 image: request.image.to_string(),
+                image_digest: Some(image_info.digest.clone()),
                 loops: vec![],
-                guest_ipv4: Some(IpNetwork::new(
-                    IpAddr::V4(guest_ipv4),
-                    ipv4_network_mask as u8,
-                )?),
-                guest_ipv6: Some(IpNetwork::new(
-                    IpAddr::V6(guest_ipv6),
-                    ipv6_network_mask as u8,
-                )?),
+                guest_ipv4: Some(IpNetwork::new(IpAddr::V4(guest_ipv4), ipv4_network_mask)?),
+                guest_ipv6: Some(IpNetwork::new(IpAddr::V6(guest_ipv6), ipv6_network_mask)?),
                 guest_mac: Some(guest_mac_string.clone()),
-                gateway_ipv4: Some(IpNetwork::new(
-                    IpAddr::V4(Ipv4Addr::from_str(gateway_ipv4)?),
-                    ipv4_network_mask as u8,
-                )?),
-                gateway_ipv6: Some(IpNetwork::new(
-                    IpAddr::V6(Ipv6Addr::from_str(gateway_ipv6)?),
-                    ipv6_network_mask as u8,
-                )?),
+                gateway_ipv4: Some(IpNetwork::new(IpAddr::V4(gateway_ipv4), ipv4_network_mask)?),
+                gateway_ipv6: Some(IpNetwork::new(IpAddr::V6(gateway_ipv6), ipv6_network_mask)?),
                 gateway_mac: Some(gateway_mac_string.clone()),
                 state: GuestState { exit_code: None },
             }),
             Err(error) => {
                 let _ = context.autoloop.unloop(&image_squashfs_loop.path).await;
                 let _ = context.autoloop.unloop(&cfgblk_squashfs_loop.path).await;
+                for device in &volume_loops {
+                    let _ = context.autoloop.unloop(&device.path).await;
+                }
                 let _ = fs::remove_dir(&cfgblk.dir);
                 Err(error.into())
             }
         }
     }
 
-    async fn compile(&self, image: &str, image_cache: &ImageCache) -> Result<ImageInfo> {
-        let image = ImageName::parse(image)?;
-        let compiler = ImageCompiler::new(image_cache, None)?;
-        compiler.compile(&image).await
+    fn volume_vdev(index: usize) -> String {
+        format!("xvd{}", (b'c' + index as u8) as char)
     }
 
-    async fn allocate_ipv4(&self, context: &RuntimeContext) -> Result<Ipv4Addr> {
-        let network = Ipv4Network::new(Ipv4Addr::new(10, 75, 80, 0), 24)?;
-        let mut used: Vec<Ipv4Addr> = vec![];
-        for domid_candidate in context.xen.store.list("/local/domain").await? {
-            let dom_path = format!("/local/domain/{}", domid_candidate);
-            let ip_path = format!("{}/krata/network/guest/ipv4", dom_path);
-            let existing_ip = context.xen.store.read_string(&ip_path).await?;
-            if let Some(existing_ip) = existing_ip {
-                let ipv4_network = Ipv4Network::from_str(&existing_ip)?;
-                used.push(ipv4_network.ip());
-            }
-        }
-
-        let mut found: Option<Ipv4Addr> = None;
-        for ip in network.iter() {
-            let last = ip.octets()[3];
-            if last == 0 || last == 255 {
-                continue;
-            }
-            if !used.contains(&ip) {
-                found = Some(ip);
-                break;
-            }
-        }
-
-        if found.is_none() {
-            return Err(anyhow!(
-                "unable to find ipv4 to allocate to guest, ipv4 addresses are exhausted"
-            ));
-        }
+    fn mount_tag(index: usize) -> String {
+        format!("krata-mount-{}", index)
+    }
 
-        Ok(found.unwrap())
+    async fn compile(
+        &self,
+        image: &str,
+        image_cache: &ImageCache,
+        credentials: &OciCredentialStore,
+    ) -> Result<ImageInfo> {
+        let image = ImageName::parse(image)?;
+        let compiler = ImageCompiler::new(
+            image_cache,
+            None,
+            credentials.clone(),
+            OciProgressContext::create().0,
+        )?;
+        compiler.compile(&image).await
     }
 }
@@ -17,7 +17,13 @@ use self::{
     autoloop::AutoLoop,
     launch::{GuestLaunchRequest, GuestLauncher},
 };
-use krataoci::cache::ImageCache;
+use krataoci::{
+    auth::OciCredentialStore,
+    cache::ImageCache,
+    compiler::{ImageCompiler, ImageInfo},
+    name::ImageName,
+    progress::OciProgressContext,
+};
 
 pub mod autoloop;
 pub mod cfgblk;
@@ -34,11 +40,18 @@ pub struct GuestState {
     pub exit_code: Option<i32>,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeGuestFiles {
+    pub kernel: Option<PathBuf>,
+    pub initrd: Option<PathBuf>,
+}
+
 pub struct GuestInfo {
     pub name: Option<String>,
     pub uuid: Uuid,
     pub domid: u32,
     pub image: String,
+    pub image_digest: Option<String>,
     pub loops: Vec<GuestLoopInfo>,
     pub guest_ipv4: Option<IpNetwork>,
     pub guest_ipv6: Option<IpNetwork>,
@@ -59,7 +72,7 @@ pub struct RuntimeContext {
 }
 
 impl RuntimeContext {
-    pub async fn new(store: String) -> Result<Self> {
+    pub async fn new(store: String, files: &RuntimeGuestFiles) -> Result<Self> {
         let mut image_cache_path = PathBuf::from(&store);
         image_cache_path.push("cache");
         fs::create_dir_all(&image_cache_path)?;
@@ -68,8 +81,14 @@ impl RuntimeContext {
         image_cache_path.push("image");
         fs::create_dir_all(&image_cache_path)?;
         let image_cache = ImageCache::new(&image_cache_path)?;
-        let kernel = RuntimeContext::detect_guest_file(&store, "kernel")?;
-        let initrd = RuntimeContext::detect_guest_file(&store, "initrd")?;
+        let kernel = match files.kernel {
+            Some(ref path) => path_as_string(path)?,
+            None => RuntimeContext::detect_guest_file(&store, "kernel")?,
+        };
+        let initrd = match files.initrd {
+            Some(ref path) => path_as_string(path)?,
+            None => RuntimeContext::detect_guest_file(&store, "initrd")?,
+        };
 
         Ok(RuntimeContext {
             image_cache,
@@ -125,6 +144,11 @@ impl RuntimeContext {
                 .read_string(&format!("{}/krata/image", &dom_path))
                 .await?
                 .unwrap_or("unknown".to_string());
+            let image_digest = self
+                .xen
+                .store
+                .read_string(&format!("{}/krata/image-digest", &dom_path))
+                .await?;
             let loops = self
                 .xen
                 .store
@@ -204,6 +228,7 @@ impl RuntimeContext {
                 uuid,
                 domid,
                 image,
+                image_digest,
                 loops,
                 guest_ipv4,
                 guest_ipv6,
@@ -217,6 +242,18 @@ impl RuntimeContext {
         Ok(guests)
     }
 
+    pub async fn resolve_domid(&self, domid: u32) -> Result<Option<Uuid>> {
+        let uuid_string = self
+            .xen
+            .store
+            .read_string(&format!("/local/domain/{}/krata/uuid", domid))
+            .await?;
+        Ok(match uuid_string {
+            Some(uuid_string) => Some(Uuid::from_str(&uuid_string)?),
+            None => None,
+        })
+    }
+
     pub async fn resolve(&self, uuid: Uuid) -> Result<Option<GuestInfo>> {
         for guest in self.list().await? {
             if guest.uuid == uuid {
@@ -253,20 +290,55 @@ impl RuntimeContext {
 #[derive(Clone)]
 pub struct Runtime {
     store: Arc<String>,
+    files: RuntimeGuestFiles,
     context: RuntimeContext,
     launch_semaphore: Arc<Semaphore>,
 }
 
 impl Runtime {
-    pub async fn new(store: String) -> Result<Self> {
-        let context = RuntimeContext::new(store.clone()).await?;
+    pub async fn new(store: String, files: RuntimeGuestFiles) -> Result<Self> {
+        let context = RuntimeContext::new(store.clone(), &files).await?;
         Ok(Self {
             store: Arc::new(store),
+            files,
             context,
             launch_semaphore: Arc::new(Semaphore::new(1)),
         })
     }
 
+    pub fn image_cache(&self) -> &ImageCache {
+        &self.context.image_cache
+    }
+
+    /// Pulls and compiles an image into the image cache without launching a guest.
+    pub async fn pull_image(
+        &self,
+        image: &str,
+        credentials: OciCredentialStore,
+        progress: OciProgressContext,
+    ) -> Result<ImageInfo> {
+        let image = ImageName::parse(image)?;
+        let compiler = ImageCompiler::new(&self.context.image_cache, None, credentials, progress)?;
+        compiler.compile(&image).await
+    }
+
+    /// Imports the images in an OCI layout or `docker save` archive into the image cache.
+    pub async fn import_image(
+        &self,
+        archive: &Path,
+        tag: Option<&str>,
+        progress: OciProgressContext,
+    ) -> Result<Vec<ImageInfo>> {
+        let tag = tag.map(ImageName::parse).transpose()?;
+        let compiler = ImageCompiler::new(
+            &self.context.image_cache,
+            Some(archive.to_path_buf()),
+            OciCredentialStore::new(),
+            progress,
+        )?;
+        compiler.import(tag.as_ref()).await
+    }
+
     pub async fn launch<'a>(&self, request: GuestLaunchRequest<'a>) -> Result<GuestInfo> {
         let mut launcher = GuestLauncher::new(self.launch_semaphore.clone())?;
         launcher.launch(&self.context, request).await
@@ -319,12 +391,102 @@ impl Runtime {
         Ok(uuid)
     }
 
+    pub async fn pause(&self, uuid: Uuid) -> Result<()> {
+        let info = self
+            .context
+            .resolve(uuid)
+            .await?
+            .ok_or_else(|| anyhow!("unable to resolve guest: {}", uuid))?;
+        self.context.xen.pause(info.domid).await?;
+        Ok(())
+    }
+
+    pub async fn resume(&self, uuid: Uuid) -> Result<()> {
+        let info = self
+            .context
+            .resolve(uuid)
+            .await?
+            .ok_or_else(|| anyhow!("unable to resolve guest: {}", uuid))?;
+        self.context.xen.unpause(info.domid).await?;
+        Ok(())
+    }
+
+    pub async fn update_resources(
+        &self,
+        uuid: Uuid,
+        vcpus: Option<u32>,
+        mem: Option<u64>,
+    ) -> Result<()> {
+        let info = self
+            .context
+            .resolve(uuid)
+            .await?
+            .ok_or_else(|| anyhow!("unable to resolve guest: {}", uuid))?;
+        let store = &self.context.xen.store;
+        let dom_path = store.get_domain_path(info.domid).await?;
+
+        if let Some(mem) = mem {
+            let static_max = store
+                .read_string(format!("{}/memory/static-max", dom_path).as_str())
+                .await?
+                .and_then(|x| x.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("unable to read maximum memory of guest {}", uuid))?;
+            let target = mem * 1024;
+            if target == 0 || target > static_max {
+                return Err(anyhow!(
+                    "memory of {} MB is outside of the range 1-{} MB for guest {}",
+                    mem,
+                    static_max / 1024,
+                    uuid
+                ));
+            }
+            let current = store
+                .read_string(format!("{}/memory/target", dom_path).as_str())
+                .await?
+                .and_then(|x| x.parse::<u64>().ok())
+                .unwrap_or_default();
+            if target > current {
+                // the reservation may have been lowered since boot, growing needs it raised
+                self.context.xen.set_max_mem(info.domid, static_max).await?;
+            }
+            self.context
+                .xen
+                .set_memory_target(info.domid, target)
+                .await?;
+        }
+
+        if let Some(vcpus) = vcpus {
+            let max_vcpus = store
+                .list(format!("{}/cpu", dom_path).as_str())
+                .await?
+                .len() as u32;
+            if vcpus == 0 || vcpus > max_vcpus {
+                return Err(anyhow!(
+                    "{} vcpus is outside of the range 1-{} for guest {}",
+                    vcpus,
+                    max_vcpus,
+                    uuid
+                ));
+            }
+            self.context.xen.set_vcpus_online(info.domid, vcpus).await?;
+        }
+        Ok(())
+    }
+
     pub async fn list(&self) -> Result<Vec<GuestInfo>> {
         self.context.list().await
     }
 
+    pub async fn resolve_domid(&self, domid: u32) -> Result<Option<Uuid>> {
+        self.context.resolve_domid(domid).await
+    }
+
+    pub async fn resolve(&self, uuid: Uuid) -> Result<Option<GuestInfo>> {
+        self.context.resolve(uuid).await
+    }
+
     pub async fn dupe(&self) -> Result<Runtime> {
-        Runtime::new((*self.store).clone()).await
+        Runtime::new((*self.store).clone(), self.files.clone()).await
     }
 }
 
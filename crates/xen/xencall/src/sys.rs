@@ -105,6 +105,8 @@ pub const XEN_DOMCTL_CDF_S3_INTEGRITY: u32 = 1u32 << 2;
 pub const XEN_DOMCTL_CDF_OOS_OFF: u32 = 1u32 << 3;
 pub const XEN_DOMCTL_CDF_XS_DOMAIN: u32 = 1u32 << 4;
 
+pub const XEN_DOMINF_PAUSED: u32 = 1u32 << 3;
+
 pub const XEN_X86_EMU_LAPIC: u32 = 1 << 0;
 pub const XEN_X86_EMU_HPET: u32 = 1 << 1;
 pub const XEN_X86_EMU_PM: u32 = 1 << 2;
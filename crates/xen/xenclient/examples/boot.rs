@@ -19,6 +19,8 @@ async fn main() -> Result<()> {
         name: "xenclient-test",
         max_vcpus: 1,
         mem_mb: 512,
+        target_vcpus: 1,
+        target_mem_mb: 512,
         kernel_path: kernel_image_path.as_str(),
         initrd_path: initrd_path.as_str(),
         cmdline: "debug elevator=noop",
@@ -28,7 +28,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 use uuid::Uuid;
-use xencall::sys::{CreateDomain, XEN_DOMCTL_CDF_HAP, XEN_DOMCTL_CDF_HVM_GUEST};
+use xencall::sys::{CreateDomain, XEN_DOMCTL_CDF_HAP, XEN_DOMCTL_CDF_HVM_GUEST, XEN_DOMINF_PAUSED};
 use xencall::XenCall;
 use xenstore::{
     XsPermission, XsdClient, XsdInterface, XS_PERM_NONE, XS_PERM_READ, XS_PERM_READ_WRITE,
@@ -58,6 +58,7 @@ pub struct DomainDisk<'a> {
 pub struct DomainFilesystem<'a> {
     pub path: &'a str,
     pub tag: &'a str,
+    pub read_only: bool,
 }
 
 #[derive(Debug)]
@@ -85,6 +86,9 @@ pub struct DomainConfig<'a> {
     pub name: &'a str,
     pub max_vcpus: u32,
     pub mem_mb: u64,
+    /// vCPUs online and memory populated at boot, the rest is headroom for resizing.
+    pub target_vcpus: u32,
+    pub target_mem_mb: u64,
     pub kernel_path: &'a str,
     pub initrd_path: &'a str,
     pub cmdline: &'a str,
@@ -277,7 +281,7 @@ impl XenClient {
                     &image_loader,
                     initrd.as_slice(),
                     config.max_vcpus,
-                    config.mem_mb,
+                    config.target_mem_mb,
                     1,
                 )
                 .await?;
@@ -314,7 +318,7 @@ impl XenClient {
             .await?;
             tx.write_string(
                 format!("{}/memory/target", dom_path).as_str(),
-                &(config.mem_mb * 1024).to_string(),
+                &(config.target_mem_mb * 1024).to_string(),
             )
             .await?;
             tx.write_string(format!("{}/memory/videoram", dom_path).as_str(), "0")
@@ -336,7 +340,12 @@ impl XenClient {
                 tx.mkdir(&path).await?;
                 tx.set_perms(&path, ro_perm).await?;
                 let path = format!("{}/cpu/{}/availability", dom_path, i);
-                tx.write_string(&path, "online").await?;
+                let availability = if i < config.target_vcpus {
+                    "online"
+                } else {
+                    "offline"
+                };
+                tx.write_string(&path, availability).await?;
                 tx.set_perms(&path, ro_perm).await?;
             }
             tx.commit().await?;
@@ -576,6 +585,10 @@ impl XenClient {
             ("state", "1".to_string()),
             ("path", filesystem.path.to_string()),
             ("security-model", "none".to_string()),
+            (
+                "readonly",
+                if filesystem.read_only { "1" } else { "0" }.to_string(),
+            ),
         ];
 
         let frontend_items: Vec<(&str, String)> = vec![
@@ -729,6 +742,58 @@ impl XenClient {
         Ok(())
     }
 
+    pub async fn set_max_mem(&self, domid: u32, max_kb: u64) -> Result<()> {
+        self.call.set_max_mem(domid, max_kb).await?;
+        Ok(())
+    }
+
+    pub async fn set_memory_target(&self, domid: u32, target_kb: u64) -> Result<()> {
+        let dom_path = self.store.get_domain_path(domid).await?;
+        self.store
+            .write_string(
+                format!("{}/memory/target", dom_path).as_str(),
+                &target_kb.to_string(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_vcpus_online(&self, domid: u32, vcpus: u32) -> Result<()> {
+        let dom_path = self.store.get_domain_path(domid).await?;
+        for cpu in self.store.list(format!("{}/cpu", dom_path)).await? {
+            let Ok(index) = cpu.parse::<u32>() else {
+                continue;
+            };
+            let availability = if index < vcpus { "online" } else { "offline" };
+            self.store
+                .write_string(
+                    format!("{}/cpu/{}/availability", dom_path, index).as_str(),
+                    availability,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn is_paused(&self, domid: u32) -> Result<bool> {
+        let info = self.call.get_domain_info(domid).await?;
+        Ok(info.flags & XEN_DOMINF_PAUSED != 0)
+    }
+
+    pub async fn pause(&self, domid: u32) -> Result<()> {
+        if !self.is_paused(domid).await? {
+            self.call.pause_domain(domid).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn unpause(&self, domid: u32) -> Result<()> {
+        if self.is_paused(domid).await? {
+            self.call.unpause_domain(domid).await?;
+        }
+        Ok(())
+    }
+
     async fn destroy_store(&self, domid: u32) -> Result<()> {
         let dom_path = self.store.get_domain_path(domid).await?;
         let vm_path = self.store.read_string(&format!("{}/vm", dom_path)).await?;